@@ -0,0 +1,182 @@
+//! Actions performed on host computer by daemon when keyboard sends
+//! trigger chord. They complement what keyboard firmware can do itself.
+
+use std::io::{BufRead as _, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use chrono::format::{Item, StrftimeItems};
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::keyboard::{KeyboardPart, Macro};
+use crate::output;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostAction {
+    /// Types current local date and time using strftime-like format, e.g. `%Y-%m-%d`.
+    TypeDatetime(String),
+    /// Puts text on clipboard and sends paste chord. Unlike macros, text
+    /// length isn't limited by keyboard memory.
+    PasteText(String),
+    /// Runs shell command without waiting for it to finish. It is killed
+    /// after `timeout` milliseconds, if given, or after sandbox timeout.
+    Run { command: String, timeout: Option<u64> },
+    /// Knob rotation action depending on how fast knob is turned.
+    BySpeed { slow: Box<HostAction>, fast: Box<HostAction> },
+    /// Presses keyboard macro with delays firmware doesn't support: `delay`
+    /// is waited between all key presses, delay parts where they are.
+    DelayedMacro { delay: u16, parts: Vec<KeyboardPart> },
+}
+
+impl std::fmt::Display for HostAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostAction::TypeDatetime(format) => write!(f, "type_datetime '{format}'"),
+            HostAction::PasteText(text) => write!(f, "paste_text of {} characters", text.chars().count()),
+            HostAction::Run { command, .. } => write!(f, "run '{command}'"),
+            HostAction::BySpeed { slow, fast } => write!(f, "{slow}, when turned fast: {fast}"),
+            HostAction::DelayedMacro { delay: 0, parts } => write!(f, "type '{}'", Macro::Keyboard(parts.clone())),
+            HostAction::DelayedMacro { delay, parts } =>
+                write!(f, "type '{}' with {delay}ms between key presses", Macro::Keyboard(parts.clone())),
+        }
+    }
+}
+
+impl HostAction {
+    /// Checks action parameters without performing it.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            HostAction::TypeDatetime(format) => {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    bail!("invalid date/time format '{format}'");
+                }
+            }
+            HostAction::PasteText(text) => ensure!(!text.is_empty(), "text to paste is empty"),
+            HostAction::Run { command, timeout } => {
+                ensure!(!command.trim().is_empty(), "command to run is empty");
+                ensure!(*timeout != Some(0), "timeout of command must be positive");
+            }
+            HostAction::BySpeed { slow, fast } => {
+                slow.validate()?;
+                fast.validate()?;
+            }
+            HostAction::DelayedMacro { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Performs action, shell commands are started through sandbox.
+    pub fn run(&self, sandbox: &Sandbox) -> Result<()> {
+        match self {
+            HostAction::TypeDatetime(format) => {
+                let text = chrono::Local::now().format(format).to_string();
+                output::type_text(&text)
+            }
+            HostAction::PasteText(text) => {
+                output::set_clipboard(text)?;
+                output::paste()
+            }
+            HostAction::Run { command, timeout } => {
+                let mut child = sandbox.command(command)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn().with_context(|| format!("run '{command}'"))?;
+                log_output(command, child.stdout.take(), false);
+                log_output(command, child.stderr.take(), true);
+                // Wait in background, so long-running commands don't block next actions.
+                let command = command.clone();
+                let timeout = timeout.or(sandbox.timeout).map(Duration::from_millis);
+                std::thread::spawn(move || match wait(&mut child, timeout) {
+                    Ok(Some(status)) if !status.success() => warn!("'{command}' failed: {status}"),
+                    Ok(Some(_)) => {}
+                    Ok(None) => warn!("'{command}' is killed after running for {timeout:?}"),
+                    Err(err) => warn!("'{command}' failed: {err}"),
+                });
+                Ok(())
+            }
+            // Speed is known to daemon only, it runs one of actions itself.
+            HostAction::BySpeed { slow, .. } => slow.run(sandbox),
+            HostAction::DelayedMacro { delay, parts } => {
+                let mut pressed = false;
+                for part in parts {
+                    if let KeyboardPart::Delay(ms) = part {
+                        std::thread::sleep(Duration::from_millis(*ms as u64));
+                        continue;
+                    }
+                    if pressed {
+                        std::thread::sleep(Duration::from_millis(*delay as u64));
+                    }
+                    pressed = true;
+                    match part {
+                        KeyboardPart::Key(accord) => output::press_chord(accord)?,
+                        KeyboardPart::Char(modifiers, c) => {
+                            // Config translates modified characters to chords.
+                            ensure!(modifiers.is_empty(), "character '{c}' with modifiers can't be typed as text");
+                            output::type_text(&c.to_string())?
+                        }
+                        KeyboardPart::Delay(_) => unreachable!("delays are waited above"),
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How commands of `run` actions are started, so misbehaving one can't
+/// wedge daemon. Set in `sandbox` section of config.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sandbox {
+    /// Command shell is run with, like `[systemd-run, --user, --scope]`
+    /// or `[firejail, --quiet]`.
+    #[serde(default)]
+    pub wrapper: Vec<String>,
+    /// Commands are killed after running for this many milliseconds,
+    /// unless action sets its own timeout.
+    pub timeout: Option<u64>,
+}
+
+impl Sandbox {
+    fn command(&self, command: &str) -> Command {
+        let shell = if cfg!(windows) { ["cmd", "/C", command] } else { ["sh", "-c", command] };
+        let mut args = self.wrapper.iter().map(String::as_str).chain(shell);
+        let mut command = Command::new(args.next().expect("shell is given"));
+        command.args(args);
+        command
+    }
+}
+
+/// Logs lines command prints in background, errors as warnings.
+fn log_output(command: &str, output: Option<impl Read + Send + 'static>, is_error: bool) {
+    let Some(output) = output else { return };
+    let command = command.to_owned();
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            if is_error {
+                warn!("'{command}': {line}");
+            } else {
+                info!("'{command}': {line}");
+            }
+        }
+    });
+}
+
+/// Waits for child to exit, `None` if it is killed once timeout passes.
+fn wait(child: &mut Child, timeout: Option<Duration>) -> std::io::Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else { return child.wait().map(Some) };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
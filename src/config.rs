@@ -1,22 +1,116 @@
-use anyhow::{bail, ensure, Result};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
 use serde::Deserialize;
+use serde_with::DeserializeFromStr;
+use strum_macros::EnumString;
 
-use crate::keyboard::{Macro, KeyboardPart, MouseAction, MouseEvent};
+use crate::action::{HostAction, Sandbox};
+use crate::keyboard::{Accord, Key, Macro, KeyboardPart, KnobAction, Modifier, MouseAction, MouseEvent, MouseModifier, movement_bytes, Quirk, WellKnownCode};
+use crate::layout::Layout;
+use crate::model::{self, Model};
+use crate::parse;
+use crate::protocol::Protocol;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct Config {
     pub orientation: Orientation,
+
+    /// Known keyboard model, provides geometry and key names.
+    #[serde(default, deserialize_with = "model::deserialize")]
+    pub model: Option<&'static Model>,
+
+    // Geometry may be omitted if model is given.
+    #[serde(default)]
     pub rows: u8,
+    #[serde(default)]
     pub columns: u8,
+    #[serde(default)]
     pub knobs: u8,
 
     pub layers: Vec<Layer>,
+
+    /// Bindings addressed by key name, may target several layers at once.
+    #[serde(default)]
+    pub bindings: Vec<KeyBinding>,
+
+    /// Settings applied to all bindings.
+    #[serde(default)]
+    pub defaults: Defaults,
+
+    /// Keyboard layout used to translate characters in macros to key presses.
+    #[serde(default)]
+    pub layout: Layout,
+
+    /// How daemon starts commands of `run` actions.
+    #[serde(default)]
+    pub sandbox: Sandbox,
+
+    /// Settings of keyboard itself rather than of its keys.
+    #[serde(flatten)]
+    pub device: DeviceSettings,
 }
 
 impl Config {
+    /// Loads config split into several YAML files (`*.yaml`, `*.yml`) in
+    /// directory. Files are merged in order of their names: `layers` and
+    /// `bindings` are concatenated, other settings may be given in any file,
+    /// but not differently in several ones.
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(dir).with_context(|| format!("read directory {}", dir.display()))? {
+            let path = entry?.path();
+            let is_yaml = path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml");
+            if is_yaml && path.is_file() {
+                paths.push(path);
+            }
+        }
+        ensure!(!paths.is_empty(), "no YAML files found in {}", dir.display());
+        paths.sort();
+
+        let mut documents = vec![];
+        for path in paths {
+            let contents = std::fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+            // YAML parser rejects documents without any content.
+            let is_empty = contents.lines().all(|line| line.trim().is_empty() || line.trim_start().starts_with('#'));
+            let document = if is_empty {
+                serde_yaml::Value::Null
+            } else {
+                serde_yaml::from_str(&contents).with_context(|| format!("parse {}", path.display()))?
+            };
+            documents.push((path.display().to_string(), document));
+        }
+        Ok(serde_yaml::from_value(merge_documents(documents)?)?)
+    }
+
     /// Validates config and renders it to flat list of macros for buttons
     /// and knobs taking orientation into account.
-    pub fn render(self) -> Result<Vec<FlatLayer>> {
+    pub fn render(mut self) -> Result<Vec<FlatLayer>> {
+        self.apply_model()?;
+        for layer in &mut self.layers {
+            for macro_ in layer.buttons.iter_mut().flatten().flatten() {
+                self.defaults.apply(macro_);
+            }
+            for knob in &mut layer.knobs {
+                for macro_ in [&mut knob.ccw, &mut knob.press, &mut knob.cw].into_iter().flatten() {
+                    self.defaults.apply(macro_);
+                }
+            }
+        }
+        self.expand_bindings()?;
+
+        for layer in &mut self.layers {
+            let knob_macros = layer.knobs.iter_mut()
+                .flat_map(|knob| [&mut knob.ccw, &mut knob.press, &mut knob.cw]);
+            for macro_ in layer.buttons.iter_mut().flatten().chain(knob_macros).flatten() {
+                self.layout.translate(macro_)?;
+            }
+        }
+
         // 3x1 keys + 1 knob keyboard has some limitations we need to check.
         let is_limited = (self.rows == 1 || self.columns == 1) && self.knobs == 1;
 
@@ -49,11 +143,9 @@ impl Config {
             for (r_idx, button_macro) in buttons.iter().enumerate() {
                 if let Some(m) = button_macro {
                     // Validate mouse moves as well as keyboard parts
-                    if let Macro::Mouse(MouseEvent(action, _)) = m {
-                        if let MouseAction::Move { dx, dy } = action {
-                            if *dx < -128 || *dx > 127 || *dy < -128 || *dy > 127 {
-                                bail!("Invalid mapping: mouse move dx/dy ({},{}) exceeds supported range -128..127 in macro '{}' in layer {}, button index {}.", dx, dy, m, i, r_idx);
-                            }
+                    if let Macro::Mouse(MouseEvent(MouseAction::Move { dx, dy } | MouseAction::Drag { dx, dy, .. }, _)) = m {
+                        if let Err(err) = movement_bytes(*dx, *dy) {
+                            bail!("Invalid mapping: {err}, in macro '{m}' in layer {i}, button index {r_idx}.");
                         }
                     }
 
@@ -61,7 +153,7 @@ impl Config {
                         // count delays
                         let delay_count = parts.iter().filter(|p| matches!(p, KeyboardPart::Delay(_))).count();
                         if delay_count > 1 {
-                            bail!("Invalid mapping: more than one delay found in macro '{}' in layer {}, button index {}. Only a single leading delay is allowed.", m, i, r_idx);
+                            bail!("Invalid mapping: more than one delay found in macro '{}' in layer {}, button index {}. Only a single leading delay is allowed, use `upload --emulate-unsupported` for more.", m, i, r_idx);
                         }
                         if delay_count == 1 {
                             // ensure it is the first element
@@ -72,7 +164,7 @@ impl Config {
                                     }
                                 }
                                 _ => {
-                                    bail!("Invalid mapping: delay must be the first item in macro '{}' in layer {}, button index {}.", m, i, r_idx);
+                                    bail!("Invalid mapping: delay must be the first item in macro '{}' in layer {}, button index {}, use `upload --emulate-unsupported` for delays elsewhere.", m, i, r_idx);
                                 }
                             }
                         }
@@ -85,18 +177,16 @@ impl Config {
                 let check = |opt_macro: &Option<Macro>| -> Result<()> {
                     if let Some(m) = opt_macro {
                         // Validate mouse move values on knobs too
-                        if let Macro::Mouse(MouseEvent(action, _)) = m {
-                            if let MouseAction::Move { dx, dy } = action {
-                                if *dx < -128 || *dx > 127 || *dy < -128 || *dy > 127 {
-                                    bail!("Invalid mapping: mouse move dx/dy ({},{}) exceeds supported range -128..127 in knob macro '{}' in layer {}, knob index {}.", dx, dy, m, i, k_idx);
-                                }
+                        if let Macro::Mouse(MouseEvent(MouseAction::Move { dx, dy } | MouseAction::Drag { dx, dy, .. }, _)) = m {
+                            if let Err(err) = movement_bytes(*dx, *dy) {
+                                bail!("Invalid mapping: {err}, in knob macro '{m}' in layer {i}, knob index {k_idx}.");
                             }
                         }
 
                         if let Macro::Keyboard(parts) = m {
                             let delay_count = parts.iter().filter(|p| matches!(p, KeyboardPart::Delay(_))).count();
                             if delay_count > 1 {
-                                bail!("Invalid mapping: more than one delay found in knob macro '{}' in layer {}, knob index {}. Only a single leading delay is allowed.", m, i, k_idx);
+                                bail!("Invalid mapping: more than one delay found in knob macro '{}' in layer {}, knob index {}. Only a single leading delay is allowed, use `upload --emulate-unsupported` for more.", m, i, k_idx);
                             }
                             if delay_count == 1 {
                                 match parts.first() {
@@ -106,7 +196,7 @@ impl Config {
                                         }
                                     }
                                     _ => {
-                                        bail!("Invalid mapping: delay must be the first item in knob macro '{}' in layer {}, knob index {}.", m, i, k_idx);
+                                        bail!("Invalid mapping: delay must be the first item in knob macro '{}' in layer {}, knob index {}, use `upload --emulate-unsupported` for delays elsewhere.", m, i, k_idx);
                                     }
                                 }
                             }
@@ -123,11 +213,278 @@ impl Config {
             Ok(FlatLayer { buttons, knobs })
         }).collect()
     }
+
+    /// Takes geometry from declared model and resolves key names in
+    /// `bindings` to button numbers and knob actions.
+    pub fn apply_model(&mut self) -> Result<()> {
+        if let Some(model) = self.model {
+            for (field, value, expected) in [("rows", &mut self.rows, model.rows),
+                                             ("columns", &mut self.columns, model.columns),
+                                             ("knobs", &mut self.knobs, model.knobs)] {
+                if *value == 0 {
+                    *value = expected;
+                }
+                ensure!(*value == expected, "`{field}` is {value}, but model {} has {expected}", model.name);
+            }
+        }
+        ensure!(self.rows > 0 && self.columns > 0, "`rows` and `columns` must be given unless `model` is set");
+
+        // Model names keys in normal orientation, while key numbers are
+        // counted as buttons are written in layer grid.
+        let (orows, ocols) = if self.orientation.is_horizontal() {
+            (self.rows as usize, self.columns as usize)
+        } else {
+            (self.columns as usize, self.rows as usize)
+        };
+        let written_buttons = reorient_grid(self.orientation, self.rows as usize, self.columns as usize,
+            (0..orows).map(|r| (0..ocols).map(|c| (r * ocols + c) as u8).collect()).collect());
+        let written_knobs = reorient_row(self.orientation, (0..self.knobs).collect());
+
+        for binding in &mut self.bindings {
+            if let KeyRef::Legend(n) = binding.key {
+                ensure!((n as usize) < written_buttons.len(),
+                        "Invalid mapping: key {} doesn't exist, keyboard has {} buttons", binding.key, written_buttons.len());
+                binding.key = KeyRef::Button(written_buttons[n as usize]);
+                continue;
+            }
+            if let KeyRef::Cell(r, c) = binding.key {
+                ensure!((r as usize) < orows && (c as usize) < ocols,
+                        "Invalid mapping: key {} doesn't exist, layer grid has {orows} rows of {ocols} buttons", binding.key);
+                binding.key = KeyRef::Button((r as usize * ocols + c as usize) as u8);
+                continue;
+            }
+            let KeyRef::Named(name) = &binding.key else { continue };
+            let Some(model) = self.model else {
+                bail!("Invalid mapping: key name '{name}' can only be used when `model` is set");
+            };
+            binding.key = match model.key(name) {
+                Some(KeyRef::Button(n)) => KeyRef::Button(written_buttons[n as usize]),
+                Some(KeyRef::Knob(n, action)) => KeyRef::Knob(written_knobs[n as usize], action),
+                _ => bail!("Invalid mapping: model {} has no key '{name}'", model.name),
+            };
+        }
+        Ok(())
+    }
+
+    /// Drops bindings whose `when` condition doesn't match given host.
+    pub fn select_for_host(&mut self, host: &Host) {
+        self.bindings.retain(|binding| binding.when.as_ref().is_none_or(|when| when.matches(host)));
+    }
+
+    /// Moves entries of `bindings` into grids of targeted layers.
+    /// Layers which don't define `buttons` or `knobs` get empty ones.
+    fn expand_bindings(&mut self) -> Result<()> {
+        let (orows, ocols) = if self.orientation.is_horizontal() {
+            (self.rows as usize, self.columns as usize)
+        } else {
+            (self.columns as usize, self.rows as usize)
+        };
+
+        for layer in &mut self.layers {
+            if layer.buttons.is_empty() {
+                layer.buttons = vec![vec![None; ocols]; orows];
+            }
+            if layer.knobs.is_empty() {
+                layer.knobs.resize_with(self.knobs as usize, Knob::default);
+            }
+        }
+
+        let triggers = self.triggers()?;
+        // Wildcards fill keys left unbound, so they go last.
+        let (wildcards, bindings): (Vec<_>, Vec<_>) = std::mem::take(&mut self.bindings).into_iter().zip(triggers)
+            .partition(|(binding, _)| binding.key == KeyRef::All);
+        for (binding, trigger) in bindings.into_iter().chain(wildcards) {
+            let targets = match &binding.layers {
+                Some(layers) => layers.clone(),
+                None => (1..=self.layers.len() as u8).collect(),
+            };
+            // Host action bindings send trigger chord which is caught by daemon.
+            let mut macro_ = match (binding.bound_macro(), trigger) {
+                (Some(macro_), _) => macro_,
+                (None, Some(trigger)) => Macro::Keyboard(vec![KeyboardPart::Key(trigger)]),
+                (None, None) => unreachable!("binding without macro has action"),
+            };
+            if binding.defaults {
+                self.defaults.apply(&mut macro_);
+            }
+
+            for layer_num in targets {
+                ensure!(layer_num >= 1 && layer_num as usize <= self.layers.len(),
+                        "Invalid mapping: binding for key {} targets layer {}, but only {} layers are defined",
+                        binding.key, layer_num, self.layers.len());
+                let layer = &mut self.layers[layer_num as usize - 1];
+
+                if binding.key == KeyRef::All {
+                    let knob_slots = layer.knobs.iter_mut()
+                        .flat_map(|knob| [&mut knob.ccw, &mut knob.press, &mut knob.cw]);
+                    for slot in layer.buttons.iter_mut().flatten().chain(knob_slots).filter(|slot| slot.is_none()) {
+                        *slot = Some(macro_.clone());
+                    }
+                    continue;
+                }
+
+                let slot = match &binding.key {
+                    KeyRef::Button(n) => {
+                        let n = *n as usize;
+                        ensure!(n < orows * ocols,
+                                "Invalid mapping: key {} doesn't exist, keyboard has {} buttons",
+                                binding.key, orows * ocols);
+                        layer.buttons.get_mut(n / ocols).and_then(|row| row.get_mut(n % ocols))
+                    }
+                    KeyRef::Knob(n, action) => {
+                        ensure!((*n as usize) < layer.knobs.len(),
+                                "Invalid mapping: key {} doesn't exist, keyboard has {} knobs",
+                                binding.key, layer.knobs.len());
+                        let knob = &mut layer.knobs[*n as usize];
+                        Some(match action {
+                            KnobAction::RotateCCW => &mut knob.ccw,
+                            KnobAction::Press => &mut knob.press,
+                            KnobAction::RotateCW => &mut knob.cw,
+                        })
+                    }
+                    KeyRef::All => unreachable!("wildcards are expanded above"),
+                    KeyRef::Cell(..) | KeyRef::Legend(_) | KeyRef::Named(_) => unreachable!("key names are resolved by apply_model"),
+                };
+                // Grid of wrong size is reported later during validation.
+                let Some(slot) = slot else { continue };
+                ensure!(slot.is_none(),
+                        "Invalid mapping: key {} on layer {} is bound both in layer and in bindings",
+                        binding.key, layer_num);
+                *slot = Some(macro_.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns host actions together with chords keyboard sends to trigger them.
+    pub fn host_actions(&self) -> Result<Vec<(Accord, HostAction)>> {
+        Ok(self.triggers()?.into_iter().zip(&self.bindings)
+            .filter_map(|(trigger, binding)| Some((trigger?, binding.action()?)))
+            .collect())
+    }
+
+    /// Returns how soon host actions may fire again for triggers whose interval
+    /// differs from daemon default: ones given by `min_interval` and zero for
+    /// knob rotations, which send chord per step.
+    pub fn min_intervals(&mut self) -> Result<Vec<(Accord, Duration)>> {
+        self.apply_model()?;
+        Ok(self.triggers()?.into_iter().zip(&self.bindings)
+            .filter_map(|(trigger, binding)| {
+                let is_rotation = matches!(binding.key, KeyRef::Knob(_, KnobAction::RotateCCW | KnobAction::RotateCW));
+                let ms = binding.min_interval.or(is_rotation.then_some(0))?;
+                Some((trigger?, Duration::from_millis(ms)))
+            })
+            .collect())
+    }
+
+    /// Replaces macros using features protocol lacks by trigger chords and
+    /// returns host actions which daemon performs instead. Emulated are
+    /// delays protocol lacks entirely, delays not leading macro, since
+    /// firmware only knows single delay between all key presses, and
+    /// macros longer than protocol allows.
+    ///
+    /// Chords are assigned in order macros are written, so upload and daemon
+    /// agree on them as long as config doesn't change.
+    pub fn emulate_unsupported(&mut self, protocol: Protocol) -> Result<Vec<(Accord, HostAction)>> {
+        let used: Vec<Accord> = self.triggers()?.into_iter().flatten().collect();
+        let mut pool = trigger_pool().filter(|accord| !used.contains(accord));
+        let mut actions = vec![];
+        let layout = self.layout;
+        let mut emulate = |macro_: &mut Macro, defaults: Option<&Defaults>| -> Result<()> {
+            let Macro::Keyboard(parts) = &*macro_ else { return Ok(()) };
+            let is_delay = |part: &KeyboardPart| matches!(part, KeyboardPart::Delay(_));
+            let delays_fit = !parts.iter().any(is_delay)
+                || protocol.supports_delays() && !parts.iter().skip(1).any(is_delay);
+            // Characters may take several presses once translated, like dead
+            // keys do. Ones layout can't produce are left for host to type.
+            let mut translated = macro_.clone();
+            let presses_fit = layout.translate(&mut translated).is_ok()
+                && matches!(&translated, Macro::Keyboard(presses) if presses.len() <= protocol.max_presses());
+            if delays_fit && presses_fit {
+                return Ok(());
+            }
+            for part in parts.iter() {
+                if let KeyboardPart::Delay(ms) = part {
+                    ensure!(*ms <= 6000, "Invalid mapping: delay {ms}ms exceeds maximum supported 6000ms in macro '{macro_}'");
+                }
+            }
+            // Leading delay is waited between all key presses, like firmware does.
+            let (delay, rest) = match parts.split_first() {
+                Some((KeyboardPart::Delay(ms), rest)) => (*ms, rest),
+                _ => (0, &parts[..]),
+            };
+            let trigger = pool.next()
+                .ok_or_else(|| anyhow!("Invalid mapping: too many host actions and emulated delays"))?;
+            // Defaults are applied to layer macros on render, so daemon must
+            // expect trigger as it's sent and press rest as it'd be sent.
+            let mut rest = Macro::Keyboard(rest.to_vec());
+            let mut sent = Macro::Keyboard(vec![KeyboardPart::Key(trigger)]);
+            if let Some(defaults) = defaults {
+                defaults.apply(&mut rest);
+                defaults.apply(&mut sent);
+            }
+            layout.translate_chords(&mut rest)?;
+            let (Macro::Keyboard(rest), Macro::Keyboard(sent)) = (rest, sent) else { unreachable!("defaults keep macro kind") };
+            let KeyboardPart::Key(sent) = sent[0] else { unreachable!("defaults keep part kind") };
+            actions.push((sent, HostAction::DelayedMacro { delay, parts: rest }));
+            *macro_ = Macro::Keyboard(vec![KeyboardPart::Key(trigger)]);
+            Ok(())
+        };
+
+        for layer in &mut self.layers {
+            let knob_macros = layer.knobs.iter_mut()
+                .flat_map(|knob| [&mut knob.ccw, &mut knob.press, &mut knob.cw]);
+            for macro_ in layer.buttons.iter_mut().flatten().chain(knob_macros).flatten() {
+                emulate(macro_, Some(&self.defaults))?;
+            }
+        }
+        for binding in &mut self.bindings {
+            let Some(mut macro_) = binding.bound_macro() else { continue };
+            emulate(&mut macro_, binding.defaults.then_some(&self.defaults))?;
+            binding.macro_ = Some(macro_);
+            binding.delay_only = None;
+        }
+        Ok(actions)
+    }
+
+    /// Returns trigger chords for each entry of `bindings`, `None` for entries
+    /// which are plain macros. Chords not given explicitly are taken from pool.
+    fn triggers(&self) -> Result<Vec<Option<Accord>>> {
+        for binding in &self.bindings {
+            binding.validate()?;
+        }
+
+        let explicit: Vec<Accord> = self.bindings.iter().filter_map(|b| b.trigger).collect();
+        let mut pool = trigger_pool().filter(|accord| !explicit.contains(accord));
+
+        self.bindings.iter().map(|binding| {
+            if binding.action().is_none() {
+                return Ok(None);
+            }
+            match binding.trigger {
+                Some(trigger) => Ok(Some(trigger)),
+                None => pool.next()
+                    .map(Some)
+                    .ok_or_else(|| anyhow!("Invalid mapping: too many host actions, set `trigger` explicitly")),
+            }
+        }).collect()
+    }
+}
+
+/// Keyboard settings applied on upload along with bindings.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct DeviceSettings {
+    /// Protocol quirks to apply in addition to ones known for firmware revision.
+    #[serde(default)]
+    pub quirks: Vec<Quirk>,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq, EnumString)]
 #[serde(rename_all="lowercase")]
+#[strum(serialize_all="lowercase")]
 pub enum Orientation {
+    #[default]
     Normal,
     UpsideDown,
     Clockwise,
@@ -142,22 +499,434 @@ impl Orientation {
 
 #[derive(Debug, Deserialize)]
 pub struct Layer {
+    #[serde(default)]
     pub buttons: Vec<Vec<Option<Macro>>>,
+    #[serde(default)]
     pub knobs: Vec<Knob>,
 }
 
+/// Binding of single key given by name rather than by position in layer grid.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyBinding {
+    pub key: KeyRef,
+    /// One-based numbers of layers to bind key on, all layers if not given.
+    pub layers: Option<Vec<u8>>,
+    #[serde(rename = "macro")]
+    pub macro_: Option<Macro>,
+    /// Key doing nothing but waiting for given number of milliseconds,
+    /// used as timing pad in chained hardware macros.
+    pub delay_only: Option<u16>,
+    /// Whether settings from `defaults` section are applied to this binding.
+    #[serde(default = "default_true")]
+    pub defaults: bool,
+
+    /// Binding is only used on machines matching condition.
+    pub when: Option<Condition>,
+
+    /// Chord sent by keyboard to trigger host action, chosen automatically if not given.
+    pub trigger: Option<Accord>,
+    /// Host action: type current date and time using given format.
+    pub type_datetime: Option<String>,
+    /// Host action: paste given text through clipboard.
+    #[serde(alias = "paste_clipboard")]
+    pub paste_text: Option<String>,
+    /// Host action: run shell command.
+    pub run: Option<String>,
+    /// Milliseconds after which command of `run` is killed, overrides sandbox timeout.
+    pub timeout: Option<u64>,
+    /// Host action fires again only after this many milliseconds, overrides
+    /// daemon default. Knob rotations aren't limited unless it's given.
+    pub min_interval: Option<u64>,
+    /// Host action performed instead when knob is turned fast.
+    pub fast: Option<FastAction>,
+}
+
+impl KeyBinding {
+    /// Returns host action performed by daemon instead of macro.
+    pub fn action(&self) -> Option<HostAction> {
+        let slow = self.actions().next()?;
+        match self.fast.as_ref().and_then(|fast| fast.actions().next()) {
+            Some(fast) => Some(HostAction::BySpeed { slow: Box::new(slow), fast: Box::new(fast) }),
+            None => Some(slow),
+        }
+    }
+
+    fn actions(&self) -> impl Iterator<Item = HostAction> {
+        host_actions(&self.type_datetime, &self.paste_text, &self.run, self.timeout)
+    }
+
+    /// Macro bound to key, for `delay_only` it consists of single delay.
+    fn bound_macro(&self) -> Option<Macro> {
+        let delay = self.delay_only.map(|ms| Macro::Keyboard(vec![KeyboardPart::Delay(ms)]));
+        self.macro_.clone().or(delay)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(self.macro_.is_none() || self.delay_only.is_none(),
+                "Invalid mapping: key {} has both macro and delay_only", self.key);
+        ensure!(self.actions().count() <= 1, "Invalid mapping: key {} has several host actions", self.key);
+        ensure!(self.min_interval.is_none() || self.action().is_some(),
+                "Invalid mapping: `min_interval` is only used with host actions, key {}", self.key);
+        ensure!(self.timeout.is_none() || self.run.is_some(),
+                "Invalid mapping: `timeout` is only used with `run`, key {}", self.key);
+        if let Some(fast) = &self.fast {
+            ensure!(matches!(self.key, KeyRef::Knob(_, KnobAction::RotateCCW | KnobAction::RotateCW)),
+                    "Invalid mapping: `fast` is only used for knob rotation, key {}", self.key);
+            ensure!(self.actions().count() == 1,
+                    "Invalid mapping: `fast` is only used with host actions, key {}", self.key);
+            ensure!(fast.actions().count() == 1,
+                    "Invalid mapping: `fast` must have exactly one host action, key {}", self.key);
+        }
+        let action = self.action();
+        match (&self.bound_macro(), &action) {
+            (Some(_), Some(_)) => bail!("Invalid mapping: key {} has both macro and host action", self.key),
+            (None, None) => bail!("Invalid mapping: key {} has neither macro nor host action", self.key),
+            (Some(_), None) => ensure!(self.trigger.is_none(),
+                                       "Invalid mapping: `trigger` is only used with host actions, key {}", self.key),
+            (None, Some(action)) => action.validate()
+                .with_context(|| format!("Invalid mapping: invalid host action for key {}", self.key))?,
+        }
+        Ok(())
+    }
+}
+
+/// Host action for fast knob rotation, see `KeyBinding::fast`.
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FastAction {
+    pub type_datetime: Option<String>,
+    #[serde(alias = "paste_clipboard")]
+    pub paste_text: Option<String>,
+    pub run: Option<String>,
+    pub timeout: Option<u64>,
+}
+
+impl FastAction {
+    fn actions(&self) -> impl Iterator<Item = HostAction> {
+        host_actions(&self.type_datetime, &self.paste_text, &self.run, self.timeout)
+    }
+}
+
+fn host_actions(type_datetime: &Option<String>, paste_text: &Option<String>, run: &Option<String>, timeout: Option<u64>)
+    -> impl Iterator<Item = HostAction>
+{
+    [
+        type_datetime.clone().map(HostAction::TypeDatetime),
+        paste_text.clone().map(HostAction::PasteText),
+        run.clone().map(|command| HostAction::Run { command, timeout }),
+    ].into_iter().flatten()
+}
+
+/// Merges YAML documents given together with their names, see `Config::load_dir`.
+fn merge_documents(documents: Vec<(String, serde_yaml::Value)>) -> Result<serde_yaml::Value> {
+    use serde_yaml::{Mapping, Value};
+
+    const CONCATENATED: [&str; 2] = ["layers", "bindings"];
+
+    let mut merged = Mapping::new();
+    let mut origins: BTreeMap<String, String> = BTreeMap::new();
+    for (name, document) in documents {
+        let mapping = match document {
+            Value::Mapping(mapping) => mapping,
+            // Empty file.
+            Value::Null => continue,
+            _ => bail!("{name}: top level of config must be a mapping"),
+        };
+        for (key, value) in mapping {
+            let key_str = key.as_str().ok_or_else(|| anyhow!("{name}: config keys must be strings"))?.to_owned();
+            if CONCATENATED.contains(&key_str.as_str()) {
+                let Value::Sequence(items) = value else { bail!("{name}: `{key_str}` must be a list") };
+                match merged.entry(key).or_insert_with(|| Value::Sequence(vec![])) {
+                    Value::Sequence(all) => all.extend(items),
+                    _ => unreachable!("only lists are inserted"),
+                }
+            } else if let Some(existing) = merged.get(&key) {
+                ensure!(*existing == value, "`{key_str}` is set differently in {} and {name}", origins[&key_str]);
+            } else {
+                merged.insert(key, value);
+                origins.insert(key_str, name.clone());
+            }
+        }
+    }
+    Ok(Value::Mapping(merged))
+}
+
+/// Condition on machine config is used on. All given fields must match.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// Host name, compared case-insensitively, domain part may be omitted.
+    pub hostname: Option<String>,
+    pub os: Option<Os>,
+}
+
+impl Condition {
+    pub fn matches(&self, host: &Host) -> bool {
+        let hostname_matches = self.hostname.as_ref().is_none_or(|name| {
+            let short = host.hostname.split('.').next().unwrap_or_default();
+            host.hostname.eq_ignore_ascii_case(name) || short.eq_ignore_ascii_case(name)
+        });
+        let os_matches = self.os.is_none_or(|os| host.os == Some(os));
+        hostname_matches && os_matches
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, strum_macros::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Os {
+    Linux,
+    #[serde(alias = "mac")]
+    Macos,
+    Windows,
+}
+
+/// Machine config is rendered on.
+#[derive(Debug, Default)]
+pub struct Host {
+    pub hostname: String,
+    pub os: Option<Os>,
+}
+
+impl Host {
+    pub fn current() -> Self {
+        let os = if cfg!(target_os = "linux") {
+            Some(Os::Linux)
+        } else if cfg!(target_os = "macos") {
+            Some(Os::Macos)
+        } else if cfg!(windows) {
+            Some(Os::Windows)
+        } else {
+            None
+        };
+        Self { hostname: current_hostname().unwrap_or_default(), os }
+    }
+}
+
+fn current_hostname() -> Option<String> {
+    if let Ok(name) = std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        return Some(name.trim().to_owned());
+    }
+    if let Some(name) = std::env::var_os("COMPUTERNAME") {
+        return Some(name.to_string_lossy().into_owned());
+    }
+    let output = std::process::Command::new("hostname").output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Chords assigned to host actions which don't set trigger explicitly.
+/// They are unlikely to clash with anything.
+fn trigger_pool() -> impl Iterator<Item = Accord> {
+    use WellKnownCode::*;
+    const KEYS: [WellKnownCode; 12] = [F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24];
+    let modifiers = [
+        Modifier::Ctrl | Modifier::Alt | Modifier::Shift,
+        Modifier::Ctrl | Modifier::Alt | Modifier::Shift | Modifier::Win,
+    ];
+    modifiers.into_iter()
+        .flat_map(|mods| KEYS.into_iter().map(move |code| Accord::new(mods, Some(code.into()))))
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Settings applied to all bindings unless binding opts out.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    /// Replaces modifiers in keyboard macros, e.g. `{ cmd: ctrl }` to reuse
+    /// macOS config on Windows. All replacements are done at once, so
+    /// modifiers may be swapped.
+    #[serde(default)]
+    pub modifier_remap: BTreeMap<Modifier, Modifier>,
+
+    /// Modifier for mouse events which don't specify one explicitly.
+    pub mouse_modifier: Option<MouseModifier>,
+}
+
+impl Defaults {
+    pub fn apply(&self, macro_: &mut Macro) {
+        match macro_ {
+            Macro::Keyboard(parts) => {
+                if self.modifier_remap.is_empty() {
+                    return;
+                }
+                // Characters aren't translated yet, so modifiers layout adds,
+                // like shift of `+`, aren't remapped.
+                for part in parts {
+                    if let KeyboardPart::Key(Accord { modifiers, .. }) | KeyboardPart::Char(modifiers, _) = part {
+                        *modifiers = modifiers.iter()
+                            .map(|m| *self.modifier_remap.get(&m).unwrap_or(&m))
+                            .collect();
+                    }
+                }
+            }
+            Macro::Mouse(MouseEvent(_, modifier)) => {
+                if modifier.is_none() {
+                    *modifier = self.mouse_modifier;
+                }
+            }
+            Macro::Media(_) => {}
+        }
+    }
+}
+
+/// Key name used in config: one-based button number counted row by row
+/// as buttons are written in layer grid (`5`), row and column in layer grid
+/// (`r2c1`), knob action (`knob1-cw`), printed legend counted row by row in
+/// normal orientation (`k5`), name of key defined by model (`top`) or `*`
+/// for all keys of layer not bound otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyRef {
+    /// Zero-based button index.
+    Button(u8),
+    /// Zero-based knob index and action.
+    Knob(u8, KnobAction),
+    /// All keys left unbound.
+    All,
+    /// Zero-based row and column in layer grid, resolved using geometry.
+    Cell(u8, u8),
+    /// Zero-based legend number, resolved using geometry.
+    Legend(u8),
+    /// Key name, resolved using model.
+    Named(String),
+}
+
+impl Display for KeyRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyRef::Button(n) => write!(f, "{}", n + 1),
+            KeyRef::Knob(n, action) => write!(f, "knob{}-{}", n + 1, action),
+            KeyRef::All => write!(f, "*"),
+            KeyRef::Cell(r, c) => write!(f, "r{}c{}", r + 1, c + 1),
+            KeyRef::Legend(n) => write!(f, "k{}", n + 1),
+            KeyRef::Named(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl FromStr for KeyRef {
+    type Err = nom::error::Error<String>;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        parse::from_str(parse::key_ref, s)
+    }
+}
+
+// Plain button numbers are integers in YAML, so accept them along with strings.
+impl<'de> Deserialize<'de> for KeyRef {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = KeyRef;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "key name, like '5' or 'knob1-cw'")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> std::result::Result<KeyRef, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> std::result::Result<KeyRef, E> {
+                v.parse().map_err(|_| E::custom(format!("invalid key name '{v}'")))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(try_from = "KnobSpec")]
 pub struct Knob {
     pub ccw: Option<Macro>,
     pub press: Option<Macro>,
     pub cw: Option<Macro>,
 }
 
+/// Knob as written in config file, before sugar is expanded.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KnobSpec {
+    ccw: Option<Macro>,
+    press: Option<Macro>,
+    cw: Option<Macro>,
+    /// Shorthand for scrolling with optional modifier:
+    /// `scroll: ctrl` is the same as `ccw: ctrl-wheelup, cw: ctrl-wheeldown`.
+    scroll: Option<Scroll>,
+}
+
+impl TryFrom<KnobSpec> for Knob {
+    type Error = String;
+
+    fn try_from(spec: KnobSpec) -> std::result::Result<Self, Self::Error> {
+        let KnobSpec { ccw, press, cw, scroll } = spec;
+        let Some(scroll) = scroll else {
+            return Ok(Knob { ccw, press, cw });
+        };
+        if ccw.is_some() || cw.is_some() {
+            return Err("knob 'scroll' can't be combined with 'ccw' or 'cw'".to_owned());
+        }
+        let modifier = match scroll {
+            Scroll::Plain => None,
+            Scroll::With(modifier) => Some(modifier),
+        };
+        Ok(Knob {
+            ccw: Some(Macro::Mouse(MouseEvent(MouseAction::WheelUp(1), modifier))),
+            press,
+            cw: Some(Macro::Mouse(MouseEvent(MouseAction::WheelDown(1), modifier))),
+        })
+    }
+}
+
+/// Modifier used for knob `scroll` shorthand, `none` means plain scrolling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DeserializeFromStr)]
+pub enum Scroll {
+    Plain,
+    With(MouseModifier),
+}
+
+impl FromStr for Scroll {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(Scroll::Plain);
+        }
+        MouseModifier::from_str(s)
+            .map(Scroll::With)
+            .map_err(|_| format!("invalid scroll modifier '{s}', expected one of: none, ctrl, shift, alt"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FlatLayer {
     pub buttons: Vec<Option<Macro>>,
     pub knobs: Vec<Knob>,
 }
 
+impl FlatLayer {
+    /// Iterates over bound keys in order they are uploaded: buttons, then
+    /// knob actions.
+    pub fn bindings(&self) -> impl Iterator<Item = (Key, &Macro)> {
+        let buttons = self.buttons.iter().enumerate()
+            .filter_map(|(i, macro_)| Some((Key::Button(i as u8), macro_.as_ref()?)));
+        let knobs = self.knobs.iter().enumerate().flat_map(|(i, knob)| {
+            [(KnobAction::RotateCCW, &knob.ccw), (KnobAction::Press, &knob.press), (KnobAction::RotateCW, &knob.cw)]
+                .into_iter()
+                .filter_map(move |(action, macro_)| Some((Key::Knob(i as u8, action), macro_.as_ref()?)))
+        });
+        buttons.chain(knobs)
+    }
+}
+
 fn reorient_grid<T: Clone>(orientation: Orientation, rows: usize, cols: usize, data: Vec<Vec<T>>) -> Vec<T> {
     // Transforms physical button position to virtual.
     let tr = match orientation {
@@ -189,9 +958,15 @@ fn reorient_row<T>(orientation: Orientation, mut data: Vec<T>) -> Vec<T> {
 mod tests {
     use crate::config::Layer;
 
-    use super::{reorient_grid, Config, Knob, Orientation};
+    use super::{merge_documents, reorient_grid, Condition, Config, FlatLayer, Host, Knob, Orientation, Os};
+    use crate::action::HostAction;
+    use crate::keyboard::{Macro, Quirk};
+    use crate::protocol::Protocol;
 
     use std::path::PathBuf;
+    use std::time::Duration;
+
+    use indoc::indoc;
 
     #[test]
     fn parse_example_config() -> anyhow::Result<()> {
@@ -205,6 +980,13 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_orientation() {
+        assert_eq!("counterclockwise".parse(), Ok(Orientation::CounterClockwise));
+        assert_eq!("upsidedown".parse(), Ok(Orientation::UpsideDown));
+        assert!("sideways".parse::<Orientation>().is_err());
+    }
+
     #[test]
     fn test_reorient_grid() {
         assert_eq!(
@@ -259,10 +1041,568 @@ mod tests {
                     knobs: vec![Knob { ccw: None, press: None, cw: None }],
                 },
             ],
+            ..Default::default()
         };
         config.render().unwrap();
     }
 
+    #[test]
+    fn expand_bindings_to_layers() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: clockwise
+            rows: 1
+            columns: 2
+            knobs: 1
+            layers:
+              - buttons: [[a], [~]]
+              - {}
+              - {}
+            bindings:
+              - key: 2
+                macro: ctrl-c
+              - key: 1
+                layers: [2, 3]
+                macro: ctrl-v
+              - key: knob1-press
+                layers: [3]
+                macro: mute
+        "}).unwrap();
+        let layers = config.render().unwrap();
+        let macros = |layer: &FlatLayer| layer.buttons.iter()
+            .map(|m| m.as_ref().map(|m| m.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(macros(&layers[0]), [Some("a".to_owned()), Some("ctrl-c".to_owned())]);
+        assert_eq!(macros(&layers[1]), [Some("ctrl-v".to_owned()), Some("ctrl-c".to_owned())]);
+        assert_eq!(layers[1].knobs[0].press, None);
+        assert_eq!(layers[2].knobs[0].press, Some("mute".parse().unwrap()));
+    }
+
+    #[test]
+    fn wildcard_binding() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 3
+            knobs: 1
+            layers:
+              - buttons: [[a, ~, ~]]
+              - {}
+            bindings:
+              - { key: '*', layers: [1], macro: disabled }
+              - { key: 2, layers: [1], macro: b }
+        "}).unwrap();
+        let layers = config.render().unwrap();
+        let disabled = Some(Macro::Keyboard(vec![]));
+        assert_eq!(layers[0].buttons, [Some("a".parse().unwrap()), Some("b".parse().unwrap()), disabled.clone()]);
+        assert_eq!(layers[0].knobs[0].cw, disabled);
+        assert_eq!(layers[1].buttons, [None, None, None]);
+    }
+
+    #[test]
+    fn legend_key_names() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: upsidedown
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers: [{}]
+            bindings:
+              - { key: k1, macro: a }
+              - { key: r1c1, macro: b }
+        "}).unwrap();
+        // Legends follow normal orientation, rows and columns follow layer grid.
+        let layers = config.render().unwrap();
+        assert_eq!(layers[0].buttons, [Some("a".parse().unwrap()), Some("b".parse().unwrap())]);
+
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers: [{}]
+            bindings:
+              - { key: k3, macro: a }
+        "}).unwrap();
+        assert!(config.render().is_err());
+
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers: [{}]
+            bindings:
+              - { key: r2c1, macro: a }
+        "}).unwrap();
+        assert!(config.render().is_err());
+    }
+
+    #[test]
+    fn delay_only_binding() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers: [{}]
+            bindings:
+              - { key: 1, delay_only: 500 }
+              - { key: 2, delay_only: 7000 }
+        "}).unwrap();
+        assert!(config.render().is_err());
+
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers: [{}]
+            bindings:
+              - { key: 1, delay_only: 500 }
+        "}).unwrap();
+        assert_eq!(config.render().unwrap()[0].buttons[0], Some("delay[500]".parse().unwrap()));
+
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers: [{}]
+            bindings:
+              - { key: 1, delay_only: 500, macro: a }
+        "}).unwrap();
+        assert!(config.render().is_err());
+    }
+
+    #[test]
+    fn flat_layer_bindings() {
+        let layer = FlatLayer {
+            buttons: vec![None, Some("a".parse().unwrap())],
+            knobs: vec![Knob { press: Some("mute".parse().unwrap()), ..Knob::default() }],
+        };
+        let keys: Vec<String> = layer.bindings().map(|(key, _)| key.to_string()).collect();
+        assert_eq!(keys, ["button 1", "knob 0 press"]);
+    }
+
+    #[test]
+    fn emulate_delays() {
+        let mut config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 3
+            knobs: 0
+            defaults: { modifier_remap: { win: ctrl } }
+            layers:
+              - buttons: [['delay[100],win-a,b', c, null]]
+            bindings:
+              - { key: 3, run: 'true' }
+        "}).unwrap();
+        assert!(config.emulate_unsupported(Protocol::K884x).unwrap().is_empty());
+        let actions = config.emulate_unsupported(Protocol::K8890).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].0, "ctrl-alt-shift-f14".parse().unwrap());
+        assert_eq!(actions[0].1.to_string(), "type 'ctrl-a,b' with 100ms between key presses");
+
+        let layers = config.render().unwrap();
+        assert_eq!(layers[0].buttons[0], Some("ctrl-alt-shift-f14".parse().unwrap()));
+
+        // k884x firmware only knows single leading delay.
+        let mut config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers:
+              - buttons: [['delay[100],a,b', 'alt-tab,delay[200],enter']]
+        "}).unwrap();
+        let actions = config.emulate_unsupported(Protocol::K884x).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].1.to_string(), "type 'opt-tab,delay[200],enter'");
+        assert!(config.render().is_ok());
+
+        let mut config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers:
+              - buttons: [['a,b,c,d,e', 'a,b,c,d,e,f']]
+        "}).unwrap();
+        let actions = config.emulate_unsupported(Protocol::K8890).unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].1.to_string(), "type 'a,b,c,d,e,f'");
+
+        // Dead key makes `é` two presses, modified characters are chords.
+        let mut config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layout: de
+            layers:
+              - buttons: [['a,b,c,d,é', 'ctrl-+,a,b,c,d,e']]
+        "}).unwrap();
+        let actions = config.emulate_unsupported(Protocol::K8890).unwrap();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0].1.to_string(), "type 'a,b,c,d,é'");
+        assert_eq!(actions[1].1.to_string(), "type 'ctrl-rightbracket,a,b,c,d,e'");
+    }
+
+    #[test]
+    fn device_settings() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            model: minibar
+            orientation: normal
+            quirks: [be-media, short-finish]
+            layers: [{}]
+        "}).unwrap();
+        assert_eq!(config.device.quirks, [Quirk::BigEndianMedia, Quirk::ShortFinish]);
+    }
+
+    #[test]
+    fn knobs_match_model() {
+        let parse = |yaml: &str| serde_yaml::from_str::<Config>(yaml).unwrap().render();
+        let knob = "{ ccw: volumedown, press: mute, cw: volumeup }";
+        let layers = parse(&format!("{{ model: 3x3-2knobs, orientation: normal, layers: [{{ knobs: [{knob}, {knob}] }}] }}")).unwrap();
+        assert_eq!(layers[0].knobs[1].press, Some("mute".parse().unwrap()));
+
+        assert_eq!(parse("{ model: 3x3-2knobs, orientation: normal, knobs: 3, layers: [{}] }").err().unwrap().to_string(),
+                   "`knobs` is 3, but model 3x3-2knobs has 2");
+        assert_eq!(parse(&format!("{{ model: 3x3-2knobs, orientation: normal, layers: [{{ knobs: [{knob}] }}] }}")).err().unwrap().to_string(),
+                   "Invalid number of knobs in layer 0");
+
+        let layers = parse("{ model: 4x3-3knobs, orientation: normal, layers: [{}], bindings: [{ key: knob3-cw, macro: volumeup }] }").unwrap();
+        assert_eq!(layers[0].knobs[2].cw, Some("volumeup".parse().unwrap()));
+        assert!(parse("{ model: 3x3-2knobs, orientation: normal, layers: [{}], bindings: [{ key: knob3-cw, macro: volumeup }] }")
+            .err().unwrap().to_string().contains("keyboard has 2 knobs"));
+    }
+
+    #[test]
+    fn model_key_names() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            model: minibar
+            orientation: clockwise
+            layers:
+              - buttons: [[~, a, ~]]
+            bindings:
+              - key: top
+                macro: b
+              - key: knob-cw
+                macro: volumeup
+        "}).unwrap();
+        let layers = config.render().unwrap();
+        assert_eq!(layers[0].buttons, [Some("b".parse().unwrap()), Some("a".parse().unwrap()), None]);
+        assert_eq!(layers[0].knobs[0].cw, Some("volumeup".parse().unwrap()));
+
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 3
+            knobs: 1
+            layers: [{}]
+            bindings: [{ key: top, macro: b }]
+        "}).unwrap();
+        assert!(config.render().err().unwrap().to_string().contains("only be used when `model` is set"));
+    }
+
+    #[test]
+    #[should_panic(expected="bound both in layer and in bindings")]
+    fn reject_binding_conflicting_with_grid() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 1
+            knobs: 0
+            layers:
+              - buttons: [[a]]
+            bindings:
+              - { key: 1, macro: b }
+        "}).unwrap();
+        config.render().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected="targets layer 2")]
+    fn reject_binding_to_missing_layer() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 1
+            knobs: 0
+            layers:
+              - {}
+            bindings:
+              - { key: 1, layers: [2], macro: b }
+        "}).unwrap();
+        config.render().unwrap();
+    }
+
+    #[test]
+    fn apply_defaults() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 4
+            knobs: 0
+            layers:
+              - buttons: [[cmd-c, ctrl-alt-x, wheelup, alt-wheeldown]]
+              - {}
+            bindings:
+              - { key: 1, layers: [2], macro: cmd-v, defaults: false }
+              - { key: 2, layers: [2], macro: cmd-v }
+              - { key: 3, layers: [2], macro: 'cmd-+' }
+            defaults:
+              modifier_remap: { cmd: ctrl, ctrl: cmd, shift: alt }
+              mouse_modifier: shift
+        "}).unwrap();
+        let layers = config.render().unwrap();
+        let parse = |s: &str| Some(s.parse::<Macro>().unwrap());
+
+        assert_eq!(layers[0].buttons, [parse("ctrl-c"), parse("alt-cmd-x"), parse("shift-wheelup"), parse("alt-wheeldown")]);
+        // Shift added by layout for `+` isn't remapped.
+        assert_eq!(layers[1].buttons, [parse("cmd-v"), parse("ctrl-v"), parse("ctrl-shift-equal"), None]);
+    }
+
+    #[test]
+    fn translate_characters_using_layout() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 2
+            layout: uk
+            layers:
+              - buttons: [['@', 'ctrl-a,:']]
+                knobs: [{ ccw: '-', cw: '+' }, {}]
+        "}).unwrap();
+        let layers = config.render().unwrap();
+        let parse = |s: &str| Some(s.parse::<Macro>().unwrap());
+
+        assert_eq!(layers[0].buttons, [parse("shift-quote"), parse("ctrl-a,shift-semicolon")]);
+        assert_eq!(layers[0].knobs[0].ccw, parse("minus"));
+        assert_eq!(layers[0].knobs[0].cw, parse("shift-equal"));
+    }
+
+    #[test]
+    fn number_shorthand() {
+        let layer: Layer = serde_yaml::from_str(indoc! {"
+            buttons:
+              - - number: 42
+                - { number: '-0.5', numpad: true, enter: true }
+                - { number: '007', enter: true }
+                - 5
+        "}).unwrap();
+        let parse = |s: &str| Some(s.parse::<Macro>().unwrap());
+        assert_eq!(layer.buttons[0], [
+            parse("4,2"),
+            parse("numpadminus,numpad0,numpaddot,numpad5,numpadenter"),
+            parse("0,0,7,enter"),
+            parse("5"),
+        ]);
+
+        assert!(serde_yaml::from_str::<Layer>("buttons: [[{ number: 12a }]]").is_err());
+        assert!(serde_yaml::from_str::<Layer>("buttons: [[{ enter: true }]]").is_err());
+    }
+
+    #[test]
+    fn launch_shorthand() {
+        let layer: Layer = serde_yaml::from_str(indoc! {"
+            buttons:
+              - [{ launch: calculator }, { launch: computer }, browser]
+        "}).unwrap();
+        let parse = |s: &str| Some(s.parse::<Macro>().unwrap());
+        assert_eq!(layer.buttons[0], [parse("calculator"), parse("mycomputer"), parse("browser")]);
+
+        assert!(serde_yaml::from_str::<Layer>("buttons: [[{ launch: terminal }]]").is_err());
+        assert!(serde_yaml::from_str::<Layer>("buttons: [[{ launch: email, enter: true }]]").is_err());
+    }
+
+    #[test]
+    fn knob_scroll_shorthand() {
+        let knob: Knob = serde_yaml::from_str("{ scroll: ctrl, press: mclick }").unwrap();
+        assert_eq!(knob.ccw, Some("ctrl-wheelup".parse().unwrap()));
+        assert_eq!(knob.cw, Some("ctrl-wheeldown".parse().unwrap()));
+        assert_eq!(knob.press, Some("mclick".parse().unwrap()));
+
+        let knob: Knob = serde_yaml::from_str("{ scroll: none }").unwrap();
+        assert_eq!(knob.ccw, Some("wheelup".parse().unwrap()));
+        assert_eq!(knob.press, None);
+
+        assert!(serde_yaml::from_str::<Knob>("{ scroll: ctrl, cw: a }").is_err());
+        assert!(serde_yaml::from_str::<Knob>("{ scroll: win }").is_err());
+    }
+
+    #[test]
+    fn assign_triggers_to_host_actions() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers:
+              - {}
+            bindings:
+              - { key: 1, type_datetime: '%Y-%m-%d' }
+              - { key: 2, type_datetime: '%H:%M', trigger: ctrl-f12 }
+        "}).unwrap();
+        let actions = config.host_actions().unwrap();
+        assert_eq!(actions, [
+            ("ctrl-alt-shift-f13".parse().unwrap(), HostAction::TypeDatetime("%Y-%m-%d".to_owned())),
+            ("ctrl-f12".parse().unwrap(), HostAction::TypeDatetime("%H:%M".to_owned())),
+        ]);
+
+        let layers = config.render().unwrap();
+        let parse = |s: &str| Some(s.parse::<Macro>().unwrap());
+        assert_eq!(layers[0].buttons, [parse("ctrl-alt-shift-f13"), parse("ctrl-f12")]);
+    }
+
+    #[test]
+    #[should_panic(expected="invalid date/time format")]
+    fn reject_invalid_datetime_format() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 1
+            knobs: 0
+            layers:
+              - {}
+            bindings:
+              - { key: 1, type_datetime: '%Q' }
+        "}).unwrap();
+        config.render().unwrap();
+    }
+
+    #[test]
+    fn paste_text_action() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers:
+              - {}
+            bindings:
+              - { key: 1, macro: a }
+              - { key: 2, paste_clipboard: 'Kind regards' }
+        "}).unwrap();
+        let actions = config.host_actions().unwrap();
+        assert_eq!(actions, [
+            ("ctrl-alt-shift-f13".parse().unwrap(), HostAction::PasteText("Kind regards".to_owned())),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected="several host actions")]
+    fn reject_several_host_actions() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 1
+            knobs: 0
+            layers:
+              - {}
+            bindings:
+              - { key: 1, type_datetime: '%Y', paste_text: abc }
+        "}).unwrap();
+        config.render().unwrap();
+    }
+
+    #[test]
+    fn select_bindings_for_host() {
+        let mut config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 2
+            knobs: 0
+            layers:
+              - {}
+            bindings:
+              - { key: 1, macro: cmd-c, when: { os: mac } }
+              - { key: 1, macro: ctrl-c, when: { os: linux } }
+              - { key: 2, macro: a, when: { hostname: WorkPC, os: linux } }
+        "}).unwrap();
+        config.select_for_host(&Host { hostname: "workpc.example.com".to_owned(), os: Some(Os::Linux) });
+        let layers = config.render().unwrap();
+        let parse = |s: &str| Some(s.parse::<Macro>().unwrap());
+        assert_eq!(layers[0].buttons, [parse("ctrl-c"), parse("a")]);
+
+        let condition: Condition = serde_yaml::from_str("{ hostname: workpc }").unwrap();
+        assert!(!condition.matches(&Host { hostname: "homepc".to_owned(), os: Some(Os::Linux) }));
+        assert!(serde_yaml::from_str::<Condition>("{ os: beos }").is_err());
+        assert!(serde_yaml::from_str::<Condition>("{ user: me }").is_err());
+    }
+
+    #[test]
+    fn merge_config_files() {
+        let documents = [
+            ("00-base.yaml", "{ orientation: normal, rows: 1, columns: 1, knobs: 0 }"),
+            ("10-editing.yaml", "layers: [{ buttons: [[ctrl-c]] }]\nbindings: [{ key: 1, layers: [3], macro: b }]"),
+            ("20-media.yaml", "rows: 1\nlayers: [{ buttons: [[play]] }, {}]"),
+            ("30-empty.yaml", "~"),
+        ];
+        let documents = documents.iter()
+            .map(|(name, yaml)| (name.to_string(), serde_yaml::from_str(yaml).unwrap()))
+            .collect();
+        let config: Config = serde_yaml::from_value(merge_documents(documents).unwrap()).unwrap();
+        let layers = config.render().unwrap();
+        let parse = |s: &str| Some(s.parse::<Macro>().unwrap());
+        assert_eq!(layers[0].buttons, [parse("ctrl-c")]);
+        assert_eq!(layers[1].buttons, [parse("play")]);
+        assert_eq!(layers[2].buttons, [parse("b")]);
+
+        let documents = vec![
+            ("a.yaml".to_owned(), serde_yaml::from_str("rows: 1").unwrap()),
+            ("b.yaml".to_owned(), serde_yaml::from_str("rows: 2").unwrap()),
+        ];
+        let err = merge_documents(documents).unwrap_err();
+        assert_eq!(err.to_string(), "`rows` is set differently in a.yaml and b.yaml");
+    }
+
+    #[test]
+    fn sandbox_settings() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 1
+            layers: [{}]
+            sandbox: { wrapper: [systemd-run, --user, --scope], timeout: 10000 }
+        "}).unwrap();
+        assert_eq!(config.sandbox.wrapper, ["systemd-run", "--user", "--scope"]);
+        assert_eq!(config.sandbox.timeout, Some(10000));
+    }
+
+    #[test]
+    fn speed_dependent_knob_action() {
+        let mut config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 1
+            columns: 1
+            knobs: 1
+            layers:
+              - {}
+            bindings:
+              - key: knob1-cw
+                run: volume +1
+                fast: { run: volume +10, timeout: 500 }
+        "}).unwrap();
+        let actions = config.host_actions().unwrap();
+        assert_eq!(actions[0].1, HostAction::BySpeed {
+            slow: Box::new(HostAction::Run { command: "volume +1".to_owned(), timeout: None }),
+            fast: Box::new(HostAction::Run { command: "volume +10".to_owned(), timeout: Some(500) }),
+        });
+        // Knob sends chord per step, so it isn't debounced.
+        assert_eq!(config.min_intervals().unwrap(), [(actions[0].0, Duration::ZERO)]);
+
+        let parse = |bindings: &str| serde_yaml::from_str::<Config>(&format!(
+            "{{ orientation: normal, rows: 1, columns: 1, knobs: 1, layers: [{{}}], bindings: [{bindings}] }}"
+        )).unwrap().host_actions();
+        assert!(parse("{ key: knob1-press, run: a, fast: { run: b } }").is_err());
+        assert!(parse("{ key: knob1-press, paste_text: a, timeout: 500 }").is_err());
+        assert!(parse("{ key: knob1-cw, macro: a, fast: { run: b } }").is_err());
+        assert!(parse("{ key: knob1-cw, run: a, fast: {} }").is_err());
+    }
+
     #[test]
     fn accept_single_leading_delay() {
         let config = Config {
@@ -280,6 +1620,7 @@ mod tests {
                     knobs: vec![],
                 }
             ],
+            ..Default::default()
         };
         config.render().unwrap();
     }
@@ -302,6 +1643,7 @@ mod tests {
                     knobs: vec![],
                 }
             ],
+            ..Default::default()
         };
         config.render().unwrap();
     }
@@ -324,6 +1666,7 @@ mod tests {
                     knobs: vec![],
                 }
             ],
+            ..Default::default()
         };
         config.render().unwrap();
     }
@@ -341,6 +1684,7 @@ mod tests {
                     knobs: vec![Knob { ccw: Some("delay[500],1".parse().unwrap()), press: None, cw: None }],
                 }
             ],
+            ..Default::default()
         };
         config.render().unwrap();
     }
@@ -359,6 +1703,7 @@ mod tests {
                     knobs: vec![Knob { ccw: Some("delay[100],delay[200],1".parse().unwrap()), press: None, cw: None }],
                 }
             ],
+            ..Default::default()
         };
         config.render().unwrap();
     }
@@ -377,6 +1722,7 @@ mod tests {
                     knobs: vec![Knob { ccw: Some("1,delay[100]".parse().unwrap()), press: None, cw: None }],
                 }
             ],
+            ..Default::default()
         };
         config.render().unwrap();
     }
@@ -1,2 +1,4 @@
 pub const VENDOR_ID: u16 = 0x1189;
-pub const PRODUCT_IDS: [u16; 3] = [0x8840, 0x8842, 0x8890];
+/// Vendor and product IDs searched by default. 12-key, 4-knob pads with
+/// product ID 8850 have their own vendor ID.
+pub const DEVICE_IDS: [(u16, u16); 4] = [(VENDOR_ID, 0x8840), (VENDOR_ID, 0x8842), (0x514c, 0x8850), (VENDOR_ID, 0x8890)];
@@ -0,0 +1,219 @@
+//! Rewrites config for keyboard of another model, dropping what target
+//! keyboard can't do.
+
+use std::str::FromStr;
+
+use anyhow::{Context as _, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::config::{Config, Knob};
+use crate::keyboard::{KeyboardPart, Macro, MouseAction, MouseEvent};
+use crate::model::Model;
+use crate::protocol::Protocol;
+
+/// What config is converted for: known model or just protocol, in the
+/// latter case geometry is kept.
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    Model(&'static Model),
+    Protocol(Protocol),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(protocol) = s.parse() {
+            return Ok(Target::Protocol(protocol));
+        }
+        Model::find(s).map(Target::Model).ok_or_else(|| format!("unknown model or protocol '{s}'"))
+    }
+}
+
+pub struct Conversion {
+    /// Converted config as YAML.
+    pub config: String,
+    /// Descriptions of bindings which were changed or dropped.
+    pub warnings: Vec<String>,
+}
+
+/// Converts config for target keyboard. Buttons and knobs missing on target
+/// are dropped, keyboard macros are cut to fit into its macro length and
+/// delays are removed if unsupported.
+///
+/// Converted config lists all macros in layer grids in normal orientation.
+pub fn convert(mut config: Config, target: Target) -> Result<Conversion> {
+    let mut warnings = vec![];
+    if !config.host_actions()?.is_empty() {
+        warnings.push("host actions aren't converted, their keys send trigger chords which daemon won't know".to_owned());
+    }
+    config.apply_model()?;
+    let (rows, columns, knobs) = (config.rows as usize, config.columns as usize, config.knobs as usize);
+    let layers = config.render()?;
+
+    let (protocol, (trows, tcolumns, tknobs)) = match target {
+        Target::Model(model) => (model.protocol(), (model.rows as usize, model.columns as usize, model.knobs as usize)),
+        Target::Protocol(protocol) => (protocol, (rows, columns, knobs)),
+    };
+    // Same rule as in config validation.
+    let is_limited = (trows == 1 || tcolumns == 1) && tknobs == 1;
+
+    let mut out_layers = vec![];
+    for (i, layer) in layers.iter().enumerate() {
+        for (j, macro_) in layer.buttons.iter().enumerate() {
+            let (r, c) = (j / columns, j % columns);
+            if let Some(macro_) = macro_.as_ref().filter(|_| r >= trows || c >= tcolumns) {
+                warnings.push(format!("layer {}, button {}: '{macro_}' is dropped, target has no such button", i + 1, j + 1));
+            }
+        }
+        for (j, knob) in layer.knobs.iter().enumerate().skip(tknobs) {
+            if [&knob.ccw, &knob.press, &knob.cw].iter().any(|macro_| macro_.is_some()) {
+                warnings.push(format!("layer {}, knob {}: dropped, target has no such knob", i + 1, j + 1));
+            }
+        }
+        let mut fit = |macro_: &Option<Macro>, key: String| -> Value {
+            let Some(macro_) = macro_ else { return Value::Null };
+            match fit_macro(macro_, protocol, is_limited) {
+                Ok(fitted) => {
+                    if &fitted != macro_ {
+                        warnings.push(format!("layer {}, {key}: '{macro_}' is changed to '{fitted}'", i + 1));
+                    }
+                    Value::String(fitted.to_string())
+                }
+                Err(reason) => {
+                    warnings.push(format!("layer {}, {key}: '{macro_}' is dropped, {reason}", i + 1));
+                    Value::Null
+                }
+            }
+        };
+
+        let buttons: Vec<Value> = (0..trows).map(|r| {
+            Value::Sequence((0..tcolumns).map(|c| {
+                let macro_ = if r < rows && c < columns { &layer.buttons[r * columns + c] } else { &None };
+                fit(macro_, format!("row {}, column {}", r + 1, c + 1))
+            }).collect())
+        }).collect();
+
+        let empty = Knob::default();
+        let knobs: Vec<Value> = (0..tknobs).map(|j| {
+            let knob = layer.knobs.get(j).unwrap_or(&empty);
+            let mut mapping = Mapping::new();
+            for (action, macro_) in [("ccw", &knob.ccw), ("press", &knob.press), ("cw", &knob.cw)] {
+                mapping.insert(action.into(), fit(macro_, format!("knob{}-{action}", j + 1)));
+            }
+            Value::Mapping(mapping)
+        }).collect();
+
+        let mut mapping = Mapping::new();
+        mapping.insert("buttons".into(), Value::Sequence(buttons));
+        mapping.insert("knobs".into(), Value::Sequence(knobs));
+        out_layers.push(Value::Mapping(mapping));
+    }
+
+    let mut root = Mapping::new();
+    root.insert("orientation".into(), "normal".into());
+    match target {
+        Target::Model(model) => {
+            root.insert("model".into(), model.name.into());
+        }
+        Target::Protocol(_) => {
+            root.insert("rows".into(), rows.into());
+            root.insert("columns".into(), columns.into());
+            root.insert("knobs".into(), knobs.into());
+        }
+    }
+    root.insert("layers".into(), Value::Sequence(out_layers));
+
+    let config = serde_yaml::to_string(&root)?;
+    // Catch limits converter doesn't know about before config is used.
+    serde_yaml::from_str::<Config>(&config)?.render().context("converted config is invalid")?;
+    Ok(Conversion { config, warnings })
+}
+
+/// Changes keyboard macro to fit protocol limits, fails if it can't be done.
+fn fit_macro(macro_: &Macro, protocol: Protocol, is_limited: bool) -> std::result::Result<Macro, &'static str> {
+    if let Macro::Mouse(MouseEvent(action @ (MouseAction::WheelUp(_) | MouseAction::WheelDown(_)
+                                             | MouseAction::WheelLeft(_) | MouseAction::WheelRight(_)), modifier)) = macro_ {
+        if protocol.supports_wheel_notches() {
+            return Ok(macro_.clone());
+        }
+        let action = match action {
+            MouseAction::WheelUp(_) => MouseAction::WheelUp(1),
+            MouseAction::WheelDown(_) => MouseAction::WheelDown(1),
+            MouseAction::WheelLeft(_) => MouseAction::WheelLeft(1),
+            _ => MouseAction::WheelRight(1),
+        };
+        return Ok(Macro::Mouse(MouseEvent(action, *modifier)));
+    }
+    if let Macro::Mouse(MouseEvent(MouseAction::Drag { .. }, _)) = macro_ {
+        if !protocol.supports_drag() {
+            return Err("target can't drag");
+        }
+    }
+    let Macro::Keyboard(parts) = macro_ else { return Ok(macro_.clone()) };
+    // Disabled key stays disabled.
+    if parts.is_empty() {
+        return Ok(macro_.clone());
+    }
+    let mut parts = parts.clone();
+    if !protocol.supports_delays() {
+        parts.retain(|part| !matches!(part, KeyboardPart::Delay(_)));
+    }
+    parts.truncate(protocol.max_presses());
+    if is_limited && parts.iter().skip(1).any(|part| matches!(part, KeyboardPart::Key(accord) if !accord.modifiers.is_empty())) {
+        return Err("target supports modifiers for first key only");
+    }
+    if parts.is_empty() {
+        return Err("nothing is left of it");
+    }
+    Ok(Macro::Keyboard(parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{convert, Target};
+    use crate::config::Config;
+
+    #[test]
+    fn convert_to_smaller_model() {
+        let config: Config = serde_yaml::from_str(indoc! {"
+            orientation: normal
+            rows: 2
+            columns: 2
+            knobs: 2
+            layers:
+              - buttons:
+                  - ['delay[100],a,b,c,d,e,f', b]
+                  - [c, 'ctrl-a,ctrl-b']
+                knobs:
+                  - { ccw: wheelup*3, cw: volumeup }
+                  - { press: mute }
+        "}).unwrap();
+        let conversion = convert(config, "minibar".parse().unwrap()).unwrap();
+        assert_eq!(conversion.config, indoc! {"
+            orientation: normal
+            model: minibar
+            layers:
+            - buttons:
+              - - a,b,c,d,e
+              - - c
+              - - null
+              knobs:
+              - ccw: wheelup
+                press: null
+                cw: volumeup
+        "});
+        assert_eq!(conversion.warnings, [
+            "layer 1, button 2: 'b' is dropped, target has no such button",
+            "layer 1, button 4: 'ctrl-a,ctrl-b' is dropped, target has no such button",
+            "layer 1, knob 2: dropped, target has no such knob",
+            "layer 1, row 1, column 1: 'delay[100],a,b,c,d,e,f' is changed to 'a,b,c,d,e'",
+            "layer 1, knob1-ccw: 'wheelup*3' is changed to 'wheelup'",
+        ]);
+
+        assert!("k8890".parse::<Target>().is_ok());
+        assert!("k9999".parse::<Target>().is_err());
+    }
+}
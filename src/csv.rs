@@ -0,0 +1,159 @@
+//! Bindings as CSV with row per bound key: `layer,key,macro`, so large
+//! layouts may be planned in spreadsheets and imported back.
+//!
+//! Buttons are named by legends (`k1`, `k2`, …) and knobs as `knob1-cw`,
+//! both counted in normal orientation, so rows don't depend on how
+//! keyboard is held.
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::config::{Config, FlatLayer, KeyRef};
+use crate::keyboard::{Key, Macro};
+use crate::model::Model;
+use crate::parse;
+
+const HEADER: [&str; 3] = ["layer", "key", "macro"];
+
+/// Renders bound keys of all layers as CSV with header.
+pub fn export(layers: &[FlatLayer]) -> String {
+    let mut out = row(&HEADER);
+    for (i, layer) in layers.iter().enumerate() {
+        for (key, macro_) in layer.bindings() {
+            let key = match key {
+                Key::Button(n) => format!("k{}", n + 1),
+                Key::Knob(n, action) => format!("knob{}-{action}", n + 1),
+            };
+            out.push_str(&row(&[&(i + 1).to_string(), &key, &macro_.to_string()]));
+        }
+    }
+    out
+}
+
+/// Turns CSV rows into config for given model with key bound in
+/// `bindings` section. Header row is optional.
+pub fn import(text: &str, model: &'static Model) -> Result<String> {
+    let mut rows = parse(text)?;
+    if rows.first().is_some_and(|row| row.iter().map(String::as_str).eq(HEADER)) {
+        rows.remove(0);
+    }
+
+    let mut layers = 0;
+    let mut bindings = vec![];
+    for (i, row) in rows.iter().enumerate() {
+        let [layer, key, macro_] = row.as_slice() else {
+            bail!("row {}: expected 3 fields (layer, key, macro), got {}", i + 1, row.len());
+        };
+        let layer: u8 = layer.trim().parse().ok().filter(|n| *n > 0)
+            .with_context(|| format!("row {}: invalid layer number '{layer}'", i + 1))?;
+        let key: KeyRef = key.trim().parse().map_err(|_| anyhow!("row {}: invalid key '{key}'", i + 1))?;
+        let macro_: Macro = macro_.trim().parse().map_err(|_| match parse::suggest(macro_) {
+            Some(hint) => anyhow!("row {}: invalid macro '{macro_}': {hint}", i + 1),
+            None => anyhow!("row {}: invalid macro '{macro_}'", i + 1),
+        })?;
+        layers = layers.max(layer);
+
+        let mut binding = Mapping::new();
+        binding.insert("key".into(), key.to_string().into());
+        binding.insert("layers".into(), Value::Sequence(vec![layer.into()]));
+        binding.insert("macro".into(), macro_.to_string().into());
+        bindings.push(Value::Mapping(binding));
+    }
+    ensure!(!bindings.is_empty(), "CSV has no bindings");
+
+    let mut root = Mapping::new();
+    root.insert("orientation".into(), "normal".into());
+    root.insert("model".into(), model.name.into());
+    root.insert("layers".into(), Value::Sequence(vec![Value::Mapping(Mapping::new()); layers as usize]));
+    root.insert("bindings".into(), Value::Sequence(bindings));
+
+    let config = serde_yaml::to_string(&root)?;
+    serde_yaml::from_str::<Config>(&config)?.render().context("imported config is invalid")?;
+    Ok(config)
+}
+
+/// Formats CSV row, fields are quoted when needed.
+fn row(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }).collect();
+    format!("{}\n", fields.join(","))
+}
+
+/// Parses CSV as written by spreadsheets: fields may be quoted with doubled
+/// quotes inside, lines end with LF or CRLF. Empty lines are skipped.
+fn parse(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|field| !field.is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    ensure!(!quoted, "CSV ends inside quoted field");
+    row.push(field);
+    if row.iter().any(|field| !field.is_empty()) {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{export, import, parse};
+    use crate::config::{Config, FlatLayer, Knob};
+    use crate::model::Model;
+
+    #[test]
+    fn parse_quoted_fields() {
+        assert_eq!(parse("1,k1,\"a,b\"\r\n\n2,k2,\"say \"\"hi\"\"\"").unwrap(),
+                   [vec!["1", "k1", "a,b"], vec!["2", "k2", "say \"hi\""]]);
+        assert!(parse("1,k1,\"a").is_err());
+    }
+
+    #[test]
+    fn export_and_import_bindings() {
+        let layers = [FlatLayer {
+            buttons: vec![Some("ctrl-c".parse().unwrap()), None, Some("a,b".parse().unwrap())],
+            knobs: vec![Knob { cw: Some("volumeup".parse().unwrap()), ..Knob::default() }],
+        }];
+        let csv = export(&layers);
+        assert_eq!(csv, indoc! {"
+            layer,key,macro
+            1,k1,ctrl-c
+            1,k3,\"a,b\"
+            1,knob1-cw,volumeup
+        "});
+
+        let config: Config = serde_yaml::from_str(&import(&csv, Model::find("minibar").unwrap()).unwrap()).unwrap();
+        let imported = config.render().unwrap();
+        assert_eq!(imported[0].buttons, layers[0].buttons);
+        assert_eq!(imported[0].knobs[0].cw, layers[0].knobs[0].cw);
+
+        assert!(import("1,k9,a\n", Model::find("minibar").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! Daemon performing host actions when keyboard sends their trigger chords.
+//!
+//! Daemon may be started as root to open keyboard input devices. When it's
+//! given user to run as, it forks helper keeping root privileges and drops
+//! them itself before any host action runs. Helper does only what needs
+//! root: opens input devices and sets up reconnected keyboard.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{ensure, Result};
+use log::{debug, error, info, warn};
+
+use crate::action::{HostAction, Sandbox};
+use crate::keyboard::Accord;
+use crate::listener::{ChordTracker, Debouncer, Listener, RateClassifier, Speed};
+
+use privileges::Helper;
+
+/// Knob is considered turned fast if its steps follow each other more often.
+const FAST_KNOB_INTERVAL: Duration = Duration::from_millis(80);
+/// How often input devices of keyboard are looked for once it's gone.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+/// Failed reconnection attempts after which missing keyboard is warned
+/// about, a minute of them.
+const RECONNECT_WARN_ATTEMPTS: u32 = 30;
+
+/// Runs host actions, as `user` if given. When keyboard is unplugged or
+/// resets, its input devices are opened again once it's back and
+/// `on_reconnect` is called.
+pub fn run(actions: Vec<(Accord, HostAction)>, mut debouncer: Debouncer, sandbox: &Sandbox, ids: &[(u16, u16)],
+           user: Option<&str>, on_reconnect: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+    ensure!(!actions.is_empty(), "config has no host actions, nothing to do");
+    for (trigger, action) in &actions {
+        info!("{trigger}: {action}");
+    }
+
+    // Privileges are dropped before listener starts its threads.
+    let mut helper = privileges::drop_to(user, ids, on_reconnect)?;
+    let mut listener = listen(&mut helper, ids)?;
+    let mut chords = ChordTracker::default();
+    let mut rates = RateClassifier::new(FAST_KNOB_INTERVAL);
+    loop {
+        let event = match listener.next_event() {
+            Ok(event) => event,
+            Err(err) => {
+                warn!("keyboard is gone ({err:#}), waiting for it to reconnect");
+                listener = reconnect(|| listen(&mut helper, ids), RECONNECT_INTERVAL, RECONNECT_WARN_ATTEMPTS);
+                info!("keyboard is reconnected");
+                chords = ChordTracker::default();
+                let set_up = match &mut helper {
+                    Some(helper) => helper.set_up(),
+                    None => on_reconnect(),
+                };
+                if let Err(err) = set_up {
+                    error!("reconnected keyboard isn't set up: {err:#}");
+                }
+                continue;
+            }
+        };
+        let Some(chord) = chords.feed(&event) else { continue };
+        let Some((_, action)) = actions.iter().find(|(trigger, _)| *trigger == chord) else { continue };
+        if !debouncer.accept(&chord, Instant::now()) {
+            debug!("{chord}: repeated too soon, ignored");
+            continue;
+        }
+
+        let action = match action {
+            HostAction::BySpeed { slow, fast } => match rates.classify(&chord, Instant::now()) {
+                Speed::Slow => slow,
+                Speed::Fast => fast,
+            },
+            action => action,
+        };
+        info!("{chord}: {action}");
+        if let Err(err) = action.run(sandbox) {
+            error!("{action} failed: {err:#}");
+        }
+    }
+}
+
+/// Opens input devices of keyboard, through helper if there is one.
+fn listen(helper: &mut Option<Helper>, ids: &[(u16, u16)]) -> Result<Listener> {
+    match helper {
+        Some(helper) => helper.listen(),
+        None => Listener::open(ids),
+    }
+}
+
+/// Calls `open` until it succeeds, waiting `interval` before each attempt.
+/// Failures are expected while keyboard is unplugged, they are warned about
+/// once there are `warn_attempts` of them in a row.
+fn reconnect<T>(mut open: impl FnMut() -> Result<T>, interval: Duration, warn_attempts: u32) -> T {
+    let mut last_error = String::new();
+    let mut attempts = 0;
+    loop {
+        std::thread::sleep(interval);
+        let err = match open() {
+            Ok(opened) => return opened,
+            Err(err) => format!("{err:#}"),
+        };
+        attempts += 1;
+        // Each error is logged once, it repeats until keyboard is back.
+        if attempts == warn_attempts {
+            warn!("keyboard isn't back after {attempts} attempts to reconnect: {err}");
+        } else if err != last_error {
+            if attempts > warn_attempts {
+                warn!("reconnect: {err}");
+            } else {
+                debug!("reconnect: {err}");
+            }
+        }
+        last_error = err;
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod privileges {
+    use std::ffi::{CStr, CString};
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd};
+
+    use anyhow::{anyhow, bail, ensure, Context as _, Result};
+    use log::info;
+
+    use crate::listener::Listener;
+    use crate::term;
+
+    /// Requests to helper.
+    const OPEN_DEVICES: u8 = 1;
+    const SET_UP: u8 = 2;
+    /// Keyboards have few input devices, more aren't passed.
+    const MAX_DEVICES: usize = 16;
+
+    /// Process keeping root privileges daemon has dropped.
+    pub struct Helper {
+        socket: OwnedFd,
+    }
+
+    impl Helper {
+        /// Starts listening to input devices of keyboard opened by helper.
+        pub fn listen(&mut self) -> Result<Listener> {
+            Ok(Listener::from_devices(self.request(OPEN_DEVICES)?))
+        }
+
+        /// Sets up reconnected keyboard.
+        pub fn set_up(&mut self) -> Result<()> {
+            self.request(SET_UP).map(drop)
+        }
+
+        fn request(&mut self, request: u8) -> Result<Vec<File>> {
+            send(&self.socket, &[request], &[]).context("send request to privileged helper")?;
+            let mut reply = [0; 1024];
+            let (len, devices) = receive(&self.socket, &mut reply).context("receive reply of privileged helper")?;
+            match reply[..len] {
+                [] => bail!("privileged helper has exited"),
+                [0] => Ok(devices),
+                [_, ref message @ ..] => Err(anyhow!("{}", String::from_utf8_lossy(message))),
+            }
+        }
+    }
+
+    /// Switches process to given user. If process runs as root, helper
+    /// keeping root is forked first and returned.
+    ///
+    /// Must be called before any thread is started: only calling thread is
+    /// forked, and environment is changed.
+    pub fn drop_to(user: Option<&str>, ids: &[(u16, u16)],
+                   on_reconnect: &mut dyn FnMut() -> Result<()>) -> Result<Option<Helper>> {
+        let is_root = unsafe { libc::geteuid() } == 0;
+        let Some(user) = user else {
+            if is_root {
+                term::warning("host actions run as root, pass --user to run them as another user");
+            }
+            return Ok(None);
+        };
+
+        let name = CString::new(user)?;
+        let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+        ensure!(!passwd.is_null(), "user '{user}' doesn't exist");
+        let (uid, gid, home) = unsafe {
+            ((*passwd).pw_uid, (*passwd).pw_gid, CStr::from_ptr((*passwd).pw_dir).to_string_lossy().into_owned())
+        };
+        if !is_root {
+            ensure!(uid == unsafe { libc::geteuid() }, "daemon must be started as root to run as user '{user}'");
+            return Ok(None);
+        }
+
+        let helper = fork_helper(ids, on_reconnect)?;
+        // Groups can't be changed once user is switched.
+        ensure!(unsafe { libc::initgroups(name.as_ptr(), gid) } == 0, "set groups of '{user}': {}", io::Error::last_os_error());
+        ensure!(unsafe { libc::setgid(gid) } == 0, "switch to group of '{user}': {}", io::Error::last_os_error());
+        ensure!(unsafe { libc::setuid(uid) } == 0, "switch to user '{user}': {}", io::Error::last_os_error());
+        ensure!(unsafe { libc::setuid(0) } != 0, "root privileges are still held after switching to '{user}'");
+
+        // Host actions expect environment of user they run as.
+        std::env::set_var("HOME", home);
+        std::env::set_var("USER", user);
+        std::env::set_var("LOGNAME", user);
+        info!("running as {user}, keyboard is opened by privileged helper");
+        Ok(Some(helper))
+    }
+
+    fn fork_helper(ids: &[(u16, u16)], on_reconnect: &mut dyn FnMut() -> Result<()>) -> Result<Helper> {
+        let mut fds = [0; 2];
+        let res = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) };
+        ensure!(res == 0, "create socket for privileged helper: {}", io::Error::last_os_error());
+        let (daemon, helper) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        match unsafe { libc::fork() } {
+            -1 => bail!("start privileged helper: {}", io::Error::last_os_error()),
+            0 => {
+                drop(daemon);
+                serve(&helper, ids, on_reconnect);
+                // Nothing of daemon is run in helper, not even exit handlers.
+                unsafe { libc::_exit(0) }
+            }
+            _ => Ok(Helper { socket: daemon }),
+        }
+    }
+
+    /// Serves requests of daemon until it exits.
+    fn serve(socket: &OwnedFd, ids: &[(u16, u16)], on_reconnect: &mut dyn FnMut() -> Result<()>) {
+        let mut request = [0; 1];
+        while let Ok((1, _)) = receive(socket, &mut request) {
+            let (result, mut devices) = match request[0] {
+                OPEN_DEVICES => match Listener::open_devices(ids) {
+                    Ok(devices) => (Ok(()), devices),
+                    Err(err) => (Err(err), vec![]),
+                },
+                _ => (on_reconnect(), vec![]),
+            };
+            devices.truncate(MAX_DEVICES);
+            let reply = match result {
+                Ok(()) => vec![0],
+                Err(err) => [&[1], format!("{err:#}").as_bytes()].concat(),
+            };
+            let fds: Vec<RawFd> = devices.iter().map(File::as_raw_fd).collect();
+            if send(socket, &reply, &fds).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Sends message with given file descriptors attached.
+    fn send(socket: &OwnedFd, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+        let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut _, iov_len: data.len() };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        let fds_len = std::mem::size_of_val(fds) as libc::c_uint;
+        let space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+        // `u64` keeps control header aligned.
+        let mut control = vec![0u64; space.div_ceil(8)];
+        if !fds.is_empty() {
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = space as _;
+            unsafe {
+                let header = libc::CMSG_FIRSTHDR(&msg);
+                (*header).cmsg_level = libc::SOL_SOCKET;
+                (*header).cmsg_type = libc::SCM_RIGHTS;
+                (*header).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+                std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(header).cast(), fds.len());
+            }
+        }
+        if unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Receives message into buffer, returns its length, zero once other
+    /// side is closed, and files attached to it.
+    fn receive(socket: &OwnedFd, buf: &mut [u8]) -> io::Result<(usize, Vec<File>)> {
+        let mut iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        let space = unsafe { libc::CMSG_SPACE(std::mem::size_of::<[RawFd; MAX_DEVICES]>() as libc::c_uint) } as usize;
+        let mut control = vec![0u64; space.div_ceil(8)];
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+        let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut files = vec![];
+        unsafe {
+            let mut header = libc::CMSG_FIRSTHDR(&msg);
+            while !header.is_null() {
+                if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(header).cast::<RawFd>();
+                    let count = ((*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+                    for i in 0..count {
+                        files.push(File::from_raw_fd(data.add(i).read_unaligned()));
+                    }
+                }
+                header = libc::CMSG_NXTHDR(&msg, header);
+            }
+        }
+        Ok((len as usize, files))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::io::{Read as _, Seek as _, Write as _};
+        use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd};
+
+        use super::{receive, send};
+
+        #[test]
+        fn pass_files() {
+            let mut fds = [0; 2];
+            assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) }, 0);
+            let (daemon, helper) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+            let path = std::env::temp_dir().join(format!("ch57x-helper-{}", std::process::id()));
+            let mut file = std::fs::File::options().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            file.write_all(b"event").unwrap();
+            send(&helper, &[0], &[file.as_raw_fd()]).unwrap();
+
+            let mut buf = [0; 16];
+            let (len, mut files) = receive(&daemon, &mut buf).unwrap();
+            assert_eq!((len, files.len()), (1, 1));
+            let mut text = String::new();
+            files[0].rewind().unwrap();
+            files[0].read_to_string(&mut text).unwrap();
+            assert_eq!(text, "event");
+
+            drop(helper);
+            assert_eq!(receive(&daemon, &mut buf).unwrap().0, 0);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod privileges {
+    use anyhow::{ensure, Result};
+
+    use crate::listener::Listener;
+
+    /// Never exists, running as another user is only supported on Linux.
+    pub enum Helper {}
+
+    impl Helper {
+        pub fn listen(&mut self) -> Result<Listener> {
+            match *self {}
+        }
+
+        pub fn set_up(&mut self) -> Result<()> {
+            match *self {}
+        }
+    }
+
+    pub fn drop_to(user: Option<&str>, _ids: &[(u16, u16)],
+                   _on_reconnect: &mut dyn FnMut() -> Result<()>) -> Result<Option<Helper>> {
+        ensure!(user.is_none(), "running daemon as another user is only supported on Linux");
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::bail;
+
+    use super::reconnect;
+
+    #[test]
+    fn reconnect_after_failures() {
+        let mut attempts = 0;
+        let opened = reconnect(|| {
+            attempts += 1;
+            if attempts < 4 {
+                bail!("no input devices of keyboard found, is it connected?");
+            }
+            Ok(attempts)
+        }, Duration::ZERO, 2);
+        assert_eq!(opened, 4);
+    }
+}
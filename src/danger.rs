@@ -0,0 +1,73 @@
+//! Bindings which do harm when key is pressed by accident, like closing
+//! window or powering computer off. Upload refuses them unless allowed.
+
+use std::fmt::Display;
+
+use crate::config::FlatLayer;
+use crate::keyboard::{Accord, Code, Key, KeyboardPart, Macro, Modifier, WellKnownCode};
+
+/// Key bound to dangerous macro.
+#[derive(Debug)]
+pub struct Dangerous {
+    /// Zero-based layer index.
+    pub layer: usize,
+    pub key: Key,
+    pub macro_: Macro,
+    pub reason: &'static str,
+}
+
+impl Display for Dangerous {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on layer {} is bound to '{}', which {}", self.key, self.layer + 1, self.macro_, self.reason)
+    }
+}
+
+/// Tells what chord of macro does if it's dangerous.
+pub fn reason(macro_: &Macro) -> Option<&'static str> {
+    let Macro::Keyboard(parts) = macro_ else { return None };
+    parts.iter().find_map(|part| match part {
+        KeyboardPart::Key(accord) => accord_reason(accord),
+        _ => None,
+    })
+}
+
+fn accord_reason(accord: &Accord) -> Option<&'static str> {
+    let Some(Code::WellKnown(code)) = accord.code else { return None };
+    let ctrl = accord.modifiers.contains(Modifier::Ctrl) || accord.modifiers.contains(Modifier::RightCtrl);
+    let alt = accord.modifiers.contains(Modifier::Alt) || accord.modifiers.contains(Modifier::RightAlt);
+    match code {
+        WellKnownCode::Power => Some("powers computer off"),
+        WellKnownCode::Delete if ctrl && alt => Some("opens security screen or restarts computer"),
+        WellKnownCode::Backspace if ctrl && alt => Some("may kill graphical session"),
+        WellKnownCode::F4 if alt => Some("closes window"),
+        _ => None,
+    }
+}
+
+/// Finds dangerous bindings of all layers.
+pub fn find(layers: &[FlatLayer]) -> Vec<Dangerous> {
+    layers.iter().enumerate().flat_map(|(layer, flat)| {
+        flat.bindings()
+            .filter_map(|(key, macro_)| reason(macro_).map(|reason| Dangerous { layer, key, macro_: macro_.clone(), reason }))
+            .collect::<Vec<_>>()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, reason};
+    use crate::config::FlatLayer;
+
+    #[test]
+    fn find_dangerous_bindings() {
+        assert_eq!(reason(&"alt-f4".parse().unwrap()), Some("closes window"));
+        assert_eq!(reason(&"a,ctrl-alt-delete".parse().unwrap()), Some("opens security screen or restarts computer"));
+        assert_eq!(reason(&"power".parse().unwrap()), Some("powers computer off"));
+        assert_eq!(reason(&"f4".parse().unwrap()), None);
+        assert_eq!(reason(&"ctrl-delete".parse().unwrap()), None);
+
+        let layers = [FlatLayer { buttons: vec![Some("ctrl-c".parse().unwrap()), Some("power".parse().unwrap())], knobs: vec![] }];
+        let found: Vec<String> = find(&layers).iter().map(ToString::to_string).collect();
+        assert_eq!(found, ["button 1 on layer 1 is bound to 'power', which powers computer off"]);
+    }
+}
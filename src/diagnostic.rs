@@ -0,0 +1,113 @@
+//! Validation results in machine-readable form, so frontends may highlight
+//! problems in config instead of parsing error text.
+
+use anyhow::{Context as _, Result};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::danger::{self, Dangerous};
+use crate::keyboard::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Where in config problem is, like `layers[0].buttons[1]`. Keys of
+    /// rendered layers are named as in `bindings`: `layers[0].k2`.
+    pub path: Option<String>,
+    /// One-based position in config file, if known.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    /// What may be done about problem.
+    pub fix: Option<String>,
+}
+
+impl Diagnostic {
+    /// Describes error stopping validation, position is known for YAML errors.
+    pub fn error(err: &anyhow::Error) -> Self {
+        let yaml = err.chain().find_map(|cause| cause.downcast_ref::<serde_yaml::Error>());
+        let location = yaml.and_then(serde_yaml::Error::location);
+        Diagnostic {
+            severity: Severity::Error,
+            path: yaml.and_then(|yaml| yaml_path(&yaml.to_string())),
+            line: location.as_ref().map(|location| location.line()),
+            column: location.as_ref().map(|location| location.column()),
+            message: format!("{err:#}"),
+            fix: None,
+        }
+    }
+}
+
+impl From<Dangerous> for Diagnostic {
+    fn from(dangerous: Dangerous) -> Self {
+        let key = match dangerous.key {
+            Key::Button(n) => format!("k{}", n + 1),
+            Key::Knob(n, action) => format!("knob{}-{action}", n + 1),
+        };
+        Diagnostic {
+            severity: Severity::Warning,
+            path: Some(format!("layers[{}].{key}", dangerous.layer)),
+            line: None,
+            column: None,
+            message: dangerous.to_string(),
+            fix: Some("bind other key, or upload with --allow-dangerous if it's intended".to_owned()),
+        }
+    }
+}
+
+/// Validates loaded config. Validation stops at first error, so at most
+/// one error is listed, warnings are only given for valid config.
+pub fn check(config: Result<Config>) -> Vec<Diagnostic> {
+    match config.and_then(|config| config.render().context("render mapping config")) {
+        Ok(layers) => danger::find(&layers).into_iter().map(Diagnostic::from).collect(),
+        Err(err) => vec![Diagnostic::error(&err)],
+    }
+}
+
+/// Takes path serde_yaml puts before message: `layers[0].buttons: invalid type`.
+fn yaml_path(message: &str) -> Option<String> {
+    let (path, _) = message.split_once(": ")?;
+    let is_path = path.chars().all(|c| c.is_ascii_alphanumeric() || "_-.[]".contains(c));
+    is_path.then(|| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context as _;
+
+    use super::{check, Diagnostic, Severity};
+    use crate::config::Config;
+
+    fn diagnose(yaml: &str) -> Vec<Diagnostic> {
+        check(serde_yaml::from_str::<Config>(yaml).context("load mapping config"))
+    }
+
+    #[test]
+    fn yaml_error_position() {
+        let diagnostics = diagnose("orientation: normal\nrows: 1\ncolumns: 2\nknobs: 0\nlayers:\n  - buttons: [[a, nosuchkey]]\n");
+        let [diagnostic] = diagnostics.as_slice() else { panic!("one diagnostic expected: {diagnostics:?}") };
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.path.as_deref(), Some("layers[0].buttons[0][1]"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(6), Some(19)));
+    }
+
+    #[test]
+    fn render_error_and_warning() {
+        let diagnostics = diagnose("orientation: normal\nrows: 1\ncolumns: 2\nknobs: 0\nlayers:\n  - buttons: [[a]]\n");
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!((diagnostics[0].path.as_ref(), diagnostics[0].line), (None, None));
+
+        let diagnostics = diagnose("orientation: normal\nrows: 1\ncolumns: 2\nknobs: 0\nlayers:\n  - buttons: [[a, power]]\n");
+        let [diagnostic] = diagnostics.as_slice() else { panic!("one diagnostic expected: {diagnostics:?}") };
+        assert_eq!(diagnostic.severity, Severity::Warning);
+        assert_eq!(diagnostic.path.as_deref(), Some("layers[0].k2"));
+        assert!(diagnostic.fix.is_some());
+    }
+}
@@ -0,0 +1,220 @@
+//! Compares rendered configs and shows differences as grid of keys per layer.
+
+use std::fmt::Write as _;
+
+use crate::config::{FlatLayer, Knob};
+use crate::keyboard::{DisplayStyle, Macro, StyledDisplay as _};
+use crate::term::{self, Style};
+
+/// Maximum width of cell contents, longer macros are truncated.
+const MAX_CELL_WIDTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Same,
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Change {
+    fn of(old: Option<&Macro>, new: Option<&Macro>) -> Self {
+        match (old, new) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Removed,
+            (Some(old), Some(new)) if old != new => Change::Changed,
+            _ => Change::Same,
+        }
+    }
+
+    fn marker(self) -> char {
+        match self {
+            Change::Same => ' ',
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Changed => '~',
+        }
+    }
+
+    fn style(self) -> Option<Style> {
+        match self {
+            Change::Same => None,
+            Change::Added => Some(Style::Added),
+            Change::Removed => Some(Style::Removed),
+            Change::Changed => Some(Style::Changed),
+        }
+    }
+}
+
+struct Cell {
+    change: Change,
+    text: String,
+}
+
+impl Cell {
+    fn new(old: Option<&Macro>, new: Option<&Macro>, style: DisplayStyle) -> Self {
+        let change = Change::of(old, new);
+        // Removed macro is shown as it was.
+        let text = match new.or(old) {
+            Some(macro_) => macro_.display(style).to_string(),
+            None => "·".to_owned(),
+        };
+        Self { change, text }
+    }
+}
+
+/// Renders differences between layers, `columns` is number of buttons in
+/// row as keyboard is held in normal orientation. Layers without changes
+/// are mentioned in single line.
+pub fn render(old: &[FlatLayer], new: &[FlatLayer], columns: usize, style: DisplayStyle, color: bool) -> String {
+    let empty = FlatLayer { buttons: vec![], knobs: vec![] };
+    let mut out = String::new();
+    let mut changed_keys = 0;
+    let mut changed_layers = 0;
+
+    for i in 0..old.len().max(new.len()) {
+        let old = old.get(i).unwrap_or(&empty);
+        let new = new.get(i).unwrap_or(&empty);
+
+        let buttons: Vec<Cell> = (0..old.buttons.len().max(new.buttons.len()))
+            .map(|j| Cell::new(flatten(old.buttons.get(j)), flatten(new.buttons.get(j)), style))
+            .collect();
+        let knobs: Vec<[Cell; 3]> = (0..old.knobs.len().max(new.knobs.len()))
+            .map(|j| knob_cells(old.knobs.get(j), new.knobs.get(j), style))
+            .collect();
+
+        let changes = buttons.iter().chain(knobs.iter().flatten())
+            .filter(|cell| cell.change != Change::Same)
+            .count();
+        if changes == 0 {
+            writeln!(out, "Layer {}: no changes", i + 1).unwrap();
+            continue;
+        }
+        changed_keys += changes;
+        changed_layers += 1;
+
+        writeln!(out, "Layer {}:", i + 1).unwrap();
+        let width = buttons.iter().chain(knobs.iter().flatten())
+            .map(|cell| cell.text.chars().count())
+            .max().unwrap_or(0)
+            .min(MAX_CELL_WIDTH);
+        for row in buttons.chunks(columns.max(1)) {
+            write_row(&mut out, "   ", row.iter(), width, color);
+        }
+        for (j, cells) in knobs.iter().enumerate() {
+            write_row(&mut out, &format!("  knob {}:", j + 1), cells.iter(), width, color);
+        }
+    }
+
+    writeln!(out, "{changed_keys} key(s) changed in {changed_layers} layer(s)").unwrap();
+    out
+}
+
+/// Returns layers with only keys which differ from `old` bound, so just
+/// they may be uploaded. Keys unbound in `new` are bound to disabled macro,
+/// since keyboard would keep old binding otherwise.
+pub fn changes(old: &[FlatLayer], new: &[FlatLayer]) -> Vec<FlatLayer> {
+    let empty = FlatLayer { buttons: vec![], knobs: vec![] };
+    let change = |old: Option<&Macro>, new: Option<&Macro>| match Change::of(old, new) {
+        Change::Same => None,
+        Change::Removed => Some(Macro::Keyboard(vec![])),
+        Change::Added | Change::Changed => new.cloned(),
+    };
+    (0..old.len().max(new.len())).map(|i| {
+        let old = old.get(i).unwrap_or(&empty);
+        let new = new.get(i).unwrap_or(&empty);
+        let buttons = (0..old.buttons.len().max(new.buttons.len()))
+            .map(|j| change(flatten(old.buttons.get(j)), flatten(new.buttons.get(j))))
+            .collect();
+        let knobs = (0..old.knobs.len().max(new.knobs.len())).map(|j| {
+            let (old, new) = (knob_actions(old.knobs.get(j)), knob_actions(new.knobs.get(j)));
+            Knob { ccw: change(old[0], new[0]), press: change(old[1], new[1]), cw: change(old[2], new[2]) }
+        }).collect();
+        FlatLayer { buttons, knobs }
+    }).collect()
+}
+
+fn flatten(macro_: Option<&Option<Macro>>) -> Option<&Macro> {
+    macro_.and_then(Option::as_ref)
+}
+
+fn knob_cells(old: Option<&Knob>, new: Option<&Knob>, style: DisplayStyle) -> [Cell; 3] {
+    let (old, new) = (knob_actions(old), knob_actions(new));
+    [0, 1, 2].map(|j| Cell::new(old[j], new[j], style))
+}
+
+fn knob_actions(knob: Option<&Knob>) -> [Option<&Macro>; 3] {
+    match knob {
+        Some(knob) => [knob.ccw.as_ref(), knob.press.as_ref(), knob.cw.as_ref()],
+        None => [None; 3],
+    }
+}
+
+fn write_row<'a>(out: &mut String, prefix: &str, cells: impl Iterator<Item = &'a Cell>, width: usize, color: bool) {
+    out.push_str(prefix);
+    for cell in cells {
+        let mut text: String = cell.text.chars().take(width).collect();
+        if cell.text.chars().count() > width {
+            text.pop();
+            text.push('…');
+        }
+        let padding = width - text.chars().count();
+        let marker = cell.change.marker();
+        match cell.change.style() {
+            Some(style) => write!(out, " {}{:padding$}", term::paint(format!("{marker}{text}"), style, color), "").unwrap(),
+            None => write!(out, " {marker}{text}{:padding$}", "").unwrap(),
+        }
+    }
+    // Padding of last cell isn't needed.
+    let trimmed = out.trim_end_matches(' ').len();
+    out.truncate(trimmed);
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{changes, render};
+    use crate::config::{FlatLayer, Knob};
+    use crate::keyboard::DisplayStyle;
+
+    fn layer(buttons: &[&str], knob: [&str; 3]) -> FlatLayer {
+        let parse = |s: &str| (!s.is_empty()).then(|| s.parse().unwrap());
+        FlatLayer {
+            buttons: buttons.iter().map(|s| parse(s)).collect(),
+            knobs: vec![Knob { ccw: parse(knob[0]), press: parse(knob[1]), cw: parse(knob[2]) }],
+        }
+    }
+
+    #[test]
+    fn render_grid_diff() {
+        let old = [
+            layer(&["a", "b", "c", "d"], ["volumedown", "mute", "volumeup"]),
+            layer(&["a", "", "", ""], ["", "", ""]),
+        ];
+        let new = [
+            layer(&["a", "ctrl-c", "", "d"], ["volumedown", "play", "volumeup"]),
+            layer(&["a", "", "", ""], ["", "", ""]),
+        ];
+        assert_eq!(render(&old, &new, 2, DisplayStyle::Text, false), indoc! {"
+            Layer 1:
+                 a          ~ctrl-c
+                -c           d
+              knob 1:  volumedown ~play        volumeup
+            Layer 2: no changes
+            3 key(s) changed in 1 layer(s)
+        "});
+    }
+
+    #[test]
+    fn changed_keys_only() {
+        let old = [layer(&["a", "b", "c"], ["volumedown", "mute", "volumeup"]), layer(&["x", "", ""], ["", "", ""])];
+        let new = [layer(&["a", "ctrl-c", ""], ["volumedown", "play", "volumeup"])];
+        assert_eq!(changes(&old, &new), [
+            layer(&["", "ctrl-c", "disabled"], ["", "play", ""]),
+            layer(&["disabled", "", ""], ["", "", ""]),
+        ]);
+        assert!(changes(&new, &new)[0].bindings().next().is_none());
+    }
+}
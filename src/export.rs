@@ -0,0 +1,235 @@
+//! Exports chords programmed into keyboard as stubs of host automation
+//! scripts, so they can be quickly wired to actions, or all bindings as CSV.
+
+use std::fmt::Write as _;
+
+use clap::ValueEnum;
+
+use crate::config::FlatLayer;
+use crate::csv;
+use crate::keyboard::{Accord, Code, KeyboardPart, Macro, Modifier, WellKnownCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// AutoHotkey v2 script
+    Ahk,
+    /// Hammerspoon Lua config
+    Hammerspoon,
+    /// Bindings as `layer,key,macro` rows, see `import`
+    Csv,
+}
+
+/// Renders script with one stub per key bound to single chord. Other
+/// macros can't be caught as hotkey and are listed in comments.
+pub fn export(layers: &[FlatLayer], format: Format) -> String {
+    let comment = match format {
+        Format::Ahk => ";",
+        Format::Hammerspoon => "--",
+        Format::Csv => return csv::export(layers),
+    };
+    let mut out = String::new();
+    writeln!(out, "{comment} Chords sent by keyboard keys, generated by ch57x-keyboard-tool.").unwrap();
+    if format == Format::Ahk {
+        writeln!(out, "#Requires AutoHotkey v2.0").unwrap();
+    }
+
+    for (i, layer) in layers.iter().enumerate() {
+        let buttons = layer.buttons.iter().enumerate()
+            .map(|(j, macro_)| (format!("button {}", j + 1), macro_));
+        let knobs = layer.knobs.iter().enumerate().flat_map(|(j, knob)| {
+            [("ccw", &knob.ccw), ("press", &knob.press), ("cw", &knob.cw)]
+                .map(|(action, macro_)| (format!("knob{}-{action}", j + 1), macro_))
+        });
+        for (key, macro_) in buttons.chain(knobs) {
+            let Some(macro_) = macro_ else { continue };
+            writeln!(out).unwrap();
+            writeln!(out, "{comment} Layer {}, {key}: {macro_}", i + 1).unwrap();
+            let stub = single_chord(macro_).and_then(|accord| match format {
+                Format::Ahk => ahk_stub(accord),
+                Format::Hammerspoon => hammerspoon_stub(accord),
+                Format::Csv => unreachable!("CSV is rendered above"),
+            });
+            match stub {
+                Some(stub) => out.push_str(&stub),
+                None => writeln!(out, "{comment} can't be used as hotkey").unwrap(),
+            }
+        }
+    }
+    out
+}
+
+fn single_chord(macro_: &Macro) -> Option<Accord> {
+    match macro_ {
+        Macro::Keyboard(parts) => match parts.as_slice() {
+            [KeyboardPart::Key(accord)] if accord.code.is_some() => Some(*accord),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn ahk_stub(accord: Accord) -> Option<String> {
+    let Some(Code::WellKnown(code)) = accord.code else { return None };
+    let key = ahk_key(code)?;
+    let modifiers: String = accord.modifiers.iter().map(|m| match m {
+        Modifier::Ctrl => "^",
+        Modifier::Shift => "+",
+        Modifier::Alt => "!",
+        Modifier::Win => "#",
+        Modifier::RightCtrl => ">^",
+        Modifier::RightShift => ">+",
+        Modifier::RightAlt => ">!",
+        Modifier::RightWin => ">#",
+    }).collect();
+    Some(format!("{modifiers}{key}:: {{\n    ; TODO\n}}\n"))
+}
+
+fn hammerspoon_stub(accord: Accord) -> Option<String> {
+    let Some(Code::WellKnown(code)) = accord.code else { return None };
+    let key = hammerspoon_key(code)?;
+    // Hammerspoon doesn't tell left and right modifiers apart.
+    let mut modifiers: Vec<&str> = accord.modifiers.iter().map(|m| match m {
+        Modifier::Ctrl | Modifier::RightCtrl => "\"ctrl\"",
+        Modifier::Shift | Modifier::RightShift => "\"shift\"",
+        Modifier::Alt | Modifier::RightAlt => "\"alt\"",
+        Modifier::Win | Modifier::RightWin => "\"cmd\"",
+    }).collect();
+    modifiers.sort();
+    modifiers.dedup();
+    Some(format!("hs.hotkey.bind({{{}}}, \"{key}\", function()\n  -- TODO\nend)\n", modifiers.join(", ")))
+}
+
+fn ahk_key(code: WellKnownCode) -> Option<String> {
+    use WellKnownCode::*;
+    let key = match code {
+        Enter => "Enter",
+        Escape => "Escape",
+        Backspace => "Backspace",
+        Tab => "Tab",
+        Space => "Space",
+        Minus => "-",
+        Equal => "=",
+        LeftBracket => "[",
+        RightBracket => "]",
+        Backslash => "\\",
+        Semicolon => "`;",
+        Quote => "'",
+        Grave => "``",
+        Comma => ",",
+        Dot => ".",
+        Slash => "/",
+        CapsLock => "CapsLock",
+        PrintScreen => "PrintScreen",
+        ScrollLock => "ScrollLock",
+        Pause => "Pause",
+        Insert => "Insert",
+        Home => "Home",
+        PageUp => "PgUp",
+        Delete => "Delete",
+        End => "End",
+        PageDown => "PgDn",
+        Right => "Right",
+        Left => "Left",
+        Down => "Down",
+        Up => "Up",
+        NumLock => "NumLock",
+        NumPadSlash => "NumpadDiv",
+        NumPadAsterisk => "NumpadMult",
+        NumPadMinus => "NumpadSub",
+        NumPadPlus => "NumpadAdd",
+        NumPadEnter => "NumpadEnter",
+        NumPadDot => "NumpadDot",
+        Application => "AppsKey",
+        NonUSHash | NonUSBackslash | Power | NumPadEqual => return None,
+        NumPad1 | NumPad2 | NumPad3 | NumPad4 | NumPad5 | NumPad6 | NumPad7 | NumPad8 | NumPad9 | NumPad0 =>
+            return Some(format!("Numpad{}", numpad_digit(code))),
+        // Letters, digits and function keys are named the same way.
+        _ => return Some(code.to_string().to_uppercase()),
+    };
+    Some(key.to_owned())
+}
+
+fn hammerspoon_key(code: WellKnownCode) -> Option<String> {
+    use WellKnownCode::*;
+    let key = match code {
+        Enter => "return",
+        Escape => "escape",
+        Backspace => "delete",
+        Tab => "tab",
+        Space => "space",
+        Minus => "-",
+        Equal => "=",
+        LeftBracket => "[",
+        RightBracket => "]",
+        Backslash => "\\\\",
+        Semicolon => ";",
+        Quote => "'",
+        Grave => "`",
+        Comma => ",",
+        Dot => ".",
+        Slash => "/",
+        CapsLock => "capslock",
+        Home => "home",
+        PageUp => "pageup",
+        Delete => "forwarddelete",
+        End => "end",
+        PageDown => "pagedown",
+        Right => "right",
+        Left => "left",
+        Down => "down",
+        Up => "up",
+        NumPadSlash => "pad/",
+        NumPadAsterisk => "pad*",
+        NumPadMinus => "pad-",
+        NumPadPlus => "pad+",
+        NumPadEnter => "padenter",
+        NumPadDot => "pad.",
+        NumPadEqual => "pad=",
+        NumPad1 | NumPad2 | NumPad3 | NumPad4 | NumPad5 | NumPad6 | NumPad7 | NumPad8 | NumPad9 | NumPad0 =>
+            return Some(format!("pad{}", numpad_digit(code))),
+        // macOS has no such keys.
+        F21 | F22 | F23 | F24 | PrintScreen | ScrollLock | Pause | Insert | NumLock
+            | NonUSHash | NonUSBackslash | Application | Power => return None,
+        _ => return Some(code.to_string()),
+    };
+    Some(key.to_owned())
+}
+
+fn numpad_digit(code: WellKnownCode) -> u8 {
+    // Numpad keys go from 1 to 9 and then 0.
+    (code as u8 - WellKnownCode::NumPad1 as u8 + 1) % 10
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{export, Format};
+    use crate::config::{FlatLayer, Knob};
+
+    #[test]
+    fn export_stubs() {
+        let layers = [FlatLayer {
+            buttons: vec![Some("ctrl-shift-f13".parse().unwrap()), Some("a,b".parse().unwrap()), None],
+            knobs: vec![Knob { press: Some("rctrl-numpad0".parse().unwrap()), ..Knob::default() }],
+        }];
+        assert_eq!(export(&layers, Format::Ahk), indoc! {"
+            ; Chords sent by keyboard keys, generated by ch57x-keyboard-tool.
+            #Requires AutoHotkey v2.0
+
+            ; Layer 1, button 1: ctrl-shift-f13
+            ^+F13:: {
+                ; TODO
+            }
+
+            ; Layer 1, button 2: a,b
+            ; can't be used as hotkey
+
+            ; Layer 1, knob1-press: rctrl-numpad0
+            >^Numpad0:: {
+                ; TODO
+            }
+        "});
+        assert_eq!(export(&layers[..], Format::Hammerspoon).lines().nth(3), Some("hs.hotkey.bind({\"ctrl\", \"shift\"}, \"f13\", function()"));
+    }
+}
@@ -0,0 +1,115 @@
+//! Journal of upload in progress, so upload killed midway may be resumed
+//! instead of started over.
+//!
+//! Journal is named after keyboard and starts with fingerprint of uploaded
+//! layers, each completed binding is appended as `<layer> <key>` line. It
+//! is removed once every binding is uploaded.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash as _, Hasher as _};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+
+use crate::config::FlatLayer;
+use crate::keyboard::Key;
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    done: HashSet<String>,
+}
+
+/// Identifies uploaded layers, journal of other config isn't resumed.
+pub fn fingerprint(layers: &[FlatLayer]) -> String {
+    let mut hasher = DefaultHasher::new();
+    format!("{layers:?}").hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Journal file of keyboard identified by serial number or, if it has
+/// none, by product ID and USB address.
+pub fn path(dir: &Path, device: &str) -> PathBuf {
+    let name: String = device.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    dir.join(format!("upload-{name}.journal"))
+}
+
+fn line(layer: usize, key: Key) -> String {
+    format!("{layer} {key}")
+}
+
+impl Journal {
+    /// Reads bindings completed by interrupted upload of the same layers,
+    /// `None` if there is no such journal.
+    pub fn interrupted(path: &Path, fingerprint: &str) -> Option<HashSet<String>> {
+        let text = std::fs::read_to_string(path).ok()?;
+        let mut lines = text.lines();
+        (lines.next() == Some(fingerprint)).then(|| lines.map(str::to_owned).collect())
+    }
+
+    /// Starts journal, continuing given completed bindings.
+    pub fn start(path: &Path, fingerprint: &str, done: HashSet<String>) -> Result<Journal> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        }
+        let mut text = format!("{fingerprint}\n");
+        for line in &done {
+            text.push_str(line);
+            text.push('\n');
+        }
+        std::fs::write(path, text).with_context(|| format!("write {}", path.display()))?;
+        let file = OpenOptions::new().append(true).open(path).with_context(|| format!("open {}", path.display()))?;
+        Ok(Journal { path: path.to_owned(), file, done })
+    }
+
+    /// Whether binding was completed before upload was resumed.
+    pub fn is_done(&self, layer: usize, key: Key) -> bool {
+        self.done.contains(&line(layer, key))
+    }
+
+    /// Appends completed binding, it's flushed at once as process may be
+    /// killed any moment.
+    pub fn record(&mut self, layer: usize, key: Key) -> Result<()> {
+        writeln!(self.file, "{}", line(layer, key))?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Removes journal of completed upload.
+    pub fn finish(self) -> Result<()> {
+        std::fs::remove_file(&self.path).with_context(|| format!("remove {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{path, Journal};
+    use crate::keyboard::{Key, KnobAction};
+
+    #[test]
+    fn resume_interrupted_upload() {
+        let dir = std::env::temp_dir().join(format!("ch57x-journal-{}", std::process::id()));
+        let path = path(&dir, "8840 1:7");
+        assert!(path.ends_with("upload-8840-1-7.journal"));
+
+        let mut journal = Journal::start(&path, "abc", HashSet::new()).unwrap();
+        journal.record(0, Key::Button(1)).unwrap();
+        journal.record(1, Key::Knob(0, KnobAction::Press)).unwrap();
+        drop(journal);
+
+        assert_eq!(Journal::interrupted(&path, "other"), None);
+        let done = Journal::interrupted(&path, "abc").unwrap();
+        assert_eq!(done.len(), 2);
+        let journal = Journal::start(&path, "abc", done).unwrap();
+        assert!(journal.is_done(1, Key::Knob(0, KnobAction::Press)));
+        assert!(!journal.is_done(0, Key::Button(0)));
+        journal.finish().unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
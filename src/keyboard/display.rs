@@ -0,0 +1,187 @@
+//! Rendering macros for humans in different styles.
+//!
+//! Unlike `Display` implementations, which print macros so they can be
+//! parsed back, styled rendering normalizes modifier names and may use
+//! platform-native symbols.
+
+use std::fmt::{Display, Formatter, Result};
+
+use strum::EnumMessage as _;
+use strum_macros::{Display, EnumString};
+
+use super::{Accord, Code, KeyboardPart, Macro, Modifier, WellKnownCode};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum DisplayStyle {
+    /// Config syntax with canonical modifier names: `ctrl-shift-c`.
+    #[default]
+    Text,
+    /// macOS menu style: `⌃⇧C`.
+    Mac,
+}
+
+pub trait StyledDisplay {
+    fn fmt_styled(&self, f: &mut Formatter<'_>, style: DisplayStyle) -> Result;
+
+    fn display(&self, style: DisplayStyle) -> Styled<'_, Self> {
+        Styled(self, style)
+    }
+}
+
+/// Value rendered with given style, see `StyledDisplay::display`.
+pub struct Styled<'a, T: ?Sized>(&'a T, DisplayStyle);
+
+impl<T: StyledDisplay + ?Sized> Display for Styled<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.0.fmt_styled(f, self.1)
+    }
+}
+
+impl StyledDisplay for Modifier {
+    fn fmt_styled(&self, f: &mut Formatter<'_>, style: DisplayStyle) -> Result {
+        match style {
+            // First serialization is canonical one.
+            DisplayStyle::Text => write!(f, "{}", self.get_serializations()[0]),
+            DisplayStyle::Mac => write!(f, "{}", match self {
+                Modifier::Ctrl | Modifier::RightCtrl => '⌃',
+                Modifier::Alt | Modifier::RightAlt => '⌥',
+                Modifier::Shift | Modifier::RightShift => '⇧',
+                Modifier::Win | Modifier::RightWin => '⌘',
+            }),
+        }
+    }
+}
+
+impl StyledDisplay for Code {
+    fn fmt_styled(&self, f: &mut Formatter<'_>, style: DisplayStyle) -> Result {
+        use WellKnownCode::*;
+        let Code::WellKnown(code) = self else { return write!(f, "{self}") };
+        if style == DisplayStyle::Text {
+            return write!(f, "{code}");
+        }
+        let symbol = match code {
+            Enter => "↩",
+            Escape => "⎋",
+            Backspace => "⌫",
+            Delete => "⌦",
+            Tab => "⇥",
+            Space => "Space",
+            CapsLock => "⇪",
+            Left => "←",
+            Right => "→",
+            Up => "↑",
+            Down => "↓",
+            PageUp => "⇞",
+            PageDown => "⇟",
+            Home => "↖",
+            End => "↘",
+            Minus => "-",
+            Equal => "=",
+            LeftBracket => "[",
+            RightBracket => "]",
+            Backslash => "\\",
+            Semicolon => ";",
+            Quote => "'",
+            Grave => "`",
+            Comma => ",",
+            Dot => ".",
+            Slash => "/",
+            code => return write!(f, "{}", code.to_string().to_uppercase()),
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+impl StyledDisplay for Accord {
+    fn fmt_styled(&self, f: &mut Formatter<'_>, style: DisplayStyle) -> Result {
+        let mut modifiers: Vec<Modifier> = self.modifiers.iter().collect();
+        match style {
+            DisplayStyle::Text => {
+                for (i, modifier) in modifiers.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "-")?;
+                    }
+                    modifier.fmt_styled(f, style)?;
+                }
+                if let Some(code) = self.code {
+                    if !modifiers.is_empty() {
+                        write!(f, "-")?;
+                    }
+                    code.fmt_styled(f, style)?;
+                }
+            }
+            DisplayStyle::Mac => {
+                // Order used in macOS menus.
+                modifiers.sort_by_key(|modifier| match modifier {
+                    Modifier::Ctrl | Modifier::RightCtrl => 0,
+                    Modifier::Alt | Modifier::RightAlt => 1,
+                    Modifier::Shift | Modifier::RightShift => 2,
+                    Modifier::Win | Modifier::RightWin => 3,
+                });
+                for modifier in &modifiers {
+                    modifier.fmt_styled(f, style)?;
+                }
+                if let Some(code) = self.code {
+                    code.fmt_styled(f, style)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StyledDisplay for KeyboardPart {
+    fn fmt_styled(&self, f: &mut Formatter<'_>, style: DisplayStyle) -> Result {
+        match self {
+            KeyboardPart::Key(accord) => accord.fmt_styled(f, style),
+            KeyboardPart::Char(modifiers, c) => {
+                Accord::new(*modifiers, None).fmt_styled(f, style)?;
+                if style == DisplayStyle::Text && !modifiers.is_empty() {
+                    write!(f, "-")?;
+                }
+                write!(f, "{c}")
+            }
+            KeyboardPart::Delay(_) => write!(f, "{self}"),
+        }
+    }
+}
+
+impl StyledDisplay for Macro {
+    fn fmt_styled(&self, f: &mut Formatter<'_>, style: DisplayStyle) -> Result {
+        let Macro::Keyboard(parts) = self else { return write!(f, "{self}") };
+        if parts.is_empty() {
+            return write!(f, "{self}");
+        }
+        let separator = match style {
+            DisplayStyle::Text => ",",
+            DisplayStyle::Mac => " ",
+        };
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{separator}")?;
+            }
+            part.fmt_styled(f, style)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisplayStyle, StyledDisplay as _};
+    use crate::keyboard::Macro;
+
+    fn render(s: &str, style: DisplayStyle) -> String {
+        s.parse::<Macro>().unwrap().display(style).to_string()
+    }
+
+    #[test]
+    fn render_styles() {
+        assert_eq!(render("cmd-opt-c,enter", DisplayStyle::Text), "alt-win-c,enter");
+        assert_eq!(render("cmd-shift-ctrl-c,enter", DisplayStyle::Mac), "⌃⇧⌘C ↩");
+        assert_eq!(render("opt-f5", DisplayStyle::Mac), "⌥F5");
+        assert_eq!(render("delay[100],shift-!", DisplayStyle::Text), "delay[100],shift-!");
+        assert_eq!(render("volumeup", DisplayStyle::Mac), "volumeup");
+    }
+}
@@ -1,117 +1,37 @@
 use anyhow::{bail, ensure, Result};
-use log::debug;
 use rusb::{Context, DeviceHandle};
 
 use crate::keyboard::Accord;
 
-use super::{Key, Keyboard, Macro, MouseAction, MouseEvent};
+use super::{Key, Keyboard, Macro, MacroKind, MouseAction, MouseButtons, MouseEvent, movement_bytes, Quirk, Quirks};
+
+/// Bytes of binding message kinds.
+pub const KINDS: &[(MacroKind, u8)] = &[
+    (MacroKind::Keys, 1),
+    (MacroKind::Media, 2),
+    (MacroKind::Mouse, 3),
+    (MacroKind::Delay, 5),
+];
 
 pub struct Keyboard884x {
     handle: DeviceHandle<Context>,
     endpoint: u8,
+    quirks: Quirks,
 }
 
 impl Keyboard for Keyboard884x {
-    fn bind_key(&mut self, layer: u8, key: Key, expansion: &Macro) -> Result<()> {
-        ensure!(layer <= 15, "invalid layer index");
-
-        debug!("bind {} on layer {} to {}", key, layer, expansion);
-
-        let mut msg = vec![
-            0x03,
-            0xfe,
-            key.to_key_id(15)?,
-            layer + 1,
-            expansion.kind(),
-            0,
-            0,
-            0,
-            0,
-            0,
-        ];
-
-        match expansion {
-            Macro::Keyboard(presses) => {
-                ensure!(presses.len() <= 18, "macro sequence is too long");
-
-                // Count only key parts when putting header length
-                let key_count = presses.iter().filter(|p| matches!(p, super::KeyboardPart::Key(_))).count();
-
-                // Use actual key count. Using 0 for single-key breaks cases with a leading delay.
-                msg.push(key_count as u8);
-
-                for part in presses.iter() {
-                    match part {
-                        super::KeyboardPart::Key(Accord { modifiers, code }) => {
-                            msg.extend_from_slice(&[modifiers.as_u8(), code.map_or(0, |c| c.value())]);
-                        }
-                        super::KeyboardPart::Delay(_) => {
-                            // Delay entries are not part of the header payload for key programming.
-                        }
-                    }
-                }
-            }
-            Macro::Media(code) => {
-                let [low, high] = (*code as u16).to_le_bytes();
-                msg.extend_from_slice(&[0, low, high, 0, 0, 0, 0]);
-            }
-            Macro::Mouse(MouseEvent(MouseAction::Click(buttons), _)) => {
-                ensure!(!buttons.is_empty(), "buttons must be given for click macro");
-                // Python encoding: [modifier, button, x, y, wheel]
-                msg.push(5);
-                msg.extend_from_slice(&[0, buttons.as_u8(), 0, 0, 0]);
-            }
-            Macro::Mouse(MouseEvent(MouseAction::WheelUp, modifier)) => {
-                msg.push(5);
-                msg.extend_from_slice(&[modifier.map_or(0, |m| m as u8), 0, 0, 0, 1]);
-            }
-            Macro::Mouse(MouseEvent(MouseAction::WheelDown, modifier)) => {
-                msg.push(5);
-                msg.extend_from_slice(&[modifier.map_or(0, |m| m as u8), 0, 0, 0, 255]);
-            }
-            // ...existing code...
-            Macro::Mouse(MouseEvent(MouseAction::Move { dx, dy }, modifier)) => {
-                // Encode dx/dy as low bytes (two's complement via cast) into Python-style positions:
-                // [modifier, button/flag, x, y, wheel]
-                let x_b = ((*dx as i32) & 0xff) as u8;
-                let y_b = ((*dy as i32) & 0xff) as u8;
-                msg.push(5);
-                msg.extend_from_slice(&[modifier.map_or(0, |m| m as u8), 0, x_b, y_b, 0]);
-            }
-        };
-
-        // Send main programming message (keys/media/mouse)
-        self.send(&msg)?;
-
-        // If macro has a leading delay part (we validated earlier that any delay must be leading),
-        // send a single delay message with the specified ms after programming the macro.
-        if let Macro::Keyboard(parts) = expansion {
-            if let Some(super::KeyboardPart::Delay(ms)) = parts.first() {
-                if *ms > 6000 {
-                    return Err(anyhow::anyhow!("delay value {ms}ms exceeds maximum supported 6000ms"));
-                }
-                let mut delay_msg = msg.clone();
-                delay_msg[4] = 0x05;
-                let [low, high] = ms.to_le_bytes();
-                delay_msg[5] = low;
-                delay_msg[6] = high;
-                self.send(&delay_msg)?;
-            }
-        }
-
-        // Finish key binding
-        self.send(&[0x03, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0])?;
-        self.send(&[0x03, 0xfd, 0xfe, 0xff])?;
-        self.send(&[0x03, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0])?;
-
-        Ok(())
+    fn encode(&self, layer: u8, key: Key, expansion: &Macro) -> Result<Vec<Vec<u8>>> {
+        bind_messages(layer, key, expansion, self.quirks)
     }
 
+    // LED message of k884x firmwares isn't known, so nothing is sent rather
+    // than guessing bytes which may be taken for binding.
     fn set_led(&mut self, _n: u8) -> Result<()> {
         bail!(
-            "If you have a device which supports backlight LEDs, please let us know at \
-               https://github.com/kriomant/ch57x-keyboard-tool/issues/60. We'll be glad to \
-               help you reverse-engineer it."
+            "LED modes of k884x keyboards can't be set yet, their message isn't known. \
+               If vendor software switches modes of your keyboard, please capture its USB traffic \
+               and share it at https://github.com/kriomant/ch57x-keyboard-tool/issues/60. We'll be \
+               glad to help you reverse-engineer it."
         )
     }
 
@@ -119,21 +39,226 @@ impl Keyboard for Keyboard884x {
         &self.handle
     }
 
+    fn get_handle_mut(&mut self) -> &mut DeviceHandle<Context> {
+        &mut self.handle
+    }
+
     fn get_endpoint(&self) -> u8 {
         self.endpoint
     }
 
+    fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
     fn preferred_endpoint() -> u8 {
         0x04
     }
 }
 
+/// Encodes messages binding key to macro. Whole binding is encoded before
+/// anything is sent, so invalid macro doesn't leave key half-programmed.
+pub fn bind_messages(layer: u8, key: Key, expansion: &Macro, quirks: Quirks) -> Result<Vec<Vec<u8>>> {
+    ensure!(layer <= 15, "invalid layer index");
+
+    let mut msg = vec![
+        0x03,
+        0xfe,
+        key.to_key_id(15)?,
+        layer + 1,
+        expansion.kind().byte(KINDS)?,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+
+    match expansion {
+        Macro::Keyboard(presses) => {
+            ensure!(presses.len() <= 18, "macro sequence is too long, keyboard takes at most 18 key presses; \
+                                      use `upload --emulate-unsupported` or `paste_text` for longer ones");
+
+            // Count only key parts when putting header length
+            let key_count = presses.iter().filter(|p| matches!(p, super::KeyboardPart::Key(_))).count();
+
+            // Use actual key count. Using 0 for single-key breaks cases with a leading delay.
+            msg.push(key_count as u8);
+
+            for part in presses.iter() {
+                match part {
+                    super::KeyboardPart::Key(Accord { modifiers, code }) => {
+                        msg.extend_from_slice(&[modifiers.as_u8(), code.map_or(0, |c| c.value())]);
+                    }
+                    super::KeyboardPart::Delay(_) => {
+                        // Delay entries are not part of the header payload for key programming.
+                    }
+                    super::KeyboardPart::Char(_, c) => {
+                        bail!("character '{c}' must be translated using keyboard layout");
+                    }
+                }
+            }
+        }
+        Macro::Media(code) => {
+            let [low, high] = if quirks.contains(Quirk::BigEndianMedia) {
+                (*code as u16).to_be_bytes()
+            } else {
+                (*code as u16).to_le_bytes()
+            };
+            msg.extend_from_slice(&[0, low, high, 0, 0, 0, 0]);
+        }
+        Macro::Mouse(event) => {
+            msg.push(5);
+            msg.extend_from_slice(&mouse_payload(event, quirks)?);
+        }
+    };
+
+    // Main programming message (keys/media/mouse)
+    let mut messages = vec![msg.clone()];
+
+    // If macro has a leading delay part (we validated earlier that any delay must be leading),
+    // a single delay message with the specified ms follows programming the macro.
+    if let Macro::Keyboard(parts) = expansion {
+        if let Some(super::KeyboardPart::Delay(ms)) = parts.first() {
+            if *ms > 6000 {
+                return Err(anyhow::anyhow!("delay value {ms}ms exceeds maximum supported 6000ms"));
+            }
+            let mut delay_msg = msg.clone();
+            delay_msg[4] = MacroKind::Delay.byte(KINDS)?;
+            let [low, high] = ms.to_le_bytes();
+            delay_msg[5] = low;
+            delay_msg[6] = high;
+            messages.push(delay_msg);
+        }
+    }
+
+    // Finish key binding
+    messages.push(vec![0x03, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0]);
+    if !quirks.contains(Quirk::ShortFinish) {
+        messages.push(vec![0x03, 0xfd, 0xfe, 0xff]);
+        messages.push(vec![0x03, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0]);
+    }
+
+    Ok(messages)
+}
+
+/// Encodes mouse event as `[modifier, buttons, x, y, wheel]`, or with
+/// modifier after wheel byte for firmwares having `modifier-after-wheel` quirk.
+fn mouse_payload(event: &MouseEvent, quirks: Quirks) -> Result<[u8; 5]> {
+    let MouseEvent(action, modifier) = event.vertical()?;
+    let modifier = modifier.map_or(0, |m| m as u8);
+    let (buttons, x, y, wheel) = match action {
+        MouseAction::Click(buttons) => {
+            ensure!(!buttons.is_empty(), "buttons must be given for click macro");
+            // Modifier isn't sent with clicks.
+            return Ok([0, buttons.as_u8(), 0, 0, 0]);
+        }
+        // Wheel byte is signed number of notches.
+        MouseAction::WheelUp(notches) => (0, 0, 0, notches),
+        MouseAction::WheelDown(notches) => (0, 0, 0, (-(notches as i8)) as u8),
+        MouseAction::WheelLeft(_) | MouseAction::WheelRight(_) => unreachable!("horizontal scroll is made vertical"),
+        // Movement is two's complement low bytes.
+        MouseAction::Move { dx, dy } => {
+            let (x, y) = movement_bytes(dx, dy)?;
+            (0, x, y, 0)
+        }
+        // Button and movement are sent in the same report, firmware
+        // releases button after it like after click.
+        MouseAction::Drag { button, dx, dy } => {
+            let (x, y) = movement_bytes(dx, dy)?;
+            (MouseButtons::from(button).as_u8(), x, y, 0)
+        }
+    };
+    if quirks.contains(Quirk::ModifierAfterWheel) {
+        Ok([buttons, x, y, wheel, modifier])
+    } else {
+        Ok([modifier, buttons, x, y, wheel])
+    }
+}
+
 impl Keyboard884x {
-    pub fn new(handle: DeviceHandle<Context>, endpoint: u8) -> Result<Self> {
-        let mut keyboard = Self { handle, endpoint };
+    pub fn new(handle: DeviceHandle<Context>, endpoint: u8, quirks: Quirks) -> Result<Self> {
+        let mut keyboard = Self { handle, endpoint, quirks };
 
-        keyboard.send(&[])?;
+        keyboard.wake()?;
 
         Ok(keyboard)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{bind_messages, mouse_payload};
+    use crate::keyboard::{Key, KnobAction, Macro, Quirk, Quirks};
+
+    fn payload(s: &str, quirks: Quirks) -> [u8; 5] {
+        let Macro::Mouse(event) = s.parse().unwrap() else { panic!("not mouse macro") };
+        mouse_payload(&event, quirks).unwrap()
+    }
+
+    // Bytes 11..16 of bind messages.
+    #[test]
+    fn encode_wheel_with_modifier() {
+        assert_eq!(payload("ctrl-wheelup", Quirks::empty()), [0x01, 0, 0, 0, 0x01]);
+        assert_eq!(payload("ctrl-wheeldown*2", Quirks::empty()), [0x01, 0, 0, 0, 0xfe]);
+        assert_eq!(payload("ctrl-wheelup", Quirk::ModifierAfterWheel.into()), [0, 0, 0, 0x01, 0x01]);
+        assert_eq!(payload("click", Quirk::ModifierAfterWheel.into()), [0, 0x01, 0, 0, 0]);
+        assert_eq!(payload("mousemove[-1,2]", Quirks::empty()), [0, 0, 0xff, 0x02, 0]);
+    }
+
+    #[test]
+    fn encode_binding_before_sending() {
+        let messages = bind_messages(0, Key::Button(1), &"delay[100],a".parse().unwrap(), Quirks::empty()).unwrap();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[1][..7], [0x03, 0xfe, 0x02, 0x01, 0x05, 0x64, 0x00]);
+        let messages = bind_messages(0, Key::Button(1), &"a".parse().unwrap(), Quirk::ShortFinish.into()).unwrap();
+        assert_eq!(messages.len(), 2);
+    }
+
+    // Pads like 4x3-3knobs: each knob has its own key ids following buttons.
+    #[test]
+    fn encode_three_knobs() {
+        let mute = "mute".parse().unwrap();
+        let ids: Vec<u8> = (0..3)
+            .flat_map(|n| [KnobAction::RotateCCW, KnobAction::Press, KnobAction::RotateCW].map(|action| Key::Knob(n, action)))
+            .map(|key| bind_messages(0, key, &mute, Quirks::empty()).unwrap()[0][2])
+            .collect();
+        assert_eq!(ids, (16..25).collect::<Vec<u8>>());
+    }
+
+    // 4x4 pads have one button more than ids known for buttons.
+    #[test]
+    fn reject_sixteenth_button() {
+        let a = "a".parse().unwrap();
+        assert_eq!(bind_messages(0, Key::Button(14), &a, Quirks::empty()).unwrap()[0][2], 15);
+        let err = bind_messages(0, Key::Button(15), &a, Quirks::empty()).unwrap_err();
+        assert!(err.to_string().contains("at most 15 buttons"));
+    }
+
+    #[test]
+    fn encode_drag() {
+        assert_eq!(payload("mdrag[10,-5]", Quirks::empty()), [0, 0x04, 10, 0xfb, 0]);
+        assert_eq!(payload("ctrl-drag[0,3]", Quirks::empty()), [0x01, 0x01, 0, 3, 0]);
+    }
+
+    #[test]
+    fn reject_long_move() {
+        assert_eq!(payload("mousemove[-128,127]", Quirks::empty()), [0, 0, 0x80, 0x7f, 0]);
+        for s in ["mousemove[200,0]", "drag[0,-129]"] {
+            let Macro::Mouse(event) = s.parse().unwrap() else { panic!("not mouse macro") };
+            assert!(mouse_payload(&event, Quirks::empty()).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn encode_horizontal_wheel() {
+        assert_eq!(payload("wheelleft*2", Quirks::empty()), [0x02, 0, 0, 0, 2]);
+        assert_eq!(payload("wheelright", Quirks::empty()), [0x02, 0, 0, 0, 0xff]);
+        let Macro::Mouse(event) = "ctrl-wheelleft".parse().unwrap() else { panic!("not mouse macro") };
+        assert!(mouse_payload(&event, Quirks::empty()).is_err());
+    }
+}
@@ -1,11 +1,16 @@
+pub(crate) mod display;
 pub(crate) mod k884x;
 pub(crate) mod k8890;
+pub(crate) mod quirks;
+
+pub use display::{DisplayStyle, StyledDisplay};
+pub use quirks::{Quirk, Quirks};
 
 use crate::parse;
 
 use std::{time::Duration, str::FromStr, fmt::Display};
 
-use anyhow::{anyhow, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
 use enumset::{EnumSetType, EnumSet};
 use log::debug;
 use rusb::{Context, DeviceHandle};
@@ -15,30 +20,133 @@ use strum_macros::{EnumString, Display, EnumIter, EnumMessage};
 use itertools::Itertools as _;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+/// How many times wake message is sent before giving up.
+const WAKE_ATTEMPTS: usize = 3;
+/// How long queued report is waited for when IN endpoint is drained.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(1);
+/// Maximum number of reports read at once when draining, so keyboard
+/// sending reports continuously doesn't stall upload.
+const MAX_DRAINED: usize = 16;
+/// Pause after each message with `paced` quirk.
+const PACE: Duration = Duration::from_millis(20);
 
 pub trait Keyboard {
-    fn bind_key(&mut self, layer: u8, key: Key, expansion: &Macro) -> Result<()>;
+    /// Encodes messages binding key on zero-based layer to macro.
+    fn encode(&self, layer: u8, key: Key, expansion: &Macro) -> Result<Vec<Vec<u8>>>;
+
+    /// Binds key, messages are sent only once whole binding is encoded.
+    fn bind_key(&mut self, layer: u8, key: Key, expansion: &Macro) -> Result<()> {
+        debug!("bind {key} on layer {layer} to {expansion}");
+        for msg in self.encode(layer, key, expansion)? {
+            self.send(&msg)?;
+        }
+        Ok(())
+    }
+
+    /// Binds keys given as zero-based layer, key and macro. Backends may
+    /// send them in single programming session; neither protocol is known
+    /// to allow that, so by default keys are bound one by one, stopping at
+    /// first failure.
+    fn bind_all(&mut self, bindings: &[(u8, Key, &Macro)]) -> Result<()> {
+        for &(layer, key, expansion) in bindings {
+            self.bind_key(layer, key, expansion)
+                .with_context(|| format!("bind {key} on layer {}", layer + 1))?;
+        }
+        Ok(())
+    }
+
     fn set_led(&mut self, n: u8) -> Result<()>;
 
     fn preferred_endpoint() -> u8 where Self: Sized;
     fn get_handle(&self) -> &DeviceHandle<Context>;
+    fn get_handle_mut(&mut self) -> &mut DeviceHandle<Context>;
     fn get_endpoint(&self) -> u8;
+    fn get_quirks(&self) -> Quirks;
+    fn set_quirks(&mut self, quirks: Quirks);
 
     fn send(&mut self, msg: &[u8]) -> Result<()> {
         let mut buf = [0; 64];
         buf[..msg.len()].copy_from_slice(msg);
+        let buf = if self.get_quirks().contains(Quirk::ShortReports) { msg } else { &buf };
 
         debug!("send: {:02x?}", buf);
-        let written = self
-            .get_handle()
-            .write_interrupt(self.get_endpoint(), &buf, DEFAULT_TIMEOUT)?;
+        let endpoint = self.get_endpoint();
+        let written = match self.get_handle().write_interrupt(endpoint, buf, DEFAULT_TIMEOUT) {
+            // Keyboard autosuspended by kernel may stall the first transfer,
+            // clearing halt resumes it.
+            Err(rusb::Error::Pipe) => {
+                debug!("endpoint stalled, clearing halt and sending again");
+                self.get_handle_mut().clear_halt(endpoint)?;
+                match self.get_handle().write_interrupt(endpoint, buf, DEFAULT_TIMEOUT) {
+                    Err(rusb::Error::Pipe) => bail!(
+                        "keyboard endpoint stalls even after being cleared; if kernel autosuspends keyboard, \
+                         disable it as shown by `list-devices --verbose` and replug keyboard"),
+                    result => result?,
+                }
+            }
+            result => result?,
+        };
         ensure!(written == buf.len(), "not all data written");
+        if self.get_quirks().contains(Quirk::DrainIn) {
+            self.drain()?;
+        }
+        if self.get_quirks().contains(Quirk::Paced) {
+            std::thread::sleep(PACE);
+        }
         Ok(())
     }
+
+    /// Reads and drops reports queued on IN endpoint.
+    fn drain(&mut self) -> Result<()> {
+        for _ in 0..MAX_DRAINED {
+            if self.receive(DRAIN_TIMEOUT)?.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends empty message keyboard expects once interface is claimed.
+    /// Keyboards behind bad cables may miss it, so it's retried on timeout.
+    fn wake(&mut self) -> Result<()> {
+        for attempt in 1..=WAKE_ATTEMPTS {
+            match self.send(&[]) {
+                Err(err) if err.downcast_ref() == Some(&rusb::Error::Timeout) => {
+                    debug!("wake message timed out, attempt {attempt} of {WAKE_ATTEMPTS}");
+                }
+                result => return result,
+            }
+        }
+        bail!("keyboard never acknowledged wake message after {WAKE_ATTEMPTS} attempts, \
+               try another cable or USB port")
+    }
+
+    /// Resets keyboard on USB level, so it is reattached like after replugging.
+    fn reset(&mut self) -> Result<()> {
+        debug!("reset device");
+        match self.get_handle_mut().reset() {
+            // Device is already reattached and can't be found by old handle.
+            Ok(()) | Err(rusb::Error::NotFound) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Reads message sent by keyboard, `None` if nothing arrives in time.
+    /// It is expected on IN endpoint with the same number as `get_endpoint`.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let mut buf = [0; 64];
+        match self.get_handle().read_interrupt(self.get_endpoint() | 0x80, &mut buf, timeout) {
+            Ok(read) => {
+                debug!("receive: {:02x?}", &buf[..read]);
+                Ok(Some(buf[..read].to_vec()))
+            }
+            Err(rusb::Error::Timeout) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
 }
 
-#[allow(unused)]
-#[derive(Debug, Clone, Copy, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
 #[repr(u8)]
 pub enum KnobAction {
     #[strum(serialize="ccw")]
@@ -66,9 +174,12 @@ impl Display for Key {
 }
 
 impl Key {
-    fn to_key_id(self, base: u8) -> Result<u8> {
+    pub fn to_key_id(self, base: u8) -> Result<u8> {
         match self {
-            Key::Button(n) if n >= base => Err(anyhow!("invalid key index")),
+            // Ids following buttons belong to knobs, so key id of extra
+            // button, like 16th one of 4x4 pads, isn't known.
+            Key::Button(n) if n >= base => Err(anyhow!("invalid key index: key id of button {} isn't known, \
+                                                         protocol addresses at most {base} buttons", n + 1)),
             Key::Button(n) => Ok(n + 1),
             Key::Knob(n, _) if n >= 4 => Err(anyhow!("invalid knob index")),
             // Special case: 4th knob (index 3)
@@ -77,9 +188,22 @@ impl Key {
             Key::Knob(n, action) => Ok(base + 1 + 3 * n + (action as u8)),
         }
     }
+
+    /// Inverse of `to_key_id`, ids of 4th knob are taken for buttons.
+    pub fn from_key_id(id: u8, base: u8) -> Option<Key> {
+        if id == 0 {
+            return None;
+        }
+        if id <= base {
+            return Some(Key::Button(id - 1));
+        }
+        let (n, action) = ((id - base - 1) / 3, (id - base - 1) % 3);
+        let action = [KnobAction::RotateCCW, KnobAction::Press, KnobAction::RotateCW][action as usize];
+        (n < 3).then_some(Key::Knob(n, action))
+    }
 }
 
-#[derive(Debug, EnumSetType, EnumString, EnumIter, EnumMessage, Display)]
+#[derive(Debug, PartialOrd, Ord, EnumSetType, EnumString, EnumIter, EnumMessage, Display, DeserializeFromStr)]
 #[strum(ascii_case_insensitive)]
 pub enum Modifier {
     #[strum(serialize="ctrl")]
@@ -108,17 +232,61 @@ pub type Modifiers = EnumSet<Modifier>;
 #[strum(serialize_all="lowercase")]
 #[strum(ascii_case_insensitive)]
 pub enum MediaCode {
+	BrightnessUp = 0x6f,
+	BrightnessDown = 0x70,
+	Record = 0xb2,
+	FastForward = 0xb3,
+	Rewind = 0xb4,
 	Next = 0xb5,
     #[strum(serialize="previous", serialize="prev")]
 	Previous = 0xb6,
 	Stop = 0xb7,
+	Eject = 0xb8,
+	Shuffle = 0xb9,
+	Repeat = 0xbc,
 	Play = 0xcd,
 	Mute = 0xe2,
+	BassBoost = 0xe5,
 	VolumeUp = 0xe9,
 	VolumeDown = 0xea,
+	BassUp = 0x152,
+	BassDown = 0x153,
+	TrebleUp = 0x154,
+	TrebleDown = 0x155,
 	Favorites = 0x182,
+	Email = 0x18a,
 	Calculator = 0x192,
+	MyComputer = 0x194,
+	Browser = 0x196,
 	ScreenLock = 0x19e,
+	Search = 0x221,
+	BrowserHome = 0x223,
+	BrowserBack = 0x224,
+	BrowserForward = 0x225,
+	BrowserRefresh = 0x227,
+	Bookmarks = 0x22a,
+}
+
+/// Applications launched by consumer "AL" usages, see `launch` macro shorthand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum LaunchApp {
+    Calculator,
+    Email,
+    Browser,
+    #[serde(alias="computer")]
+    MyComputer,
+}
+
+impl LaunchApp {
+    pub fn media_code(self) -> MediaCode {
+        match self {
+            LaunchApp::Calculator => MediaCode::Calculator,
+            LaunchApp::Email => MediaCode::Email,
+            LaunchApp::Browser => MediaCode::Browser,
+            LaunchApp::MyComputer => MediaCode::MyComputer,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -157,6 +325,14 @@ impl Code {
             Self::Custom(code) => code,
         }
     }
+
+    /// Returns well-known code with given HID usage value if there is one.
+    pub fn from_value(value: u8) -> Self {
+        use strum::IntoEnumIterator as _;
+        WellKnownCode::iter()
+            .find(|code| *code as u8 == value)
+            .map_or(Self::Custom(value), Self::WellKnown)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumIter, Display)]
@@ -319,7 +495,7 @@ impl Display for Accord {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, DeserializeFromStr)]
 #[strum(ascii_case_insensitive)]
 #[repr(u8)]
 pub enum MouseModifier {
@@ -343,11 +519,17 @@ pub type MouseButtons = EnumSet<MouseButton>;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MouseAction {
     Click(MouseButtons),
-    WheelUp,
-    WheelDown,
+    /// Wheel scroll by given number of notches (1..=127).
+    WheelUp(u8),
+    WheelDown(u8),
+    /// Horizontal scroll, sent as vertical wheel with shift held.
+    WheelLeft(u8),
+    WheelRight(u8),
     /// Relative move in device units. Positive X = right, Positive Y = down.
     #[allow(dead_code)]
     Move { dx: i16, dy: i16 },
+    /// Move with button held, it's released afterwards.
+    Drag { button: MouseButton, dx: i16, dy: i16 },
 }
 
 impl Display for MouseAction {
@@ -356,9 +538,23 @@ impl Display for MouseAction {
             MouseAction::Click(buttons) => {
                 write!(f, "{}", buttons.iter().format("+"))?;
             }
-            MouseAction::WheelUp => { write!(f, "wheelup")?; }
-            MouseAction::WheelDown => { write!(f, "wheeldown")?; }
+            MouseAction::WheelUp(1) => { write!(f, "wheelup")?; }
+            MouseAction::WheelUp(n) => { write!(f, "wheelup*{}", n)?; }
+            MouseAction::WheelDown(1) => { write!(f, "wheeldown")?; }
+            MouseAction::WheelDown(n) => { write!(f, "wheeldown*{}", n)?; }
+            MouseAction::WheelLeft(1) => { write!(f, "wheelleft")?; }
+            MouseAction::WheelLeft(n) => { write!(f, "wheelleft*{}", n)?; }
+            MouseAction::WheelRight(1) => { write!(f, "wheelright")?; }
+            MouseAction::WheelRight(n) => { write!(f, "wheelright*{}", n)?; }
             MouseAction::Move { dx, dy } => { write!(f, "move({},{})", dx, dy)?; }
+            MouseAction::Drag { button, dx, dy } => {
+                let prefix = match button {
+                    MouseButton::Left => "",
+                    MouseButton::Right => "r",
+                    MouseButton::Middle => "m",
+                };
+                write!(f, "{prefix}drag[{dx},{dy}]")?;
+            }
         }
         Ok(())
     }
@@ -378,10 +574,43 @@ impl Display for MouseEvent {
     }
 }
 
+/// Encodes pointer movement as two's complement bytes.
+///
+/// Larger moves aren't split into several reports: mouse binding of either
+/// protocol holds exactly one report, and neither is known to take more
+/// reports per key or wider deltas. Sending guessed layout could misprogram
+/// key, so such moves are rejected.
+pub fn movement_bytes(dx: i16, dy: i16) -> Result<(u8, u8)> {
+    let byte = |n: i16| i8::try_from(n).map(|n| n as u8);
+    match (byte(dx), byte(dy)) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        _ => bail!("mouse movement ({dx},{dy}) exceeds supported range -128..127: keyboard sends single mouse report \
+                    per key press and can't be told to send several, press key bound to shorter move repeatedly instead"),
+    }
+}
+
+impl MouseEvent {
+    /// Turns horizontal scroll into vertical one with shift held, which
+    /// apps take for horizontal scroll. No mouse message of keyboards is
+    /// known to have horizontal wheel byte.
+    pub fn vertical(self) -> Result<MouseEvent> {
+        let action = match self.0 {
+            MouseAction::WheelLeft(n) => MouseAction::WheelUp(n),
+            MouseAction::WheelRight(n) => MouseAction::WheelDown(n),
+            _ => return Ok(self),
+        };
+        ensure!(self.1.is_none(), "horizontal scroll is sent with shift held, so other modifier can't be given: {self}");
+        Ok(MouseEvent(action, Some(MouseModifier::Shift)))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeyboardPart {
     Key(Accord),
     Delay(u16),
+    /// Character with optional modifiers, it is translated to key presses
+    /// using keyboard layout before programming.
+    Char(Modifiers, char),
 }
 
 impl std::fmt::Display for KeyboardPart {
@@ -389,12 +618,20 @@ impl std::fmt::Display for KeyboardPart {
         match self {
             KeyboardPart::Key(accord) => write!(f, "{}", accord),
             KeyboardPart::Delay(ms) => write!(f, "delay[{}]", ms),
+            KeyboardPart::Char(modifiers, c) => {
+                for m in modifiers.iter() {
+                    write!(f, "{}-", m)?;
+                }
+                // Comma separates parts, so legend showing it is quoted.
+                if *c == ',' { write!(f, "','") } else { write!(f, "{}", c) }
+            }
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Macro {
+    /// Key presses, none for disabled key.
     Keyboard(Vec<KeyboardPart>),
     #[allow(unused)]
     Media(MediaCode),
@@ -402,12 +639,39 @@ pub enum Macro {
     Mouse(MouseEvent),
 }
 
+/// Kind of binding message, each protocol maps kinds to bytes with its own table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum MacroKind {
+    #[strum(serialize = "keyboard")]
+    Keys,
+    #[strum(serialize = "media")]
+    Media,
+    #[strum(serialize = "mouse")]
+    Mouse,
+    /// Delay before keyboard macro, sent in message of its own.
+    #[strum(serialize = "delay")]
+    Delay,
+}
+
+impl MacroKind {
+    /// Returns byte for kind from protocol table, fails if protocol doesn't support it.
+    pub fn byte(self, table: &[(MacroKind, u8)]) -> Result<u8> {
+        table.iter().find(|(kind, _)| *kind == self).map(|(_, byte)| *byte)
+            .ok_or_else(|| anyhow!("{self} macros are not supported for this keyboard model"))
+    }
+
+    /// Finds kind by its byte in protocol table.
+    pub fn from_byte(table: &[(MacroKind, u8)], byte: u8) -> Option<Self> {
+        table.iter().find(|(_, b)| *b == byte).map(|(kind, _)| *kind)
+    }
+}
+
 impl Macro {
-    fn kind(&self) -> u8 {
+    fn kind(&self) -> MacroKind {
         match self {
-            Macro::Keyboard(_) => 1,
-            Macro::Media(_) => 2,
-            Macro::Mouse(_) => 3,
+            Macro::Keyboard(_) => MacroKind::Keys,
+            Macro::Media(_) => MacroKind::Media,
+            Macro::Mouse(_) => MacroKind::Mouse,
         }
     }
 }
@@ -423,6 +687,7 @@ impl FromStr for Macro {
 impl Display for Macro {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Macro::Keyboard(accords) if accords.is_empty() => write!(f, "disabled"),
             Macro::Keyboard(accords) => {
                 write!(f, "{}", accords.iter().format(","))
             }
@@ -438,32 +703,131 @@ impl Display for Macro {
 
 // Provide a custom Deserialize impl so we can return friendlier errors when
 // users try to combine media tokens with delays/keys (media must be standalone).
+// Besides string, macro may be given as table with one of shorthand forms,
+// see `MacroTable`.
 impl<'de> serde::Deserialize<'de> for Macro {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        // Deserialize as string first
-        let s = String::deserialize(deserializer)?;
-
-        // If the user provided a comma-separated macro and any segment is a media
-        // token, return a clear error explaining media macros must be standalone.
-        if s.contains(',') {
-            for seg in s.split(',') {
-                let seg = seg.trim();
-                if seg.parse::<MediaCode>().is_ok() {
-                    return Err(serde::de::Error::custom(format!(
-                        "media macros must be standalone: '{}' cannot be combined with delays or other keys",
-                        seg
-                    )));
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Macro;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "macro string or table")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Macro, E> {
+                // If the user provided a comma-separated macro and any segment is a media
+                // token, return a clear error explaining media macros must be standalone.
+                if s.contains(',') {
+                    for seg in s.split(',') {
+                        let seg = seg.trim();
+                        if seg.parse::<MediaCode>().is_ok() {
+                            return Err(E::custom(format!(
+                                "media macros must be standalone: '{}' cannot be combined with delays or other keys",
+                                seg
+                            )));
+                        }
+                    }
                 }
+
+                // Fall back to existing FromStr parsing and surface its error if parsing fails.
+                match s.parse::<Macro>() {
+                    Ok(m) => Ok(m),
+                    Err(e) => Err(E::custom(format!("invalid macro '{}': {}", s, parse::suggest(s).unwrap_or_else(|| e.to_string())))),
+                }
+            }
+
+            // Unquoted digit keys are integers in YAML.
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Macro, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Macro, A::Error> {
+                use serde::de::Error as _;
+                let table = <MacroTable as serde::Deserialize>::deserialize(serde::de::value::MapAccessDeserializer::new(map))?;
+                table.into_macro().map_err(A::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Shorthand forms of macro which are expanded to ordinary macros.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MacroTable {
+    /// Types number, like `42` or `-3.5`.
+    #[serde(default, deserialize_with = "deserialize_number")]
+    number: Option<String>,
+    /// Use numeric keypad keys for `number`.
+    #[serde(default)]
+    numpad: bool,
+    /// Press Enter after `number`.
+    #[serde(default)]
+    enter: bool,
+    /// Launches application, like `calculator`.
+    launch: Option<LaunchApp>,
+}
+
+impl MacroTable {
+    fn into_macro(self) -> Result<Macro, String> {
+        if let Some(app) = self.launch {
+            if self.number.is_some() || self.numpad || self.enter {
+                return Err("'launch' can't be combined with other settings".to_owned());
             }
+            return Ok(Macro::Media(app.media_code()));
+        }
+        let Some(number) = self.number else {
+            return Err("macro table must contain 'number' or 'launch'".to_owned());
+        };
+
+        let mut parts = number.chars().map(|c| {
+            let code = match (c, self.numpad) {
+                ('0'..='9', false) => WellKnownCode::from_str(&c.to_string()).expect("digit key"),
+                ('0'..='9', true) => WellKnownCode::from_str(&format!("numpad{c}")).expect("numpad key"),
+                ('.', false) => WellKnownCode::Dot,
+                ('.', true) => WellKnownCode::NumPadDot,
+                ('-', false) => WellKnownCode::Minus,
+                ('-', true) => WellKnownCode::NumPadMinus,
+                _ => return Err(format!("invalid number '{number}'")),
+            };
+            Ok(KeyboardPart::Key(Accord::new(Modifiers::empty(), Some(code.into()))))
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        if self.enter {
+            let enter = if self.numpad { WellKnownCode::NumPadEnter } else { WellKnownCode::Enter };
+            parts.push(KeyboardPart::Key(Accord::new(Modifiers::empty(), Some(enter.into()))));
         }
 
-        // Fall back to existing FromStr parsing and surface its error if parsing fails.
-        match s.parse::<Macro>() {
-            Ok(m) => Ok(m),
-            Err(e) => Err(serde::de::Error::custom(format!("invalid macro '{}': {}", s, e))),
+        Ok(Macro::Keyboard(parts))
+    }
+}
+
+/// Accepts numbers given both as YAML numbers and strings, so that
+/// leading zeroes may be preserved: `"007"`.
+fn deserialize_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct Visitor;
+
+    impl<'de> serde::de::Visitor<'de> for Visitor {
+        type Value = String;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "number")
         }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<String, E> { Ok(v.to_string()) }
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<String, E> { Ok(v.to_string()) }
+        fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<String, E> { Ok(v.to_string()) }
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<String, E> { Ok(v.to_owned()) }
     }
+
+    deserializer.deserialize_any(Visitor).map(Some)
 }
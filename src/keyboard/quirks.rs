@@ -0,0 +1,79 @@
+//! Variations of keyboard protocols found in some firmware revisions.
+//!
+//! Keyboards with the same product ID may run different firmware expecting
+//! slightly different messages. Firmware revision is taken from USB device
+//! descriptor (`bcdDevice`) and quirks are looked up for it in table, so
+//! fix for one revision doesn't break others.
+
+use std::ops::RangeInclusive;
+
+use enumset::{EnumSet, EnumSetType};
+use serde_with::DeserializeFromStr;
+use strum_macros::{Display, EnumIter, EnumString};
+
+#[derive(Debug, EnumSetType, EnumString, EnumIter, Display, DeserializeFromStr)]
+pub enum Quirk {
+    /// Media key codes are sent big-endian.
+    #[strum(serialize = "be-media")]
+    BigEndianMedia,
+    /// Messages are sent as is instead of being padded with zeroes to 64 bytes.
+    #[strum(serialize = "short-reports")]
+    ShortReports,
+    /// Binding is finished by single end message, without save message.
+    #[strum(serialize = "short-finish")]
+    ShortFinish,
+    /// Mouse event modifier is sent after wheel byte rather than before
+    /// buttons, so modified scrolling like `ctrl-wheelup` works (k884x only).
+    #[strum(serialize = "modifier-after-wheel")]
+    ModifierAfterWheel,
+    /// Reports queued on IN endpoint are read and discarded unchecked after
+    /// each message, for keyboards stalling once it fills up.
+    #[strum(serialize = "drain-in")]
+    DrainIn,
+    /// Pause follows each message, for keyboards dropping messages sent
+    /// back to back.
+    #[strum(serialize = "paced")]
+    Paced,
+}
+
+pub type Quirks = EnumSet<Quirk>;
+
+/// Product ID, range of firmware revisions in BCD and their quirks.
+///
+/// No revision is known to need quirks yet, entries are added as they are
+/// reported. Revision is shown by `list-devices`.
+const TABLE: &[(u16, RangeInclusive<u16>, Quirks)] = &[];
+
+/// Number of firmware revision ranges known to need quirks.
+pub fn known_revisions() -> usize {
+    TABLE.len()
+}
+
+/// Returns quirks of keyboard with given product ID and firmware revision.
+pub fn for_device(product_id: u16, version: rusb::Version) -> Quirks {
+    let version = bcd(version);
+    TABLE.iter()
+        .filter(|(id, versions, _)| *id == product_id && versions.contains(&version))
+        .map(|(_, _, quirks)| *quirks)
+        .fold(Quirks::empty(), |all, quirks| all | quirks)
+}
+
+/// Converts version back to `bcdDevice` form, like 0x0110 for 1.1.0.
+pub fn bcd(version: rusb::Version) -> u16 {
+    (version.major() as u16) << 8 | (version.minor() as u16) << 4 | version.sub_minor() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bcd, for_device, Quirk, Quirks};
+
+    #[test]
+    fn lookup_quirks() {
+        assert_eq!(bcd(rusb::Version(1, 2, 3)), 0x0123);
+        assert_eq!(for_device(0x8840, rusb::Version(1, 0, 0)), Quirks::empty());
+        assert_eq!("be-media".parse(), Ok(Quirk::BigEndianMedia));
+        assert_eq!("drain-in".parse(), Ok(Quirk::DrainIn));
+        assert_eq!("paced".parse(), Ok(Quirk::Paced));
+        assert!("big-media".parse::<Quirk>().is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Keyboard layouts used to translate characters written in macros
+//! (like `!` or `é`) to key presses producing them, and back to show keys
+//! by legends printed on them.
+
+use std::str::FromStr as _;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::keyboard::{Accord, Code, KeyboardPart, Macro, Modifier, Modifiers, WellKnownCode};
+
+use WellKnownCode::*;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Display, EnumIter, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Layout {
+    /// US QWERTY.
+    #[default]
+    Us,
+    /// UK QWERTY.
+    Uk,
+    /// German QWERTZ.
+    De,
+    /// French AZERTY.
+    Fr,
+}
+
+/// Shift state required to get character from key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Base,
+    Shift,
+    /// Right Alt, a.k.a. AltGr.
+    AltGr,
+}
+
+impl Level {
+    fn modifiers(self) -> Modifiers {
+        match self {
+            Level::Base => Modifiers::empty(),
+            Level::Shift => Modifier::Shift.into(),
+            Level::AltGr => Modifier::RightAlt.into(),
+        }
+    }
+}
+
+type Press = (Level, WellKnownCode);
+
+/// Characters other than letters which keys may produce without modifiers
+/// in some layout, looked up to find key legends.
+const LEGEND_CHARACTERS: &str = "1234567890-=[]\\;'`,./<#+*$!&\"()_ßüöäéèçàù²";
+
+/// Diacritic typed with dead key followed by base character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Diacritic {
+    Acute,
+    Grave,
+    Circumflex,
+    Diaeresis,
+    Tilde,
+}
+
+impl Layout {
+    /// Returns sequence of key presses producing given character.
+    pub fn accords(self, c: char) -> Option<Vec<Accord>> {
+        let presses = match self.press(c) {
+            Some(press) => vec![press],
+            None => {
+                // Try to compose character using dead key.
+                let (diacritic, base) = decompose(c)?;
+                vec![self.dead_key(diacritic)?, self.press(base)?]
+            }
+        };
+        Some(presses.into_iter()
+            .map(|(level, code)| Accord::new(level.modifiers(), Some(code.into())))
+            .collect())
+    }
+
+    /// Replaces characters in keyboard macro with key presses producing them.
+    pub fn translate(self, macro_: &mut Macro) -> Result<()> {
+        self.translate_where(macro_, |_| true)
+    }
+
+    /// Replaces only characters pressed with modifiers, like `ctrl-+`, which
+    /// host can't type as text. Plain characters are left to be typed as is.
+    pub fn translate_chords(self, macro_: &mut Macro) -> Result<()> {
+        self.translate_where(macro_, |modifiers| !modifiers.is_empty())
+    }
+
+    fn translate_where(self, macro_: &mut Macro, filter: impl Fn(Modifiers) -> bool) -> Result<()> {
+        let Macro::Keyboard(parts) = macro_ else { return Ok(()) };
+        if !parts.iter().any(|p| matches!(p, KeyboardPart::Char(modifiers, _) if filter(*modifiers))) {
+            return Ok(());
+        }
+
+        let mut translated = Vec::with_capacity(parts.len());
+        for part in parts.drain(..) {
+            match part {
+                KeyboardPart::Char(modifiers, c) if filter(modifiers) => {
+                    let mut accords = self.accords(c)
+                        .ok_or_else(|| anyhow!("character '{c}' can't be typed with '{self}' layout"))?;
+                    // Explicit modifiers apply to the press producing character.
+                    if let Some(last) = accords.last_mut() {
+                        last.modifiers |= modifiers;
+                    }
+                    translated.extend(accords.into_iter().map(KeyboardPart::Key));
+                }
+                part => translated.push(part),
+            }
+        }
+        *parts = translated;
+        Ok(())
+    }
+
+    /// Returns character printed on key in this layout, if key produces
+    /// one without modifiers.
+    pub fn legend(self, code: WellKnownCode) -> Option<char> {
+        ('a'..='z').chain(LEGEND_CHARACTERS.chars())
+            .find(|c| self.press(*c) == Some((Level::Base, code)))
+    }
+
+    /// Replaces key presses in keyboard macro with characters printed on
+    /// keys, where they differ from key names: `semicolon` becomes `ö` for
+    /// German layout. Result is meant for display only, `,` legend is
+    /// shown quoted.
+    pub fn show_legends(self, macro_: &Macro) -> Macro {
+        let Macro::Keyboard(parts) = macro_ else { return macro_.clone() };
+        Macro::Keyboard(parts.iter().map(|part| match part {
+            KeyboardPart::Key(Accord { modifiers, code: Some(Code::WellKnown(code)) }) => {
+                match self.legend(*code) {
+                    Some(c) if c.to_string() != code.to_string() => KeyboardPart::Char(*modifiers, c),
+                    _ => part.clone(),
+                }
+            }
+            part => part.clone(),
+        }).collect())
+    }
+
+    /// Returns single key press producing character.
+    fn press(self, c: char) -> Option<Press> {
+        if c == ' ' {
+            return Some((Level::Base, Space));
+        }
+        if c.is_ascii_alphabetic() {
+            let level = if c.is_ascii_uppercase() { Level::Shift } else { Level::Base };
+            return Some((level, self.letter(c.to_ascii_lowercase())));
+        }
+        match self {
+            Layout::Us => us(c),
+            Layout::Uk => uk(c),
+            Layout::De => de(c),
+            Layout::Fr => fr(c),
+        }
+    }
+
+    /// Returns key producing given lowercase ASCII letter.
+    fn letter(self, c: char) -> WellKnownCode {
+        let c = match (self, c) {
+            (Layout::De, 'y') => 'z',
+            (Layout::De, 'z') => 'y',
+            (Layout::Fr, 'a') => 'q',
+            (Layout::Fr, 'q') => 'a',
+            (Layout::Fr, 'z') => 'w',
+            (Layout::Fr, 'w') => 'z',
+            (Layout::Fr, 'm') => return Semicolon,
+            _ => c,
+        };
+        WellKnownCode::from_str(c.encode_utf8(&mut [0; 4])).expect("letter key")
+    }
+
+    fn dead_key(self, diacritic: Diacritic) -> Option<Press> {
+        Some(match (self, diacritic) {
+            (Layout::De, Diacritic::Acute) => (Level::Base, Equal),
+            (Layout::De, Diacritic::Grave) => (Level::Shift, Equal),
+            (Layout::De, Diacritic::Circumflex) => (Level::Base, Grave),
+            (Layout::Fr, Diacritic::Circumflex) => (Level::Base, LeftBracket),
+            (Layout::Fr, Diacritic::Diaeresis) => (Level::Shift, LeftBracket),
+            _ => return None,
+        })
+    }
+}
+
+/// Splits character into diacritic and base character.
+/// Standalone diacritic is typed as dead key followed by space.
+fn decompose(c: char) -> Option<(Diacritic, char)> {
+    use Diacritic::*;
+    Some(match c {
+        '´' => (Acute, ' '),
+        '`' => (Grave, ' '),
+        '^' => (Circumflex, ' '),
+        '¨' => (Diaeresis, ' '),
+        '~' => (Tilde, ' '),
+        'á' => (Acute, 'a'), 'é' => (Acute, 'e'), 'í' => (Acute, 'i'), 'ó' => (Acute, 'o'), 'ú' => (Acute, 'u'), 'ý' => (Acute, 'y'),
+        'Á' => (Acute, 'A'), 'É' => (Acute, 'E'), 'Í' => (Acute, 'I'), 'Ó' => (Acute, 'O'), 'Ú' => (Acute, 'U'), 'Ý' => (Acute, 'Y'),
+        'à' => (Grave, 'a'), 'è' => (Grave, 'e'), 'ì' => (Grave, 'i'), 'ò' => (Grave, 'o'), 'ù' => (Grave, 'u'),
+        'À' => (Grave, 'A'), 'È' => (Grave, 'E'), 'Ì' => (Grave, 'I'), 'Ò' => (Grave, 'O'), 'Ù' => (Grave, 'U'),
+        'â' => (Circumflex, 'a'), 'ê' => (Circumflex, 'e'), 'î' => (Circumflex, 'i'), 'ô' => (Circumflex, 'o'), 'û' => (Circumflex, 'u'),
+        'Â' => (Circumflex, 'A'), 'Ê' => (Circumflex, 'E'), 'Î' => (Circumflex, 'I'), 'Ô' => (Circumflex, 'O'), 'Û' => (Circumflex, 'U'),
+        'ä' => (Diaeresis, 'a'), 'ë' => (Diaeresis, 'e'), 'ï' => (Diaeresis, 'i'), 'ö' => (Diaeresis, 'o'), 'ü' => (Diaeresis, 'u'), 'ÿ' => (Diaeresis, 'y'),
+        'Ä' => (Diaeresis, 'A'), 'Ë' => (Diaeresis, 'E'), 'Ï' => (Diaeresis, 'I'), 'Ö' => (Diaeresis, 'O'), 'Ü' => (Diaeresis, 'U'),
+        'ã' => (Tilde, 'a'), 'ñ' => (Tilde, 'n'), 'õ' => (Tilde, 'o'),
+        'Ã' => (Tilde, 'A'), 'Ñ' => (Tilde, 'N'), 'Õ' => (Tilde, 'O'),
+        _ => return None,
+    })
+}
+
+fn digit(c: char) -> Option<WellKnownCode> {
+    Some(match c {
+        '1' => N1, '2' => N2, '3' => N3, '4' => N4, '5' => N5,
+        '6' => N6, '7' => N7, '8' => N8, '9' => N9, '0' => N0,
+        _ => return None,
+    })
+}
+
+/// Characters produced by keys which are common for US and UK layouts.
+fn qwerty(c: char) -> Option<Press> {
+    use Level::*;
+    if let Some(code) = digit(c) {
+        return Some((Base, code));
+    }
+    Some(match c {
+        '!' => (Shift, N1),
+        '$' => (Shift, N4),
+        '%' => (Shift, N5),
+        '^' => (Shift, N6),
+        '&' => (Shift, N7),
+        '*' => (Shift, N8),
+        '(' => (Shift, N9),
+        ')' => (Shift, N0),
+        '-' => (Base, Minus),
+        '_' => (Shift, Minus),
+        '=' => (Base, Equal),
+        '+' => (Shift, Equal),
+        '[' => (Base, LeftBracket),
+        '{' => (Shift, LeftBracket),
+        ']' => (Base, RightBracket),
+        '}' => (Shift, RightBracket),
+        ';' => (Base, Semicolon),
+        ':' => (Shift, Semicolon),
+        '\'' => (Base, Quote),
+        ',' => (Base, Comma),
+        '<' => (Shift, Comma),
+        '.' => (Base, Dot),
+        '>' => (Shift, Dot),
+        '/' => (Base, Slash),
+        '?' => (Shift, Slash),
+        '`' => (Base, Grave),
+        _ => return None,
+    })
+}
+
+fn us(c: char) -> Option<Press> {
+    use Level::*;
+    Some(match c {
+        '@' => (Shift, N2),
+        '#' => (Shift, N3),
+        '"' => (Shift, Quote),
+        '\\' => (Base, Backslash),
+        '|' => (Shift, Backslash),
+        '~' => (Shift, Grave),
+        _ => return qwerty(c),
+    })
+}
+
+fn uk(c: char) -> Option<Press> {
+    use Level::*;
+    Some(match c {
+        '"' => (Shift, N2),
+        '£' => (Shift, N3),
+        '@' => (Shift, Quote),
+        '#' => (Base, NonUSHash),
+        '~' => (Shift, NonUSHash),
+        '\\' => (Base, NonUSBackslash),
+        '|' => (Shift, NonUSBackslash),
+        '¬' => (Shift, Grave),
+        '€' => (AltGr, N4),
+        _ => return qwerty(c),
+    })
+}
+
+fn de(c: char) -> Option<Press> {
+    use Level::*;
+    if let Some(code) = digit(c) {
+        return Some((Base, code));
+    }
+    Some(match c {
+        '°' => (Shift, Grave),
+        '!' => (Shift, N1),
+        '"' => (Shift, N2),
+        '²' => (AltGr, N2),
+        '§' => (Shift, N3),
+        '³' => (AltGr, N3),
+        '$' => (Shift, N4),
+        '%' => (Shift, N5),
+        '&' => (Shift, N6),
+        '/' => (Shift, N7),
+        '{' => (AltGr, N7),
+        '(' => (Shift, N8),
+        '[' => (AltGr, N8),
+        ')' => (Shift, N9),
+        ']' => (AltGr, N9),
+        '=' => (Shift, N0),
+        '}' => (AltGr, N0),
+        'ß' => (Base, Minus),
+        '?' => (Shift, Minus),
+        '\\' => (AltGr, Minus),
+        '@' => (AltGr, Q),
+        '€' => (AltGr, E),
+        'µ' => (AltGr, M),
+        'ü' => (Base, LeftBracket),
+        'Ü' => (Shift, LeftBracket),
+        '+' => (Base, RightBracket),
+        '*' => (Shift, RightBracket),
+        '~' => (AltGr, RightBracket),
+        'ö' => (Base, Semicolon),
+        'Ö' => (Shift, Semicolon),
+        'ä' => (Base, Quote),
+        'Ä' => (Shift, Quote),
+        '#' => (Base, NonUSHash),
+        '\'' => (Shift, NonUSHash),
+        ',' => (Base, Comma),
+        ';' => (Shift, Comma),
+        '.' => (Base, Dot),
+        ':' => (Shift, Dot),
+        '-' => (Base, Slash),
+        '_' => (Shift, Slash),
+        '<' => (Base, NonUSBackslash),
+        '>' => (Shift, NonUSBackslash),
+        '|' => (AltGr, NonUSBackslash),
+        _ => return None,
+    })
+}
+
+fn fr(c: char) -> Option<Press> {
+    use Level::*;
+    // Digits are on shift level in AZERTY.
+    if let Some(code) = digit(c) {
+        return Some((Shift, code));
+    }
+    Some(match c {
+        '²' => (Base, Grave),
+        '&' => (Base, N1),
+        'é' => (Base, N2),
+        '"' => (Base, N3),
+        '#' => (AltGr, N3),
+        '\'' => (Base, N4),
+        '{' => (AltGr, N4),
+        '(' => (Base, N5),
+        '[' => (AltGr, N5),
+        '-' => (Base, N6),
+        '|' => (AltGr, N6),
+        'è' => (Base, N7),
+        '_' => (Base, N8),
+        '\\' => (AltGr, N8),
+        'ç' => (Base, N9),
+        'à' => (Base, N0),
+        '@' => (AltGr, N0),
+        ')' => (Base, Minus),
+        '°' => (Shift, Minus),
+        ']' => (AltGr, Minus),
+        '=' => (Base, Equal),
+        '+' => (Shift, Equal),
+        '}' => (AltGr, Equal),
+        '€' => (AltGr, E),
+        '$' => (Base, RightBracket),
+        '£' => (Shift, RightBracket),
+        '*' => (Base, NonUSHash),
+        'µ' => (Shift, NonUSHash),
+        'ù' => (Base, Quote),
+        '%' => (Shift, Quote),
+        ',' => (Base, M),
+        '?' => (Shift, M),
+        ';' => (Base, Comma),
+        '.' => (Shift, Comma),
+        ':' => (Base, Dot),
+        '/' => (Shift, Dot),
+        '!' => (Base, Slash),
+        '§' => (Shift, Slash),
+        '<' => (Base, NonUSBackslash),
+        '>' => (Shift, NonUSBackslash),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Layout;
+    use crate::keyboard::Macro;
+
+    fn translate(layout: Layout, s: &str) -> anyhow::Result<Macro> {
+        let mut macro_ = s.parse().unwrap();
+        layout.translate(&mut macro_)?;
+        Ok(macro_)
+    }
+
+    #[test]
+    fn translate_shifted_characters() {
+        assert_eq!(translate(Layout::Us, "!").unwrap(), "shift-1".parse().unwrap());
+        assert_eq!(translate(Layout::Us, "a,:,/").unwrap(), "a,shift-semicolon,slash".parse().unwrap());
+        assert_eq!(translate(Layout::Us, "ctrl-+").unwrap(), "ctrl-shift-equal".parse().unwrap());
+        assert_eq!(translate(Layout::Uk, "@").unwrap(), "shift-quote".parse().unwrap());
+        assert_eq!(translate(Layout::Uk, "£").unwrap(), "shift-3".parse().unwrap());
+    }
+
+    #[test]
+    fn translate_altgr_characters() {
+        assert_eq!(translate(Layout::De, "@").unwrap(), "ralt-q".parse().unwrap());
+        assert_eq!(translate(Layout::De, "{").unwrap(), "ralt-7".parse().unwrap());
+        assert_eq!(translate(Layout::Fr, "€").unwrap(), "ralt-e".parse().unwrap());
+    }
+
+    #[test]
+    fn translate_dead_keys() {
+        assert_eq!(translate(Layout::De, "é").unwrap(), "equal,e".parse().unwrap());
+        assert_eq!(translate(Layout::De, "È").unwrap(), "shift-equal,shift-e".parse().unwrap());
+        assert_eq!(translate(Layout::De, "^").unwrap(), "grave,space".parse().unwrap());
+        // Characters available on keys directly don't use dead keys.
+        assert_eq!(translate(Layout::De, "ö").unwrap(), "semicolon".parse().unwrap());
+        // Base letter is taken from the same layout.
+        assert_eq!(translate(Layout::Fr, "â").unwrap(), "leftbracket,q".parse().unwrap());
+        assert_eq!(translate(Layout::Fr, "ë").unwrap(), "shift-leftbracket,e".parse().unwrap());
+    }
+
+    #[test]
+    fn reject_untypeable_character() {
+        assert!(translate(Layout::Us, "£").is_err());
+        assert!(translate(Layout::Us, "é").is_err());
+        assert!(translate(Layout::De, "ñ").is_err());
+    }
+
+    #[test]
+    fn show_key_legends() {
+        let legends = |layout: Layout, s: &str| layout.show_legends(&s.parse().unwrap()).to_string();
+        assert_eq!(legends(Layout::De, "ctrl-semicolon,y,a"), "ctrl-ö,z,a");
+        assert_eq!(legends(Layout::Fr, "1,m,semicolon"), "&,',',m");
+        assert_eq!(legends(Layout::Us, "shift-slash,enter"), "shift-/,enter");
+        // Dead key has no legend of its own.
+        assert_eq!(legends(Layout::De, "equal"), "equal");
+    }
+}
@@ -0,0 +1,405 @@
+//! Listens to key presses keyboard sends to host computer.
+//!
+//! Only Linux is supported: key events are read from evdev devices
+//! (`/dev/input/event*`) belonging to keyboard. Devices aren't grabbed,
+//! so the rest of the system still gets all key presses.
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::keyboard::{Accord, Code, Modifier, Modifiers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Released,
+    Pressed,
+    /// Auto-repeat generated by OS while key is held.
+    Repeated,
+}
+
+/// Key as seen by host: either modifier or HID usage code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKey {
+    Modifier(Modifier),
+    Code(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyEvent {
+    pub key: HostKey,
+    pub state: KeyState,
+}
+
+pub struct Listener {
+    events: mpsc::Receiver<Result<KeyEvent>>,
+}
+
+impl Listener {
+    /// Starts listening to key events of all input devices with one of given
+    /// vendor and product ID pairs.
+    pub fn open(ids: &[(u16, u16)]) -> Result<Self> {
+        Self::start(ids, false)
+    }
+
+    /// Same as `open`, but key presses aren't passed to the rest of the system
+    /// while listener exists.
+    pub fn open_exclusive(ids: &[(u16, u16)]) -> Result<Self> {
+        Self::start(ids, true)
+    }
+
+    /// Opens input devices of keyboard for `from_devices`.
+    #[cfg(target_os = "linux")]
+    pub fn open_devices(ids: &[(u16, u16)]) -> Result<Vec<std::fs::File>> {
+        platform::open_devices(ids, false)
+    }
+
+    /// Starts listening to already opened input devices, like ones opened by
+    /// more privileged process.
+    #[cfg(target_os = "linux")]
+    pub fn from_devices(devices: Vec<std::fs::File>) -> Self {
+        let (sender, events) = mpsc::channel();
+        for device in devices {
+            platform::spawn_reader(device, sender.clone());
+        }
+        Self { events }
+    }
+
+    fn start(ids: &[(u16, u16)], exclusive: bool) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        for device in platform::open_devices(ids, exclusive)? {
+            platform::spawn_reader(device, sender.clone());
+        }
+        Ok(Self { events })
+    }
+
+    /// Waits for next key event.
+    pub fn next_event(&self) -> Result<KeyEvent> {
+        self.events.recv().map_err(|_| anyhow!("all input devices are closed"))?
+    }
+
+    /// Waits for next key event for at most given time.
+    pub fn next_event_timeout(&self, timeout: Duration) -> Result<Option<KeyEvent>> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => event.map(Some),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(anyhow!("all input devices are closed")),
+        }
+    }
+}
+
+/// Collects key events into chords.
+#[derive(Debug, Default)]
+pub struct ChordTracker {
+    modifiers: Modifiers,
+    pressed: usize,
+    chord: Option<Accord>,
+}
+
+impl ChordTracker {
+    /// Feeds key event, returns chord once all its keys are released.
+    pub fn feed(&mut self, event: &KeyEvent) -> Option<Accord> {
+        match (event.key, event.state) {
+            (_, KeyState::Repeated) => {}
+            (HostKey::Modifier(m), KeyState::Pressed) => self.modifiers |= m,
+            (HostKey::Modifier(m), KeyState::Released) => self.modifiers -= m,
+            (HostKey::Code(code), KeyState::Pressed) => {
+                self.pressed += 1;
+                self.chord = Some(Accord::new(self.modifiers, Some(Code::from_value(code))));
+            }
+            (HostKey::Code(_), KeyState::Released) => self.pressed = self.pressed.saturating_sub(1),
+        }
+
+        if self.pressed == 0 && self.modifiers.is_empty() {
+            self.chord.take()
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    Slow,
+    Fast,
+}
+
+/// Classifies repeated chords, like ones sent by turned knob, by their rate.
+#[derive(Debug)]
+pub struct RateClassifier {
+    /// Chords repeated more often than this are fast.
+    fast_interval: Duration,
+    last_seen: Vec<(Accord, Instant)>,
+}
+
+impl RateClassifier {
+    pub fn new(fast_interval: Duration) -> Self {
+        Self { fast_interval, last_seen: vec![] }
+    }
+
+    /// Registers chord received at given time and returns its rate.
+    pub fn classify(&mut self, chord: &Accord, time: Instant) -> Speed {
+        let previous = match self.last_seen.iter_mut().find(|(seen, _)| seen == chord) {
+            Some((_, last)) => Some(std::mem::replace(last, time)),
+            None => {
+                self.last_seen.push((*chord, time));
+                None
+            }
+        };
+        match previous {
+            Some(previous) if time.saturating_duration_since(previous) < self.fast_interval => Speed::Fast,
+            _ => Speed::Slow,
+        }
+    }
+}
+
+/// Drops chords repeated sooner than allowed, like ones caused by key
+/// bounce, so host action fires once per press. OS auto-repeat doesn't
+/// get here, `ChordTracker` ignores it.
+#[derive(Debug)]
+pub struct Debouncer {
+    default: Duration,
+    /// Chords with interval other than default one.
+    intervals: Vec<(Accord, Duration)>,
+    last_accepted: Vec<(Accord, Instant)>,
+}
+
+impl Debouncer {
+    pub fn new(default: Duration, intervals: Vec<(Accord, Duration)>) -> Self {
+        Self { default, intervals, last_accepted: vec![] }
+    }
+
+    /// Registers chord received at given time, returns whether it is new press.
+    pub fn accept(&mut self, chord: &Accord, time: Instant) -> bool {
+        let interval = self.intervals.iter().find(|(c, _)| c == chord).map_or(self.default, |(_, interval)| *interval);
+        match self.last_accepted.iter_mut().find(|(c, _)| c == chord) {
+            Some((_, last)) if time.saturating_duration_since(*last) < interval => false,
+            Some((_, last)) => {
+                *last = time;
+                true
+            }
+            None => {
+                self.last_accepted.push((*chord, time));
+                true
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::fs::File;
+    use std::io::Read as _;
+    use std::os::fd::AsRawFd as _;
+    use std::sync::mpsc::Sender;
+
+    use anyhow::{anyhow, ensure, Context as _, Result};
+    use log::debug;
+
+    use super::{HostKey, KeyEvent, KeyState};
+    use crate::keyboard::Modifier;
+
+    const EV_KEY: u16 = 0x01;
+    /// `_IOW('E', 0x90, int)` from `linux/input.h`.
+    const EVIOCGRAB: libc::c_ulong = 0x40044590;
+
+    /// Opens input devices of keyboard, grabbing them if `exclusive`.
+    pub fn open_devices(ids: &[(u16, u16)], exclusive: bool) -> Result<Vec<File>> {
+        let devices = std::fs::read_to_string("/proc/bus/input/devices")
+            .context("read list of input devices")?;
+        let paths = find_event_devices(&devices, ids);
+        ensure!(!paths.is_empty(), "no input devices of keyboard found, is it connected?");
+
+        paths.iter().map(|path| {
+            debug!("listen to {path}");
+            let file = File::open(path)
+                .with_context(|| format!("open {path}, you may need to run as root or join 'input' group"))?;
+            if exclusive {
+                // Grab is released when file is closed.
+                let res = unsafe { libc::ioctl(file.as_raw_fd(), EVIOCGRAB as _, 1 as libc::c_int) };
+                ensure!(res == 0, "grab {path}: {}", std::io::Error::last_os_error());
+            }
+            Ok(file)
+        }).collect()
+    }
+
+    pub fn spawn_reader(device: File, sender: Sender<Result<KeyEvent>>) {
+        std::thread::spawn(move || read_events(device, sender));
+    }
+
+    /// Finds event devices in `/proc/bus/input/devices` contents.
+    pub fn find_event_devices(devices: &str, ids: &[(u16, u16)]) -> Vec<String> {
+        devices.split("\n\n").filter_map(|block| {
+            let id = block.lines().find_map(|line| line.strip_prefix("I: "))?;
+            let field = |name: &str| id.split_whitespace()
+                .find_map(|f| f.strip_prefix(name))
+                .and_then(|v| u16::from_str_radix(v, 16).ok());
+            if !ids.contains(&(field("Vendor=")?, field("Product=")?)) {
+                return None;
+            }
+            let handlers = block.lines().find_map(|line| line.strip_prefix("H: Handlers="))?;
+            let handlers: Vec<&str> = handlers.split_whitespace().collect();
+            if !handlers.contains(&"kbd") {
+                return None;
+            }
+            let event = handlers.iter().find(|h| h.starts_with("event"))?;
+            Some(format!("/dev/input/{event}"))
+        }).collect()
+    }
+
+    fn read_events(mut file: File, sender: Sender<Result<KeyEvent>>) {
+        // struct input_event { struct timeval time; u16 type; u16 code; i32 value; }
+        const LONG: usize = std::mem::size_of::<std::ffi::c_long>();
+        let mut buf = [0u8; 2 * LONG + 8];
+        loop {
+            if let Err(err) = file.read_exact(&mut buf) {
+                let _ = sender.send(Err(anyhow!(err).context("read input event")));
+                return;
+            }
+            let rest = &buf[2 * LONG..];
+            let type_ = u16::from_ne_bytes([rest[0], rest[1]]);
+            let code = u16::from_ne_bytes([rest[2], rest[3]]);
+            let value = i32::from_ne_bytes([rest[4], rest[5], rest[6], rest[7]]);
+            if type_ != EV_KEY {
+                continue;
+            }
+
+            let Some(key) = host_key(code) else {
+                debug!("unknown key code {code}");
+                continue;
+            };
+            let state = match value {
+                0 => KeyState::Released,
+                1 => KeyState::Pressed,
+                _ => KeyState::Repeated,
+            };
+            if sender.send(Ok(KeyEvent { key, state })).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn host_key(code: u16) -> Option<HostKey> {
+        Some(match code {
+            29 => HostKey::Modifier(Modifier::Ctrl),
+            42 => HostKey::Modifier(Modifier::Shift),
+            56 => HostKey::Modifier(Modifier::Alt),
+            125 => HostKey::Modifier(Modifier::Win),
+            97 => HostKey::Modifier(Modifier::RightCtrl),
+            54 => HostKey::Modifier(Modifier::RightShift),
+            100 => HostKey::Modifier(Modifier::RightAlt),
+            126 => HostKey::Modifier(Modifier::RightWin),
+            _ => HostKey::Code(hid_usage(code)?),
+        })
+    }
+
+    /// Converts Linux key code to HID usage code, see `hid_keyboard` table in
+    /// Linux `drivers/hid/hid-input.c`.
+    fn hid_usage(code: u16) -> Option<u8> {
+        const USAGES: [u16; 0x74] = [
+              0,   0,   0,   0,  30,  48,  46,  32,  18,  33,  34,  35,  23,  36,  37,  38,
+             50,  49,  24,  25,  16,  19,  31,  20,  22,  47,  17,  45,  21,  44,   2,   3,
+              4,   5,   6,   7,   8,   9,  10,  11,  28,   1,  14,  15,  57,  12,  13,  26,
+             27,  43,  43,  39,  40,  41,  51,  52,  53,  58,  59,  60,  61,  62,  63,  64,
+             65,  66,  67,  68,  87,  88,  99,  70, 119, 110, 102, 104, 111, 107, 109, 106,
+            105, 108, 103,  69,  98,  55,  74,  78,  96,  79,  80,  81,  75,  76,  77,  71,
+             72,  73,  82,  83,  86, 127, 116, 117, 183, 184, 185, 186, 187, 188, 189, 190,
+            191, 192, 193, 194,
+        ];
+        if code == 0 {
+            return None;
+        }
+        USAGES.iter().position(|c| *c == code).map(|usage| usage as u8)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use std::fs::File;
+    use std::sync::mpsc::Sender;
+
+    use anyhow::{bail, Result};
+
+    use super::KeyEvent;
+
+    pub fn open_devices(_ids: &[(u16, u16)], _exclusive: bool) -> Result<Vec<File>> {
+        bail!("listening to key presses is only supported on Linux")
+    }
+
+    pub fn spawn_reader(_device: File, _sender: Sender<Result<KeyEvent>>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ChordTracker, Debouncer, HostKey, KeyEvent, KeyState, RateClassifier, Speed};
+    use crate::keyboard::{Accord, Modifier};
+
+    fn event(key: HostKey, state: KeyState) -> KeyEvent {
+        KeyEvent { key, state }
+    }
+
+    #[test]
+    fn track_chords() {
+        let mut tracker = ChordTracker::default();
+        let ctrl = HostKey::Modifier(Modifier::Ctrl);
+        let a = HostKey::Code(0x04);
+
+        assert_eq!(tracker.feed(&event(ctrl, KeyState::Pressed)), None);
+        assert_eq!(tracker.feed(&event(a, KeyState::Pressed)), None);
+        assert_eq!(tracker.feed(&event(a, KeyState::Repeated)), None);
+        assert_eq!(tracker.feed(&event(a, KeyState::Released)), None);
+        assert_eq!(tracker.feed(&event(ctrl, KeyState::Released)), Some("ctrl-a".parse::<Accord>().unwrap()));
+
+        // Lone modifier isn't a chord.
+        assert_eq!(tracker.feed(&event(ctrl, KeyState::Pressed)), None);
+        assert_eq!(tracker.feed(&event(ctrl, KeyState::Released)), None);
+    }
+
+    #[test]
+    fn classify_rate() {
+        let mut classifier = RateClassifier::new(Duration::from_millis(100));
+        let (cw, ccw) = ("f13".parse().unwrap(), "f14".parse().unwrap());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(classifier.classify(&cw, at(0)), Speed::Slow);
+        assert_eq!(classifier.classify(&cw, at(50)), Speed::Fast);
+        assert_eq!(classifier.classify(&ccw, at(60)), Speed::Slow);
+        assert_eq!(classifier.classify(&cw, at(300)), Speed::Slow);
+    }
+
+    #[test]
+    fn debounce_chords() {
+        let (key, knob) = ("f13".parse().unwrap(), "f14".parse().unwrap());
+        let mut debouncer = Debouncer::new(Duration::from_millis(100), vec![(knob, Duration::ZERO)]);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(debouncer.accept(&key, at(0)));
+        assert!(!debouncer.accept(&key, at(30)));
+        assert!(debouncer.accept(&key, at(150)));
+        assert!(debouncer.accept(&knob, at(150)));
+        assert!(debouncer.accept(&knob, at(151)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn find_event_devices() {
+        let devices = indoc::indoc! {"
+            I: Bus=0003 Vendor=1189 Product=8890 Version=0111
+            N: Name=\"wch.cn CH57x\"
+            H: Handlers=sysrq kbd leds event5
+
+            I: Bus=0003 Vendor=1189 Product=8890 Version=0111
+            N: Name=\"wch.cn CH57x Mouse\"
+            H: Handlers=mouse2 event6
+
+            I: Bus=0003 Vendor=046d Product=c52b Version=0111
+            H: Handlers=sysrq kbd event3
+        "};
+        assert_eq!(super::platform::find_event_devices(devices, &[(0x1189, 0x8890)]), ["/dev/input/event5"]);
+    }
+}
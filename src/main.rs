@@ -1,29 +1,59 @@
+mod action;
 mod config;
 mod consts;
+mod convert;
+mod csv;
+mod daemon;
+mod danger;
+mod diagnostic;
+mod journal;
+mod diff;
+mod export;
 mod keyboard;
+mod layout;
+mod listener;
+mod model;
 mod options;
+mod output;
 mod parse;
+mod paths;
+mod protocol;
+mod repl;
+mod report;
+mod shortcuts;
+mod term;
+mod verify;
+mod watch;
 
-use std::io::{BufReader, Read, StdinLock};
+use std::ffi::OsString;
+use std::io::{BufReader, IsTerminal as _, Read, StdinLock};
+use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::config::Config;
-use crate::consts::PRODUCT_IDS;
+use crate::config::{Config, DeviceSettings, FlatLayer, Host, Knob};
+use crate::consts::{DEVICE_IDS, VENDOR_ID};
+use crate::journal::Journal;
+use crate::listener::{Debouncer, Listener};
+use crate::model::{Model, MODELS};
+use crate::protocol::Protocol;
+use crate::report::{Outcome, UploadReport};
 use crate::keyboard::{
-    k884x, k8890, Keyboard, KnobAction, MediaCode, Modifier, MouseAction, MouseButton,
+    k884x, k8890, quirks, Key, Keyboard, Quirk, Quirks, Macro, MediaCode, Modifier, MouseAction, MouseButton,
     WellKnownCode,
 };
-use crate::options::{Command, LedCommand};
-use crate::{keyboard::Key, options::Options};
+use crate::options::{Command, ConfigCommand, LedCommand};
+use crate::term::Style;
+use crate::options::Options;
 
-use anyhow::{anyhow, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use indoc::indoc;
 use itertools::Itertools;
-use log::debug;
-use options::{ConfigParams, DevelOptions};
+use log::{debug, info, warn};
+use options::{ConfigParams, DevelOptions, DeviceSelector, Speed, UploadParams, ValidateOutput};
 use rusb::{Context, Device, DeviceDescriptor, TransferType};
 
 use anyhow::Context as _;
-use clap::Parser as _;
+use clap::{CommandFactory as _, Parser as _};
 use rusb::UsbContext as _;
 use strum::EnumMessage as _;
 use strum::IntoEnumIterator as _;
@@ -32,15 +62,25 @@ fn main() -> Result<()> {
     env_logger::init();
     let options = Options::parse();
 
-    match options.command {
+    term::init(options.color);
+    if options.version {
+        print_version(options.verbose);
+        return Ok(());
+    }
+    let Some(command) = options.command else {
+        Options::command().error(clap::error::ErrorKind::MissingSubcommand, "command is required").exit()
+    };
+
+    match command {
         Command::ShowKeys => {
-            println!("Modifiers: ");
+            let heading_color = term::stdout_color();
+            println!("{}", term::paint("Modifiers: ", Style::Heading, heading_color));
             for m in Modifier::iter() {
                 println!(" - {}", m.get_serializations().iter().join(" / "));
             }
 
             println!();
-            println!("Keys:");
+            println!("{}", term::paint("Keys:", Style::Heading, heading_color));
             for c in WellKnownCode::iter() {
                 println!(" - {c}");
             }
@@ -49,57 +89,297 @@ fn main() -> Result<()> {
             println!("Custom key syntax (use decimal code): <110>");
 
             println!();
-            println!("Media keys:");
+            println!("{}", term::paint("Media keys:", Style::Heading, heading_color));
             for c in MediaCode::iter() {
                 println!(" - {}", c.get_serializations().iter().join(" / "));
             }
 
             println!();
-            println!("Mouse actions:");
-            println!(" - {}", MouseAction::WheelDown);
-            println!(" - {}", MouseAction::WheelUp);
+            println!("{}", term::paint("Mouse actions:", Style::Heading, heading_color));
+            println!(" - {} (or {}*N to scroll by N notches)", MouseAction::WheelDown(1), MouseAction::WheelDown(1));
+            println!(" - {} (or {}*N to scroll by N notches)", MouseAction::WheelUp(1), MouseAction::WheelUp(1));
+            println!(" - {} / {} (horizontal, sent as shift with wheel)", MouseAction::WheelLeft(1), MouseAction::WheelRight(1));
             for b in MouseButton::iter() {
                 println!(" - {b}");
             }
         }
 
+        Command::Validate(params) if params.output == ValidateOutput::Json => {
+            let diagnostics = diagnostic::check(load_config(&params.config).context("load mapping config"));
+            println!("{}", serde_json::to_string_pretty(&diagnostics)?);
+            let failed = diagnostics.iter().any(|diagnostic| diagnostic.severity == diagnostic::Severity::Error);
+            ensure!(!failed, "config is invalid");
+        }
+
         Command::Validate(params) => {
-            let config: Config = load_config(&params)
-                .context("load mapping config")?;
-            let _ = config.render().context("render mappings config")?;
-            println!("config is valid 👌")
+            let validate = || -> Result<()> {
+                let config: Config = load_config(&params.config)
+                    .context("load mapping config")?;
+                let _ = config.render().context("render mappings config")?;
+                Ok(())
+            };
+
+            if params.watch {
+                let path = config_path(&params.config)
+                    .ok_or_else(|| anyhow!("--watch needs config file, it can't be read from stdin"))?;
+                watch::watch(&path, || {
+                    let time = chrono::Local::now().format("%H:%M:%S");
+                    match validate() {
+                        Ok(()) => println!("[{time}] {}", term::paint("config is valid 👌", Style::Success, term::stdout_color())),
+                        Err(err) => println!("[{time}] {}", term::paint(format!("{err:#}"), Style::Error, term::stdout_color())),
+                    }
+                });
+            }
+
+            validate()?;
+            println!("{}", term::paint("config is valid 👌", Style::Success, term::stdout_color()))
         }
 
         Command::Upload(params) => {
-            let config: Config = load_config(&params)
+            let mut config: Config = load_config(&params.config)
                 .context("load mapping config")?;
+            let devices = if params.all {
+                ensure!(options.devel_options.address.is_none() && options.devel_options.device.is_none(),
+                        "--all uploads to every keyboard, it can't be used with --address or --device");
+                let devices = matching_devices(&options.devel_options)?;
+                ensure!(!devices.is_empty(), "CH57x keyboard device not found. Use --vendor-id and --product-id to override settings.");
+                devices
+            } else {
+                vec![find_device(&options.devel_options).context("find USB device")?]
+            };
+            if params.emulate_unsupported {
+                let protocol = device_protocol(&options.devel_options, devices[0].2)?;
+                let emulated = config.emulate_unsupported(protocol)?.len();
+                if emulated > 0 {
+                    term::warning(format!("{emulated} key(s) trigger host actions emulating {protocol} limits, \
+                                           run `daemon --emulate-unsupported` for them to work"));
+                }
+            }
+            let model = config.model;
+            let settings = config.device.clone();
             let layers = config.render().context("render mapping config")?;
+            let dangerous = danger::find(&layers);
+            for binding in &dangerous {
+                term::warning(binding);
+            }
+            ensure!(dangerous.is_empty() || params.allow_dangerous,
+                    "config has {} dangerous binding(s), upload with --allow-dangerous if they are intended", dangerous.len());
 
-            let mut keyboard = open_keyboard(&options.devel_options)?;
-
-            // Apply keyboard mapping.
-            for (layer_idx, layer) in layers.iter().enumerate() {
-                for (button_idx, macro_) in layer.buttons.iter().enumerate() {
-                    if let Some(macro_) = macro_ {
-                        keyboard.bind_key(layer_idx as u8, Key::Button(button_idx as u8), macro_)
-                            .context("bind key")?;
+            if !params.all {
+                upload_device(&devices[0], model, &settings, &layers, &params, &options.devel_options)?;
+                return Ok(());
+            }
+            let mut failed = 0;
+            for found in &devices {
+                let (device, _, product_id) = found;
+                let name = format!("keyboard {}:{} ({})", device.bus_number(), device.address(), model_name(*product_id));
+                println!("Uploading to {name}...");
+                match upload_device(found, model, &settings, &layers, &params, &options.devel_options) {
+                    Ok(()) => println!("✓ {name} is programmed"),
+                    Err(err) => {
+                        failed += 1;
+                        println!("✗ {name} failed: {err:#}");
                     }
                 }
+            }
+            ensure!(failed == 0, "{failed} of {} keyboard(s) failed", devices.len());
+        }
 
-                for (knob_idx, knob) in layer.knobs.iter().enumerate() {
-                    if let Some(macro_) = &knob.ccw {
-                        keyboard.bind_key(layer_idx as u8, Key::Knob(knob_idx as u8, KnobAction::RotateCCW), macro_)?;
-                    }
-                    if let Some(macro_) = &knob.press {
-                        keyboard.bind_key(layer_idx as u8, Key::Knob(knob_idx as u8, KnobAction::Press), macro_)?;
+        Command::Dev(params) => {
+            let path = config_path(&params)
+                .ok_or_else(|| anyhow!("dev needs config file, it can't be read from stdin"))?;
+            let (device, desc, id_product) = find_device(&options.devel_options).context("find USB device")?;
+            let mut keyboard = open_device(&device, &desc, id_product, &options.devel_options)?;
+            // Bindings can't be read back, so the first upload is full and
+            // later ones are compared with what was uploaded last.
+            let mut uploaded: Option<Vec<FlatLayer>> = None;
+            watch::watch(&path, || {
+                let time = chrono::Local::now().format("%H:%M:%S");
+                let color = term::stdout_color();
+                match dev_upload(keyboard.as_mut(), &params, id_product, &mut uploaded) {
+                    Ok(0) => println!("[{time}] {}", term::paint("config is valid, no keys changed", Style::Success, color)),
+                    Ok(n) => println!("[{time}] {}", term::paint(format!("{n} key(s) uploaded 👌"), Style::Success, color)),
+                    Err(err) => println!("[{time}] {}", term::paint(format!("{err:#}"), Style::Error, color)),
+                }
+            });
+        }
+
+        Command::Daemon(params) => {
+            let mut config: Config = load_config(&params.config)
+                .context("load mapping config")?;
+            let device_ids = device_ids(&options.devel_options);
+            let mut actions = config.host_actions()?;
+            let debouncer = Debouncer::new(Duration::from_millis(params.min_interval), config.min_intervals()?);
+            let emulated = if params.emulate_unsupported {
+                let protocol = match (options.devel_options.protocol, config.model) {
+                    (Some(protocol), _) => protocol,
+                    (None, Some(model)) => model.protocol(),
+                    (None, None) => device_ids.iter().filter_map(|(_, id)| Protocol::for_product(*id)).dedup().exactly_one()
+                        .map_err(|_| anyhow!("keyboard protocol is unknown, set `model` in config or use --product-id"))?,
+                };
+                actions.extend(config.emulate_unsupported(protocol)?);
+                Some(protocol)
+            } else {
+                None
+            };
+            // Config is rendered at start, so its errors don't wait for reconnection.
+            let reupload = if params.reupload {
+                let mut config: Config = load_config(&params.config).context("load mapping config")?;
+                if let Some(protocol) = emulated {
+                    config.emulate_unsupported(protocol)?;
+                }
+                Some((config.device.clone(), config.render().context("render mapping config")?))
+            } else {
+                None
+            };
+            let mut on_reconnect = || -> Result<()> {
+                let Some((settings, layers)) = &reupload else { return Ok(()) };
+                // Give just reconnected keyboard a moment to initialize.
+                std::thread::sleep(Duration::from_millis(500));
+                let mut keyboard = open_keyboard(&options.devel_options)?;
+                configure(keyboard.as_mut(), settings)?;
+                keyboard.bind_all(&all_bindings(layers))?;
+                info!("config is uploaded to reconnected keyboard");
+                Ok(())
+            };
+            daemon::run(actions, debouncer, &config.sandbox, &device_ids,
+                        params.user.as_deref(), &mut on_reconnect)?;
+        }
+
+        Command::Diff(params) => {
+            let load = |path: &OsString| -> Result<Config> {
+                load_config(&ConfigParams { config_path: Some(path.clone()), model: params.model.clone(), orientation: None })
+                    .with_context(|| format!("load mapping config {}", path.to_string_lossy()))
+            };
+            let (old, new) = (load(&params.old_config_path)?, load(&params.new_config_path)?);
+            ensure!(old.rows == new.rows && old.columns == new.columns && old.knobs == new.knobs,
+                    "configs are for keyboards of different size");
+            let columns = new.columns as usize;
+            let mut old = old.render().context("render old mapping config")?;
+            let mut new = new.render().context("render new mapping config")?;
+            if let Some(layout) = params.legends {
+                for layer in old.iter_mut().chain(new.iter_mut()) {
+                    let knobs = layer.knobs.iter_mut().flat_map(|knob| [&mut knob.ccw, &mut knob.press, &mut knob.cw]);
+                    for macro_ in layer.buttons.iter_mut().chain(knobs).flatten() {
+                        *macro_ = layout.show_legends(macro_);
                     }
-                    if let Some(macro_) = &knob.cw {
-                        keyboard.bind_key(layer_idx as u8, Key::Knob(knob_idx as u8, KnobAction::RotateCW), macro_)?;
+                }
+            }
+            print!("{}", diff::render(&old, &new, columns, params.style, term::stdout_color()));
+        }
+
+        Command::Verify(params) => {
+            ensure!(params.interactive,
+                    "keyboard bindings can't be read back, so only interactive verification is supported, use --interactive");
+            let config: Config = load_config(&params.config)
+                .context("load mapping config")?;
+            let layers = config.render().context("render mapping config")?;
+            // Key presses are grabbed, so they don't get into terminal or other apps.
+            let listener = Listener::open_exclusive(&device_ids(&options.devel_options))?;
+            verify::run(&layers, &listener)?;
+        }
+
+        Command::Config(ConfigCommand::Path { open }) => {
+            let path = paths::default_config_path()
+                .ok_or_else(|| anyhow!("can't determine config directory, set XDG_CONFIG_HOME"))?;
+            println!("{}", path.display());
+            if !path.is_file() {
+                eprintln!("File doesn't exist yet, you may copy example-mapping.yaml there.");
+            } else if open {
+                paths::open(&path)?;
+            }
+        }
+
+        Command::Examples(params) => {
+            match params.model.as_deref().and_then(Model::find) {
+                Some(model) => print!("{}", model.example_config()),
+                None => {
+                    println!("Known models (rows x columns as keyboard is held with knobs on the right):");
+                    for model in MODELS {
+                        println!(" - {}: {}", model.name, model.description);
                     }
+                    println!();
+                    println!("Print example config with `examples <MODEL>`.");
                 }
             }
         }
 
+        Command::Convert(params) => {
+            let config = load_config(&params.config).context("load mapping config")?;
+            let conversion = convert::convert(config, params.to)?;
+            for warning in &conversion.warnings {
+                term::warning(warning);
+            }
+            print!("{}", conversion.config);
+        }
+
+        Command::SendRaw(params) => {
+            if !params.yes {
+                ensure!(std::io::stdin().is_terminal(), "pass --yes to send raw messages non-interactively");
+                eprint!("Raw messages may leave keyboard misconfigured until config is uploaded again. Send {} message(s)? [y/N] ",
+                        params.messages.len());
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer).context("read answer")?;
+                ensure!(answer.trim().eq_ignore_ascii_case("y"), "nothing is sent");
+            }
+            let mut keyboard = open_keyboard(&options.devel_options)?;
+            for message in &params.messages {
+                keyboard.send(message)?;
+            }
+        }
+
+        Command::Export(params) => {
+            let config = load_config(&params.config).context("load mapping config")?;
+            let layers = config.render().context("render mapping config")?;
+            print!("{}", export::export(&layers, params.format));
+        }
+
+        Command::Import(params) => {
+            let text = std::fs::read_to_string(&params.csv_path)
+                .with_context(|| format!("read {}", params.csv_path.to_string_lossy()))?;
+            let model = Model::find(&params.model).expect("model is checked by clap");
+            print!("{}", csv::import(&text, model)?);
+        }
+
+        Command::ListDevices(params) => {
+            list_devices(&options.devel_options, params.verbose)?;
+        }
+
+        Command::Info => {
+            let (device, desc, id_product) = find_device(&options.devel_options).context("find USB device")?;
+            print_info(&device, &desc, id_product, options.devel_options.protocol);
+        }
+
+        Command::Repl => {
+            let (device, desc, id_product) = find_device(&options.devel_options).context("find USB device")?;
+            let mut keyboard = open_device(&device, &desc, id_product, &options.devel_options)?;
+            let protocol = device_protocol(&options.devel_options, id_product)?;
+            repl::run(keyboard.as_mut(), protocol)?;
+        }
+
+        Command::DumpProtocol(params) => {
+            print!("{}", protocol::describe(params.protocol));
+        }
+
+        Command::Identify(params) => {
+            // Config is rendered before touching device to be sure bindings can be restored.
+            ensure!(config_path(&params).is_some(),
+                    "LED feedback isn't supported, so keys are temporarily rebound to identify keyboard; \
+                     give config to restore bindings from afterwards");
+            let layers = load_config(&params).context("load mapping config")?
+                .render().context("render mapping config")?;
+            let mut keyboard = open_keyboard(&options.devel_options)?;
+            identify(keyboard.as_mut(), &layers)?;
+        }
+
+        Command::Provision(params) => {
+            let config: Config = load_config(&params.config)
+                .context("load mapping config")?;
+            let settings = config.device.clone();
+            let layers = config.render().context("render mapping config")?;
+            provision(&layers, &settings, &options.devel_options, params.repeat)?;
+        }
+
         Command::Led(LedCommand { index }) => {
             let mut keyboard = open_keyboard(&options.devel_options)?;
             keyboard.set_led(index)?;
@@ -109,6 +389,283 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Prints version and, if verbose, what this build supports, for issue reports.
+fn print_version(verbose: bool) {
+    println!("ch57x-keyboard-tool {}", env!("CARGO_PKG_VERSION"));
+    if !verbose {
+        return;
+    }
+    let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+    let libusb = rusb::version();
+    println!("Platform: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    println!("USB backend: libusb {}.{}.{}{}", libusb.major(), libusb.minor(), libusb.micro(),
+             if cfg!(windows) { " with UsbDk" } else { "" });
+    println!("Host actions daemon and interactive verification: {}", yes_no(cfg!(target_os = "linux")));
+    println!("Searched IDs: {}",
+             DEVICE_IDS.iter().map(|(vendor_id, product_id)| format!("{vendor_id:04x}:{product_id:04x}")).join(", "));
+
+    println!("Protocols:");
+    for protocol in Protocol::iter() {
+        println!("  {protocol}: product IDs {}", protocol.product_ids().iter().map(|id| format!("{id:04x}")).join(", "));
+        println!("    key presses per macro: {}, delays: {}, wheel notches: {}",
+                 protocol.max_presses(), yes_no(protocol.supports_delays()), yes_no(protocol.supports_wheel_notches()));
+        println!("    macro kinds: {}", protocol.kinds().iter().map(|(kind, byte)| format!("{kind}={byte}")).join(", "));
+    }
+    println!("Quirks: {}; firmware revisions needing them: {}",
+             Quirk::iter().join(", "), quirks::known_revisions());
+    println!("Models: {}", MODELS.iter().map(|model| format!("{} ({})", model.name, model.protocol())).join(", "));
+}
+
+/// Applies keyboard settings given in config.
+fn configure(keyboard: &mut dyn Keyboard, settings: &DeviceSettings) -> Result<()> {
+    let quirks = keyboard.get_quirks() | settings.quirks.iter().copied().collect::<Quirks>();
+    if !quirks.is_empty() {
+        info!("applying quirks: {}", quirks.iter().join(", "));
+    }
+    keyboard.set_quirks(quirks);
+    Ok(())
+}
+
+/// Uploads bindings and reports outcome of each. Without `keep_going`
+/// bindings following failed one are skipped.
+fn upload(keyboard: &mut dyn Keyboard, layers: &[FlatLayer], keep_going: bool, mut journal: Option<&mut Journal>) -> UploadReport {
+    let mut report = UploadReport::default();
+    let mut failed = false;
+    // Each binding is encoded right before it's sent, so upload starts at
+    // once and memory doesn't grow with config size.
+    for (layer_idx, layer) in layers.iter().enumerate() {
+        for (key, macro_) in layer.bindings() {
+            if journal.as_ref().is_some_and(|journal| journal.is_done(layer_idx, key)) {
+                continue;
+            }
+            let outcome = if failed && !keep_going {
+                Outcome::Skipped
+            } else {
+                match keyboard.bind_key(layer_idx as u8, key, macro_) {
+                    Ok(()) => {
+                        if let Some(Err(err)) = journal.as_mut().map(|journal| journal.record(layer_idx, key)) {
+                            warn!("can't write upload journal: {err:#}");
+                        }
+                        Outcome::Ok
+                    }
+                    Err(err) => {
+                        failed = true;
+                        Outcome::Failed(format!("{err:#}"))
+                    }
+                }
+            };
+            report.push(layer_idx + 1, key, macro_, outcome);
+        }
+    }
+    report
+}
+
+/// Lists bound keys of all layers for `Keyboard::bind_all`.
+fn all_bindings(layers: &[FlatLayer]) -> Vec<(u8, Key, &Macro)> {
+    layers.iter().enumerate()
+        .flat_map(|(i, layer)| layer.bindings().map(move |(key, macro_)| (i as u8, key, macro_)))
+        .collect()
+}
+
+/// Uploads rendered config to found keyboard as `upload` command options say.
+fn upload_device(found: &(Device<Context>, DeviceDescriptor, u16), model: Option<&'static Model>, settings: &DeviceSettings,
+                 layers: &[FlatLayer], params: &UploadParams, devel_options: &DevelOptions) -> Result<()> {
+    let (device, desc, id_product) = found;
+    if let Some(model) = model.filter(|model| !model.matches_product(*id_product)) {
+        let ids = model.product_ids.iter().map(|id| format!("{id:04x}")).join(", ");
+        let message = format!("config is for model {}, product ID {ids}, but keyboard has product ID {id_product:04x} ({})",
+                              model.name, model_name(*id_product));
+        ensure!(params.force, "{message}; use --force to upload anyway");
+        term::warning(message);
+    }
+    let mut keyboard = open_device(device, desc, *id_product, devel_options)?;
+    if params.speed == Some(Speed::Safe) {
+        let quirks = keyboard.get_quirks() | Quirk::Paced | Quirk::DrainIn;
+        keyboard.set_quirks(quirks);
+    }
+    configure(keyboard.as_mut(), settings)?;
+    if params.canary {
+        canary(keyboard.as_mut(), layers, devel_options)?;
+    }
+    let mut journal = open_journal(device, desc, *id_product, layers);
+    let report = upload(keyboard.as_mut(), layers, params.keep_going, journal.as_mut());
+    print!("{}", report.render(term::stdout_color()));
+    report.into_result()?;
+    if let Some(Err(err)) = journal.map(Journal::finish) {
+        warn!("can't remove upload journal: {err:#}");
+    }
+    if params.reboot_after {
+        keyboard.reset().context("reset keyboard")?;
+    }
+    Ok(())
+}
+
+/// Starts journal of upload to keyboard, offering to resume upload of the
+/// same layers if it was interrupted. Upload goes on without journal if it
+/// can't be written.
+fn open_journal(device: &Device<Context>, desc: &DeviceDescriptor, id_product: u16, layers: &[FlatLayer]) -> Option<Journal> {
+    let dir = paths::state_dir()?;
+    let device_id = read_serial(device, desc)
+        .unwrap_or_else(|| format!("{id_product:04x} {}:{}", device.bus_number(), device.address()));
+    let path = journal::path(&dir, &device_id);
+    let fingerprint = journal::fingerprint(layers);
+    let mut done = Journal::interrupted(&path, &fingerprint).unwrap_or_default();
+    if !done.is_empty() {
+        let total: usize = layers.iter().map(|layer| layer.bindings().count()).sum();
+        let message = format!("Previous upload of this config was interrupted after {} of {total} bindings.", done.len());
+        let resume = std::io::stdin().is_terminal() && {
+            eprint!("{message} Resume it? [y/N] ");
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+        };
+        if !resume {
+            info!("{message} Starting over.");
+            done.clear();
+        }
+    }
+    match Journal::start(&path, &fingerprint, done) {
+        Ok(journal) => Some(journal),
+        Err(err) => {
+            warn!("upload can't be resumed if interrupted: {err:#}");
+            None
+        }
+    }
+}
+
+/// Loads config and uploads keys changed since `uploaded`, or all of them
+/// if nothing is uploaded yet. Returns number of uploaded keys.
+fn dev_upload(keyboard: &mut dyn Keyboard, params: &ConfigParams, id_product: u16,
+              uploaded: &mut Option<Vec<FlatLayer>>) -> Result<usize> {
+    let config: Config = load_config(params).context("load mapping config")?;
+    if let Some(model) = config.model.filter(|model| !model.matches_product(id_product)) {
+        bail!("config is for model {}, but keyboard has product ID {id_product:04x} ({})", model.name, model_name(id_product));
+    }
+    let settings = config.device.clone();
+    let layers = config.render().context("render mapping config")?;
+    let changes = match uploaded.take() {
+        Some(old) => diff::changes(&old, &layers),
+        None => {
+            configure(keyboard, &settings)?;
+            layers.clone()
+        }
+    };
+    let count = changes.iter().map(|layer| layer.bindings().count()).sum();
+    for binding in danger::find(&changes) {
+        term::warning(binding);
+    }
+    if count > 0 {
+        // Keyboard state is unknown after failure, so next upload is full.
+        let report = upload(keyboard, &changes, false, None);
+        print!("{}", report.render(term::stdout_color()));
+        report.into_result()?;
+    }
+    *uploaded = Some(layers);
+    Ok(count)
+}
+
+/// Binds single key of the first layer and asks user to try it before the
+/// rest is uploaded, so config for keyboard of different geometry isn't
+/// flashed whole. Keys sending chords are preferred, they are checked by
+/// listening to keyboard when possible, other keys are confirmed by user.
+fn canary(keyboard: &mut dyn Keyboard, layers: &[FlatLayer], devel_options: &DevelOptions) -> Result<()> {
+    let bindings: Vec<(Key, &Macro)> = layers.first().map(|layer| layer.bindings().collect()).unwrap_or_default();
+    let Some(&(key, macro_)) = bindings.iter().find(|(_, macro_)| verify::expected_chords(macro_).is_ok()).or(bindings.first()) else {
+        bail!("config binds no keys on the first layer, there is no key to try first");
+    };
+    keyboard.bind_key(0, key, macro_).context("bind canary key")?;
+
+    let listener = verify::expected_chords(macro_).ok()
+        .and_then(|_| Listener::open_exclusive(&device_ids(devel_options)).ok());
+    let outcome = match listener {
+        Some(listener) => verify::check_key(&format!("{key} on layer 1"), macro_, &listener)?,
+        None => {
+            ensure!(std::io::stdin().is_terminal(), "canary key can't be confirmed non-interactively");
+            eprint!("Press {key} on layer 1, it is bound to '{macro_}'. Does it work? [y/N] ");
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer).context("read answer")?;
+            if answer.trim().eq_ignore_ascii_case("y") {
+                verify::Outcome::Passed
+            } else {
+                verify::Outcome::Failed("not confirmed".to_owned())
+            }
+        }
+    };
+    if let verify::Outcome::Failed(reason) = outcome {
+        bail!("canary {key} on layer 1 failed: {reason}; other bindings aren't uploaded, \
+               check whether `model`, geometry and `orientation` of config match keyboard");
+    }
+    println!("Canary {key} works, uploading the rest.");
+    Ok(())
+}
+
+/// Helps to find out which physical keyboard is selected: temporarily binds
+/// keys of first layer to type "id" and restores given config afterwards.
+/// LED messages of supported keyboards aren't known, so LEDs can't blink.
+fn identify(keyboard: &mut dyn Keyboard, layers: &[FlatLayer]) -> Result<()> {
+    let Some(first) = layers.first() else { bail!("config has no layers") };
+
+    let id: Macro = "i,d".parse().unwrap();
+    let placeholder = FlatLayer {
+        buttons: vec![Some(id.clone()); first.buttons.len()],
+        knobs: vec![Knob { ccw: Some(id.clone()), press: Some(id.clone()), cw: Some(id) }; first.knobs.len()],
+    };
+    keyboard.bind_all(&all_bindings(std::slice::from_ref(&placeholder)))?;
+
+    println!("Keys of the first layer now type \"id\", try them. Press Enter to restore bindings.");
+    let mut line = String::new();
+    let read = std::io::stdin().read_line(&mut line);
+    keyboard.bind_all(&all_bindings(layers)).context("restore bindings")?;
+    read.context("wait for Enter")?;
+    println!("Bindings are restored.");
+    Ok(())
+}
+
+/// Waits for keyboards to be plugged in and programs them. Keyboards
+/// connected before start are ignored until replugged.
+fn provision(layers: &[FlatLayer], settings: &DeviceSettings, devel_options: &DevelOptions, repeat: bool) -> Result<()> {
+    let address = |device: &Device<Context>| (device.bus_number(), device.address());
+    let mut known: Vec<(u8, u8)> = matching_devices(devel_options)?.iter()
+        .map(|(device, _, _)| address(device))
+        .collect();
+    if !known.is_empty() {
+        println!("{} already connected keyboard(s) are ignored, replug them to program.", known.len());
+    }
+    println!("Waiting for keyboard to be plugged in, press Ctrl-C to stop...");
+
+    let mut programmed = 0;
+    loop {
+        std::thread::sleep(Duration::from_millis(500));
+        let devices = matching_devices(devel_options)?;
+        known.retain(|addr| devices.iter().any(|(device, _, _)| address(device) == *addr));
+
+        for (device, desc, product_id) in &devices {
+            let (bus, addr) = address(device);
+            if known.contains(&(bus, addr)) {
+                continue;
+            }
+            known.push((bus, addr));
+
+            // Give just plugged in device a moment to initialize.
+            std::thread::sleep(Duration::from_millis(500));
+            let result = open_device(device, desc, *product_id, devel_options).and_then(|mut keyboard| {
+                configure(keyboard.as_mut(), settings)?;
+                keyboard.bind_all(&all_bindings(layers))
+            });
+            match &result {
+                Ok(()) => {
+                    programmed += 1;
+                    // Terminal bell lets to work without looking at screen.
+                    println!("\x07✓ keyboard {bus}:{addr} ({}) is programmed, {programmed} so far", model_name(*product_id));
+                }
+                Err(err) => println!("✗ keyboard {bus}:{addr} ({}) failed: {err:#}", model_name(*product_id)),
+            }
+            if !repeat {
+                return result;
+            }
+        }
+    }
+}
+
 fn find_interface_and_endpoint(
     device: &Device<Context>,
     interface_num: Option<u8>,
@@ -147,43 +704,53 @@ fn find_interface_and_endpoint(
             )
         })?;
 
+        // Claiming keyboard or mouse interface detaches it from OS, so
+        // typing with the keyboard stops working until it is replugged.
+        if !is_programming_interface(intf_desc.class_code(), intf_desc.sub_class_code(), intf_desc.protocol_code()) {
+            debug!("skip interface #{iface_num}, it isn't programming one: {:#?}", intf_desc);
+            continue;
+        }
+
         // Look for suitable endpoints
         if let Some(endpt_desc) = intf_desc.endpoint_descriptors().find(|ep| {
             ep.transfer_type() == TransferType::Interrupt && ep.address() == endpoint_addr
         }) {
             debug!("Found endpoint {endpt_desc:?}");
-            if intf_desc.class_code() == 0x03
-                && intf_desc.sub_class_code() == 0x00
-                && intf_desc.protocol_code() == 0x00
-            {
-                return Ok((iface_num, endpt_desc.address()));
-            } else {
-                debug!("unexpected interface parameters: {:#?}", intf_desc);
-            }
+            return Ok((iface_num, endpt_desc.address()));
         }
     }
 
     Err(anyhow!("No valid interface/endpoint combination found!"))
 }
 
+/// Tells whether interface with given class, subclass and protocol codes
+/// may be used for programming. It is either HID interface without boot
+/// protocol or vendor-specific one, but never boot keyboard or mouse.
+fn is_programming_interface(class: u8, subclass: u8, protocol: u8) -> bool {
+    matches!((class, subclass, protocol), (0x03, 0x00, 0x00) | (0xff, _, _))
+}
+
 fn open_keyboard(devel_options: &DevelOptions) -> Result<Box<dyn Keyboard>> {
     // Find USB device based on the product id
     let (device, desc, id_product) = find_device(devel_options).context("find USB device")?;
+    open_device(&device, &desc, id_product, devel_options)
+}
 
+fn open_device(device: &Device<Context>, desc: &DeviceDescriptor, id_product: u16, devel_options: &DevelOptions) -> Result<Box<dyn Keyboard>> {
     ensure!(
         desc.num_configurations() == 1,
         "only one device configuration is expected"
     );
 
-    let preferred_endpint = match id_product {
-        0x8840 | 0x8842 | 0x8850 => k884x::Keyboard884x::preferred_endpoint(),
-        0x8890 => k8890::Keyboard8890::preferred_endpoint(),
-        _ => unreachable!("unsupported device"),
+    let protocol = device_protocol(devel_options, id_product)?;
+    let preferred_endpint = match protocol {
+        Protocol::K884x => k884x::Keyboard884x::preferred_endpoint(),
+        Protocol::K8890 => k8890::Keyboard8890::preferred_endpoint(),
     };
 
     // Find correct endpoint
     let (intf_num, endpt_addr) = find_interface_and_endpoint(
-        &device,
+        device,
         devel_options.interface_number,
         devel_options.endpoint_address.unwrap_or(preferred_endpint),
     )?;
@@ -193,106 +760,334 @@ fn open_keyboard(devel_options: &DevelOptions) -> Result<Box<dyn Keyboard>> {
     let _ = handle.set_auto_detach_kernel_driver(true);
     handle
         .claim_interface(intf_num)
-        .context("claim interface")?;
+        .with_context(|| format!("claim interface {intf_num}, is keyboard used by another program?"))?;
 
-    match id_product {
-        0x8840 | 0x8842 | 0x8850 => {
-            k884x::Keyboard884x::new(handle, endpt_addr).map(|v| Box::new(v) as Box<dyn Keyboard>)
+    let quirks = quirks::for_device(id_product, desc.device_version());
+    debug!("firmware revision {:04x}, quirks: {quirks:?}", quirks::bcd(desc.device_version()));
+
+    match protocol {
+        Protocol::K884x => {
+            k884x::Keyboard884x::new(handle, endpt_addr, quirks).map(|v| Box::new(v) as Box<dyn Keyboard>)
         }
-        0x8890 => {
-            k8890::Keyboard8890::new(handle, endpt_addr).map(|v| Box::new(v) as Box<dyn Keyboard>)
+        Protocol::K8890 => {
+            k8890::Keyboard8890::new(handle, endpt_addr, quirks).map(|v| Box::new(v) as Box<dyn Keyboard>)
         }
-        _ => unreachable!("unsupported device"),
     }
 }
 
-fn find_device(devel_options: &DevelOptions) -> Result<(Device<Context>, DeviceDescriptor, u16)> {
+/// Protocol of keyboard: given with `--protocol` or known for its product ID.
+fn device_protocol(devel_options: &DevelOptions, product_id: u16) -> Result<Protocol> {
+    devel_options.protocol.or_else(|| Protocol::for_product(product_id)).ok_or_else(|| anyhow!(
+        "protocol of keyboard with product ID {product_id:04x} isn't known, pass --protocol k884x or --protocol k8890"))
+}
+
+/// Vendor and product IDs of keyboards to look for. Vendor ID which isn't
+/// given is the one known for product ID, or default one for unknown IDs.
+fn device_ids(devel_options: &DevelOptions) -> Vec<(u16, u16)> {
+    match (devel_options.vendor_id, devel_options.product_id) {
+        (Some(vendor_id), Some(product_id)) => vec![(vendor_id, product_id)],
+        (Some(vendor_id), None) => DEVICE_IDS.iter().map(|&(_, product_id)| (vendor_id, product_id)).collect(),
+        (None, Some(product_id)) => {
+            let vendor_id = DEVICE_IDS.iter().find(|(_, id)| *id == product_id).map_or(VENDOR_ID, |(vendor_id, _)| *vendor_id);
+            vec![(vendor_id, product_id)]
+        }
+        (None, None) => DEVICE_IDS.to_vec(),
+    }
+}
+
+/// Result of USB bus scan. Devices whose descriptors can't be read, which
+/// happens with some hubs and VMs, are skipped and their errors collected.
+struct Scan {
+    devices: Vec<(Device<Context>, DeviceDescriptor)>,
+    /// Bus number and address of unreadable devices with errors.
+    errors: Vec<((u8, u8), rusb::Error)>,
+}
+
+impl Scan {
+    /// Devices with configured vendor and product IDs.
+    fn matching(self, devel_options: &DevelOptions) -> Vec<(Device<Context>, DeviceDescriptor, u16)> {
+        let ids = device_ids(devel_options);
+        self.devices.into_iter()
+            .filter(|(_, desc)| ids.contains(&(desc.vendor_id(), desc.product_id())))
+            .map(|(device, desc)| {
+                let product_id = desc.product_id();
+                (device, desc, product_id)
+            })
+            .collect()
+    }
+}
+
+fn scan_devices() -> Result<Scan> {
     let options = vec![
         #[cfg(windows)] rusb::UsbOption::use_usbdk(),
     ];
     let usb_context = rusb::Context::with_options(&options)?;
 
-    let mut found = vec![];
+    let mut scan = Scan { devices: vec![], errors: vec![] };
     for device in usb_context.devices().context("get USB device list")?.iter() {
-        let desc = device.device_descriptor().context("get USB device info")?;
+        let address = (device.bus_number(), device.address());
+        let desc = match device.device_descriptor() {
+            Ok(desc) => desc,
+            Err(err) => {
+                debug!("Bus {:03} Device {:03}: can't get USB device info: {err}", address.0, address.1);
+                scan.errors.push((address, err));
+                continue;
+            }
+        };
         debug!(
             "Bus {:03} Device {:03} ID {:04x}:{:04x}",
-            device.bus_number(),
-            device.address(),
+            address.0,
+            address.1,
             desc.vendor_id(),
             desc.product_id()
         );
-        let product_id = desc.product_id();
-        if desc.vendor_id() == devel_options.vendor_id
-            && match devel_options.product_id {
-                Some(prod_id) => prod_id == product_id,
-                None => PRODUCT_IDS.contains(&product_id),
+        scan.devices.push((device, desc));
+    }
+    Ok(scan)
+}
+
+/// Lists connected devices with configured vendor and product IDs.
+fn matching_devices(devel_options: &DevelOptions) -> Result<Vec<(Device<Context>, DeviceDescriptor, u16)>> {
+    Ok(scan_devices()?.matching(devel_options))
+}
+
+/// Prints compatible keyboards and, if verbose, all other USB devices and
+/// those which couldn't be read.
+fn list_devices(devel_options: &DevelOptions, verbose: bool) -> Result<()> {
+    let scan = scan_devices()?;
+    let errors = scan.errors.len();
+    if verbose {
+        println!("USB devices:");
+        for (device, desc) in &scan.devices {
+            println!("  {}:{} ID {:04x}:{:04x}", device.bus_number(), device.address(), desc.vendor_id(), desc.product_id());
+        }
+        for ((bus, addr), err) in &scan.errors {
+            println!("  {bus}:{addr} can't be read: {err}");
+        }
+        println!();
+    }
+
+    let found = scan.matching(devel_options);
+    if found.is_empty() {
+        println!("No compatible keyboards found.");
+    } else {
+        println!("Compatible keyboards:");
+    }
+    for (i, (device, desc, product_id)) in found.iter().enumerate() {
+        let serial = read_serial(device, desc).map(|serial| format!("serial {serial}")).unwrap_or_else(|| "no serial".to_owned());
+        println!("  {}) {} ({product_id:04x}), firmware revision {:04x}, address {}:{}, {serial}",
+                 i + 1, model_name(*product_id), quirks::bcd(desc.device_version()), device.bus_number(), device.address());
+        if verbose {
+            if let Some(control) = autosuspend_control(device) {
+                println!("    autosuspend is enabled, if uploads fail, disable it with `echo on | sudo tee {}`",
+                         control.display());
             }
-        {
-            found.push((device, desc, product_id));
         }
     }
+    if errors > 0 && !verbose {
+        println!("{errors} USB device(s) couldn't be read, use --verbose to see errors.");
+    }
+    Ok(())
+}
+
+/// Returns sysfs power control file of device if kernel may autosuspend it.
+#[cfg(target_os = "linux")]
+fn autosuspend_control(device: &Device<Context>) -> Option<PathBuf> {
+    let read = |path: PathBuf| std::fs::read_to_string(path).ok().map(|value| value.trim().to_owned());
+    std::fs::read_dir("/sys/bus/usb/devices").ok()?.flatten()
+        .map(|entry| entry.path())
+        .find(|path| read(path.join("busnum")) == Some(device.bus_number().to_string())
+            && read(path.join("devnum")) == Some(device.address().to_string()))
+        .map(|path| path.join("power/control"))
+        .filter(|control| read(control.clone()).as_deref() == Some("auto"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn autosuspend_control(_device: &Device<Context>) -> Option<PathBuf> {
+    None
+}
+
+/// Prints details of keyboard taken from its USB descriptor. Firmware can't
+/// be asked for number of keys, knobs or layers, so only models known to
+/// have keyboard's product ID are shown.
+fn print_info(device: &Device<Context>, desc: &DeviceDescriptor, product_id: u16, protocol: Option<Protocol>) {
+    let handle = device.open().ok();
+    let string = |index: Option<u8>| handle.as_ref().zip(index)
+        .and_then(|(handle, index)| handle.read_string_descriptor_ascii(index).ok())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("Address: {}:{}", device.bus_number(), device.address());
+    println!("ID: {:04x}:{product_id:04x}", desc.vendor_id());
+    println!("Manufacturer: {}", string(desc.manufacturer_string_index()));
+    println!("Product: {}", string(desc.product_string_index()));
+    println!("Serial number: {}", string(desc.serial_number_string_index()));
+    let revision = quirks::bcd(desc.device_version());
+    println!("Firmware revision: {revision:04x}");
+
+    let Some(protocol) = protocol.or_else(|| Protocol::for_product(product_id)) else {
+        println!("Protocol: unknown");
+        return;
+    };
+    println!("Protocol: {protocol}, key presses per macro: {}, delays: {}",
+             protocol.max_presses(), if protocol.supports_delays() { "yes" } else { "no" });
+    let quirks = quirks::for_device(product_id, desc.device_version());
+    println!("Quirks: {}", if quirks.is_empty() { "none".to_owned() } else { quirks.iter().join(", ") });
+
+    let models: Vec<&Model> = MODELS.iter().filter(|model| model.product_ids.contains(&product_id)).collect();
+    if models.is_empty() {
+        println!("Model: unknown, see `examples` for known models");
+    }
+    for model in models {
+        println!("Model: {} ({})", model.name, model.description);
+    }
+}
 
+fn find_device(devel_options: &DevelOptions) -> Result<(Device<Context>, DeviceDescriptor, u16)> {
+    let scan = scan_devices()?;
+    let unreadable = scan.errors.len();
+    let mut found = scan.matching(devel_options);
+    if let (Some(selector), false) = (&devel_options.device, found.is_empty()) {
+        return select_device(found, selector);
+    }
     match found.len() {
+        0 if unreadable > 0 => Err(anyhow!(
+            "CH57x keyboard device not found, but {unreadable} USB device(s) couldn't be read, see `list-devices --verbose`. \
+             Use --vendor-id and --product-id to override settings."
+        )),
         0 => Err(anyhow!(
             "CH57x keyboard device not found. Use --vendor-id and --product-id to override settings."
         )),
         1 => Ok(found.pop().unwrap()),
         _ => {
-            let mut addresses = vec![];
-            for (device, desc, product_id) in found {
-                /*let handle = device.open().context("open device")?;
-                let langs = handle.read_languages(DEFAULT_TIMEOUT).context("get langs")?;
-                dbg!(&langs);
-                let lang =
-                    // First try to find US English language
-                    langs.iter().find(|l| {
-                        l.primary_language() == PrimaryLanguage::English &&
-                        l.sub_language() == SubLanguage::UnitedStates
-                    })
-                    // Then any English sublanguage
-                    .or_else(|| langs.iter().find(|l| l.primary_language() == PrimaryLanguage::English))
-                    // Then just first available language
-                    .or_else(|| langs.first())
-                    // Ok, give up
-                    .ok_or_else(|| anyhow!("No languages found"))?;
-                dbg!(lang);
-                let serial = handle.read_serial_number_string(*lang, &desc, DEFAULT_TIMEOUT)
-                    .context("read serial")?;*/
-                let address = (device.bus_number(), device.address());
-                if devel_options.address.as_ref() == Some(&address) {
-                    return Ok((device, desc, product_id))
+            if let Some(address) = devel_options.address {
+                let idx = found.iter().position(|(device, _, _)| (device.bus_number(), device.address()) == address);
+                if let Some(idx) = idx {
+                    return Ok(found.swap_remove(idx));
                 }
-
-                addresses.push(address);
+            } else if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+                let idx = pick_device(&found)?;
+                return Ok(found.swap_remove(idx));
             }
 
             Err(anyhow!(indoc! {"
                 Several compatible devices are found.
-                Unfortunately, this model of keyboard doesn't have serial number.
-                So specify USB address using --address option.
+                Select one with --device option by its number, bus:address or serial number:
+                {}"}, describe_devices(&found)))
+        }
+    }
+}
+
+/// Takes keyboard given with `--device` out of found ones.
+fn select_device(mut found: Vec<(Device<Context>, DeviceDescriptor, u16)>, selector: &DeviceSelector)
+    -> Result<(Device<Context>, DeviceDescriptor, u16)>
+{
+    let idx = match selector {
+        DeviceSelector::Index(n) => (*n <= found.len()).then(|| n - 1),
+        DeviceSelector::Address(bus, addr) => found.iter()
+            .position(|(device, _, _)| (device.bus_number(), device.address()) == (*bus, *addr)),
+        DeviceSelector::Serial(serial) => found.iter()
+            .position(|(device, desc, _)| read_serial(device, desc).as_ref() == Some(serial)),
+    };
+    let Some(idx) = idx else {
+        bail!("no compatible keyboard has {selector}, connected ones are:\n{}", describe_devices(&found));
+    };
+    Ok(found.swap_remove(idx))
+}
+
+/// Lists devices with their numbers, addresses and serial numbers, line
+/// per device.
+fn describe_devices(found: &[(Device<Context>, DeviceDescriptor, u16)]) -> String {
+    found.iter().enumerate().map(|(i, (device, desc, product_id))| {
+        let serial = read_serial(device, desc).map(|serial| format!("serial {serial}")).unwrap_or_else(|| "no serial".to_owned());
+        format!("  {}) {} ({product_id:04x}), address {}:{}, {serial}",
+                i + 1, model_name(*product_id), device.bus_number(), device.address())
+    }).join("\n")
+}
+
+/// Asks user to choose one of several devices, returns its index.
+fn pick_device(found: &[(Device<Context>, DeviceDescriptor, u16)]) -> Result<usize> {
+    eprintln!("Several compatible devices are found:");
+    eprintln!("{}", describe_devices(found));
 
-                Addresses:
-                {}
-            "}, addresses.iter().map(|(bus, addr)| format!("{bus}:{addr}")).join("\n")))
+    loop {
+        eprint!("Select device [1-{}] (run `identify` command with --device to find out which one is which): ", found.len());
+        let mut answer = String::new();
+        let read = std::io::stdin().read_line(&mut answer).context("read answer")?;
+        ensure!(read > 0, "no device selected");
+        match answer.trim().parse::<usize>() {
+            Ok(n) if (1..=found.len()).contains(&n) => return Ok(n - 1),
+            _ => eprintln!("Enter number from 1 to {}", found.len()),
         }
     }
 }
 
+/// Reads serial number string, most devices don't have it.
+fn read_serial(device: &Device<Context>, desc: &DeviceDescriptor) -> Option<String> {
+    desc.serial_number_string_index()?;
+    let handle = device.open().ok()?;
+    handle.read_serial_number_string_ascii(desc).ok()
+}
+
+fn model_name(product_id: u16) -> &'static str {
+    match Protocol::for_product(product_id) {
+        Some(Protocol::K884x) => "k884x",
+        Some(Protocol::K8890) => "k8890",
+        None => "unknown",
+    }
+}
+
+/// Returns path of config file to use: explicitly given one or, if stdin
+/// is terminal and so config isn't piped, default one if it exists.
+fn config_path(params: &ConfigParams) -> Option<PathBuf> {
+    match &params.config_path {
+        Some(path) => Some(path.into()),
+        None if std::io::stdin().is_terminal() => paths::default_config_path().filter(|path| path.is_file()),
+        None => None,
+    }
+}
+
+/// Sets model given on command line unless config declares another one.
+fn set_model(config: &mut Config, name: Option<&str>) -> Result<()> {
+    let Some(model) = name.and_then(Model::find) else { return Ok(()) };
+    match config.model {
+        Some(declared) if declared != model =>
+            bail!("config is for model {}, but --model {} is given", declared.name, model.name),
+        _ => config.model = Some(model),
+    }
+    Ok(())
+}
+
 fn load_config(params: &ConfigParams) -> Result<Config> {
     // Load and validate mapping.
     let mut stdin_reader: BufReader<StdinLock<'static>>;
     let mut file_reader: BufReader<std::fs::File>;
-    let reader: &mut dyn Read = match &params.config_path {
+    let reader: &mut dyn Read = match config_path(params) {
+        Some(path) if path.is_dir() => {
+            debug!("load config from directory {}", path.display());
+            let mut config = Config::load_dir(&path)?;
+            set_model(&mut config, params.model.as_deref())?;
+            config.orientation = params.orientation.unwrap_or(config.orientation);
+            config.apply_model()?;
+            config.select_for_host(&Host::current());
+            return Ok(config);
+        }
         Some(path) => {
+            debug!("load config from {}", path.display());
             let file = std::fs::File::open(path).context("open config file")?;
             file_reader = BufReader::new(file);
             &mut file_reader
         }
         None => {
+            ensure!(!std::io::stdin().is_terminal(),
+                    "no config file given and default one isn't found at {}",
+                    paths::default_config_path().map_or_else(|| "config directory".to_owned(), |p| p.display().to_string()));
             stdin_reader = BufReader::new(std::io::stdin().lock());
             &mut stdin_reader
         }
     };
-    Ok(serde_yaml::from_reader(reader)?)
+    let mut config: Config = serde_yaml::from_reader(reader)?;
+    set_model(&mut config, params.model.as_deref())?;
+    config.orientation = params.orientation.unwrap_or(config.orientation);
+    config.apply_model()?;
+    config.select_for_host(&Host::current());
+    Ok(config)
 }
@@ -0,0 +1,235 @@
+//! Known keyboard models: their geometry and names of keys.
+//!
+//! Config may declare `model` instead of giving `rows`, `columns` and
+//! `knobs`, then keys may also be bound by their names.
+//!
+//! Keyboards don't report their geometry, so model can't be detected and
+//! is always chosen by user.
+
+use std::fmt::Write as _;
+
+use serde::Deserialize;
+
+use crate::config::KeyRef;
+use crate::protocol::Protocol;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Model {
+    /// Name used in config.
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Geometry in normal orientation, with knobs on the right.
+    pub rows: u8,
+    pub columns: u8,
+    pub knobs: u8,
+    /// Names of buttons counted row by row in normal orientation.
+    pub button_names: &'static [&'static str],
+    /// Names of knobs, actions are appended to them: `knob-cw`.
+    pub knob_names: &'static [&'static str],
+    /// USB product IDs keyboards of this model are known to have, empty
+    /// if not known. Different models may share product ID.
+    pub product_ids: &'static [u16],
+}
+
+/// Sample macros for example configs.
+const SAMPLE_BUTTONS: [&str; 12] = [
+    "ctrl-c", "ctrl-v", "ctrl-x", "ctrl-z", "ctrl-s", "ctrl-f",
+    "ctrl-a", "ctrl-n", "ctrl-o", "ctrl-w", "ctrl-t", "ctrl-p",
+];
+const SAMPLE_KNOBS: [&str; 4] = [
+    "{ ccw: volumedown, press: mute, cw: volumeup }",
+    "{ scroll: none, press: middleclick }",
+    "{ scroll: ctrl, press: ctrl-0 }",
+    "{ ccw: ctrl-shift-tab, press: ctrl-t, cw: ctrl-tab }",
+];
+
+// Models are named by rows and columns in normal orientation.
+pub const MODELS: &[Model] = &[
+    Model {
+        name: "3x4-2knobs",
+        description: "3 rows of 4 keys with 2 knobs",
+        rows: 3,
+        columns: 4,
+        knobs: 2,
+        button_names: &[],
+        knob_names: &[],
+        product_ids: &[],
+    },
+    Model {
+        name: "3x4-4knobs",
+        description: "3 rows of 4 keys with 4 knobs",
+        rows: 3,
+        columns: 4,
+        knobs: 4,
+        button_names: &[],
+        knob_names: &[],
+        product_ids: &[0x8850],
+    },
+    Model {
+        name: "3x3-2knobs",
+        description: "3 rows of 3 keys with 2 knobs",
+        rows: 3,
+        columns: 3,
+        knobs: 2,
+        button_names: &[],
+        knob_names: &[],
+        product_ids: &[],
+    },
+    Model {
+        name: "3x2-1knob",
+        description: "3 rows of 2 keys with 1 knob",
+        rows: 3,
+        columns: 2,
+        knobs: 1,
+        button_names: &[],
+        knob_names: &[],
+        product_ids: &[],
+    },
+    Model {
+        name: "4x3-3knobs",
+        description: "4 rows of 3 keys with 3 knobs",
+        rows: 4,
+        columns: 3,
+        knobs: 3,
+        button_names: &[],
+        knob_names: &[],
+        product_ids: &[],
+    },
+    Model {
+        name: "4x1",
+        description: "4 keys without knobs",
+        rows: 4,
+        columns: 1,
+        knobs: 0,
+        button_names: &[],
+        knob_names: &[],
+        product_ids: &[],
+    },
+    Model {
+        name: "minibar",
+        description: "vertical bar of 3 keys with knob on the side",
+        rows: 3,
+        columns: 1,
+        knobs: 1,
+        button_names: &["top", "middle", "bottom"],
+        knob_names: &["knob"],
+        product_ids: &[0x8890],
+    },
+];
+
+impl Model {
+    pub fn find(name: &str) -> Option<&'static Model> {
+        MODELS.iter().find(|model| model.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Protocol used by model, models with unknown product IDs are assumed
+    /// to use the more common `k884x` one.
+    pub fn protocol(&self) -> Protocol {
+        self.product_ids.iter().find_map(|&id| Protocol::for_product(id)).unwrap_or(Protocol::K884x)
+    }
+
+    /// Tells whether keyboard with given product ID may be of this model.
+    pub fn matches_product(&self, product_id: u16) -> bool {
+        self.product_ids.is_empty() || self.product_ids.contains(&product_id)
+    }
+
+    /// Resolves key name, like `top` or `knob-press`, to button index or
+    /// knob action in normal orientation.
+    pub fn key(&self, name: &str) -> Option<KeyRef> {
+        if let Some(i) = self.button_names.iter().position(|n| *n == name) {
+            return Some(KeyRef::Button(i as u8));
+        }
+        let (knob, action) = name.rsplit_once('-')?;
+        let i = self.knob_names.iter().position(|n| *n == knob)?;
+        Some(KeyRef::Knob(i as u8, action.parse().ok()?))
+    }
+
+    /// Returns annotated config for model which may be used as starting point.
+    pub fn example_config(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "# Example config for {} (model '{}').", self.description, self.name).unwrap();
+        writeln!(out, "# Orientation is 'normal' when knobs are on the right.").unwrap();
+        writeln!(out, "model: {}", self.name).unwrap();
+        writeln!(out, "orientation: normal").unwrap();
+        writeln!(out, "layers:").unwrap();
+        writeln!(out, "  # Buttons are listed row by row.").unwrap();
+        writeln!(out, "  - buttons:").unwrap();
+        let mut samples = SAMPLE_BUTTONS.iter().cycle();
+        for _ in 0..self.rows {
+            let row: Vec<&str> = samples.by_ref().take(self.columns as usize).copied().collect();
+            writeln!(out, "      - [{}]", row.join(", ")).unwrap();
+        }
+        if self.knobs > 0 {
+            writeln!(out, "    # Knob actions: counter-clockwise, press, clockwise.").unwrap();
+            writeln!(out, "    knobs:").unwrap();
+            for knob in SAMPLE_KNOBS.iter().cycle().take(self.knobs as usize) {
+                writeln!(out, "      - {knob}").unwrap();
+            }
+        }
+
+        if !self.button_names.is_empty() {
+            writeln!(out, "  # Layer may be left empty and filled using bindings.").unwrap();
+            writeln!(out, "  - {{}}").unwrap();
+            writeln!(out, "# Keys of this model may be bound by name: {}.", self.key_names().join(", ")).unwrap();
+            writeln!(out, "bindings:").unwrap();
+            writeln!(out, "  - {{ key: {}, layers: [2], macro: ctrl-c }}", self.button_names[0]).unwrap();
+            if let Some(knob) = self.knob_names.first() {
+                writeln!(out, "  - {{ key: {knob}-press, layers: [2], macro: mute }}").unwrap();
+            }
+        }
+        out
+    }
+
+    fn key_names(&self) -> Vec<String> {
+        let knobs = self.knob_names.iter()
+            .flat_map(|knob| ["ccw", "press", "cw"].map(|action| format!("{knob}-{action}")));
+        self.button_names.iter().map(|name| name.to_string()).chain(knobs).collect()
+    }
+}
+
+/// Deserializes model given by name.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<&'static Model>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    match Model::find(&name) {
+        Some(model) => Ok(Some(model)),
+        None => {
+            let known: Vec<&str> = MODELS.iter().map(|model| model.name).collect();
+            Err(serde::de::Error::custom(format!("unknown model '{name}', known ones are: {}", known.join(", "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Model, MODELS};
+    use crate::config::{Config, KeyRef};
+    use crate::keyboard::KnobAction;
+
+    #[test]
+    fn resolve_key_names() {
+        let model = Model::find("minibar").unwrap();
+        assert_eq!(model.key("top"), Some(KeyRef::Button(0)));
+        assert_eq!(model.key("bottom"), Some(KeyRef::Button(2)));
+        assert_eq!(model.key("knob-ccw"), Some(KeyRef::Knob(0, KnobAction::RotateCCW)));
+        assert_eq!(model.key("knob-left"), None);
+        assert_eq!(model.key("left"), None);
+    }
+
+    #[test]
+    fn match_product_ids() {
+        assert!(Model::find("minibar").unwrap().matches_product(0x8890));
+        assert!(!Model::find("minibar").unwrap().matches_product(0x8840));
+        assert!(Model::find("3x3-2knobs").unwrap().matches_product(0x8840));
+    }
+
+    #[test]
+    fn example_configs_are_valid() {
+        for model in MODELS {
+            let config: Config = serde_yaml::from_str(&model.example_config()).unwrap();
+            config.render().unwrap_or_else(|err| panic!("example for {}: {err}", model.name));
+        }
+    }
+}
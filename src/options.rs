@@ -1,31 +1,71 @@
 use std::ffi::OsString;
 use std::num::ParseIntError;
 
-use clap::{Args, Parser, Subcommand};
-use crate::consts::VENDOR_ID;
+use clap::builder::PossibleValuesParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use crate::config::Orientation;
+use crate::convert::Target;
+use crate::export::Format as ExportFormat;
+use crate::keyboard::DisplayStyle;
+use crate::layout::Layout;
+use crate::model::MODELS;
 use crate::parse;
+use crate::protocol::Protocol;
+use crate::term::ColorChoice;
+
+const EXAMPLES: &str = "\
+Examples:
+  Start config for keyboard with 3 rows of 4 keys and 2 knobs:
+    ch57x-keyboard-tool examples 3x4-2knobs > mapping.yaml
+  Check and upload it:
+    ch57x-keyboard-tool validate mapping.yaml
+    ch57x-keyboard-tool upload mapping.yaml
+  List known models:
+    ch57x-keyboard-tool examples";
 
 #[derive(Parser)]
+#[command(version, disable_version_flag = true, arg_required_else_help = true, after_help = EXAMPLES)]
 pub struct Options {
     #[command(subcommand)]
-    pub command: Command,
+    pub command: Option<Command>,
+
+    /// Print version information
+    #[arg(short = 'V', long)]
+    pub version: bool,
+
+    /// With --version, also print supported keyboards, protocols and platform features
+    #[arg(long, requires = "version")]
+    pub verbose: bool,
+
+    /// When to use colors in output, 'auto' uses them for terminal unless NO_COLOR is set
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub color: ColorChoice,
 
     #[clap(flatten)]
     pub devel_options: DevelOptions,
 }
 
 #[derive(Args)]
-#[clap(version, next_help_heading = "Internal options (use with caution)")]
+#[clap(next_help_heading = "Internal options (use with caution)")]
 pub struct DevelOptions {
-    #[arg(long, default_value_t=VENDOR_ID, value_parser=hex_or_decimal)]
-    pub vendor_id: u16,
+    /// Vendor ID of keyboard, default is the one known for product ID
+    #[arg(long, value_parser=hex_or_decimal)]
+    pub vendor_id: Option<u16>,
 
     #[arg(long, value_parser=hex_or_decimal)]
     pub product_id: Option<u16>,
 
+    /// Protocol of keyboard whose product ID isn't known, like clone reusing another ID: 'k884x' or 'k8890'
+    #[arg(long)]
+    pub protocol: Option<Protocol>,
+
     #[arg(long, value_parser=parse_address)]
     pub address: Option<(u8, u8)>,
 
+    /// Keyboard to use when several are connected: serial number, bus:address or number from `list-devices`
+    #[arg(long, value_parser=parse_device_selector, conflicts_with = "address")]
+    pub device: Option<DeviceSelector>,
+
     #[arg(long)]
     pub endpoint_address: Option<u8>,
 
@@ -38,34 +78,339 @@ pub fn hex_or_decimal(s: &str) -> Result<u16, ParseIntError>
     if s.to_ascii_lowercase().starts_with("0x") {
         u16::from_str_radix(&s[2..], 16)
     } else {
-        u16::from_str_radix(s, 10)
+        s.parse()
     }
 }
 
+fn parse_hex_bytes(s: &str) -> std::result::Result<Vec<u8>, String> {
+    let bytes = parse::from_str(parse::hex_bytes, s).map_err(|err| err.to_string())?;
+    if bytes.len() > 64 {
+        return Err("message is longer than 64 bytes".to_owned());
+    }
+    Ok(bytes)
+}
+
 fn parse_address(s: &str) -> std::result::Result<(u8, u8), nom::error::Error<String>> {
     parse::from_str(parse::address, s)
 }
 
+/// Keyboard selected among connected ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    Serial(String),
+    /// Bus number and address.
+    Address(u8, u8),
+    /// One-based number in `list-devices` output.
+    Index(usize),
+}
+
+impl std::fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Serial(serial) => write!(f, "serial number {serial}"),
+            Self::Address(bus, addr) => write!(f, "address {bus}:{addr}"),
+            Self::Index(n) => write!(f, "number {n}"),
+        }
+    }
+}
+
+fn parse_device_selector(s: &str) -> std::result::Result<DeviceSelector, String> {
+    if let Ok((bus, addr)) = parse_address(s) {
+        return Ok(DeviceSelector::Address(bus, addr));
+    }
+    match s.parse::<usize>() {
+        Ok(0) => Err("devices are numbered from 1".to_owned()),
+        Ok(n) => Ok(DeviceSelector::Index(n)),
+        Err(_) if s.is_empty() => Err("device is empty".to_owned()),
+        Err(_) => Ok(DeviceSelector::Serial(s.to_owned())),
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Command {
     /// Show supported keys and modifiers
     ShowKeys,
 
     /// Validate key mappings config on stdin
-    Validate(ConfigParams),
+    Validate(ValidateParams),
 
     /// Upload key mappings from stdin to device
-    Upload(ConfigParams),
+    Upload(UploadParams),
+
+    /// Upload config each time it's saved, sending only keys changed since previous upload
+    Dev(ConfigParams),
 
     /// Select LED backlight mode
     Led(LedCommand),
+
+    /// Perform host actions from config when keyboard keys are pressed
+    Daemon(DaemonParams),
+
+    /// Show differences between two configs as grid of keys
+    Diff(DiffParams),
+
+    /// Check uploaded config by pressing keys one by one
+    Verify(VerifyParams),
+
+    /// Wait for keyboards to be plugged in and upload config to them
+    Provision(ProvisionParams),
+
+    /// Manage default config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// Temporarily rebind keys of keyboard to tell which one it is, LED feedback isn't supported.
+    /// Config is needed to restore bindings afterwards
+    Identify(ConfigParams),
+
+    /// Print annotated example config for keyboard model
+    Examples(ExamplesParams),
+
+    /// Rewrite config for keyboard of another model and print it
+    Convert(ConvertParams),
+
+    /// Print AutoHotkey or Hammerspoon script stubs for chords keys send, or bindings as CSV
+    Export(ExportParams),
+
+    /// Print config with bindings from CSV made by `export --format csv`
+    Import(ImportParams),
+
+    /// List connected compatible keyboards
+    ListDevices(ListDevicesParams),
+
+    /// Show what is known about connected keyboard: firmware revision, protocol and models
+    Info,
+
+    /// Send raw messages to keyboard, for exploring its protocol
+    SendRaw(SendRawParams),
+
+    /// Interactive shell sending raw messages and reading replies, for exploring keyboard protocol
+    Repl,
+
+    /// Describe messages sent to keyboard using protocol, generated by encoders
+    DumpProtocol(DumpProtocolParams),
 }
 
 #[derive(Parser)]
 pub struct ConfigParams {
-    /// Path to config file to upload.
-    /// If not given, read from stdin.
+    /// Path to config file or directory with several files to upload.
+    /// If not given, read from stdin or, if stdin is terminal, from default config file
+    /// (see `config path`).
     pub config_path: Option<OsString>,
+
+    /// Keyboard model config is for, if config doesn't declare it. Lets config
+    /// be checked without keyboard connected
+    #[arg(long, value_parser = PossibleValuesParser::new(MODELS.iter().map(|model| model.name)))]
+    pub model: Option<String>,
+
+    /// Orientation keyboard is held in, overrides one in config: 'normal', 'upsidedown',
+    /// 'clockwise' or 'counterclockwise'
+    #[arg(long, alias = "rotate")]
+    pub orientation: Option<Orientation>,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print path of default config file
+    Path {
+        /// Open config file in $EDITOR or default application
+        #[arg(long)]
+        open: bool,
+    },
+}
+
+#[derive(Parser)]
+pub struct ValidateParams {
+    #[clap(flatten)]
+    pub config: ConfigParams,
+
+    /// Validate config again each time file is saved
+    #[arg(long, conflicts_with = "output")]
+    pub watch: bool,
+
+    /// How problems are reported
+    #[arg(long, value_enum, default_value_t = ValidateOutput::Text)]
+    pub output: ValidateOutput,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ValidateOutput {
+    /// Message for humans
+    Text,
+    /// List of problems with severity, path, position, message and fix
+    Json,
+}
+
+#[derive(Parser)]
+pub struct UploadParams {
+    #[clap(flatten)]
+    pub config: ConfigParams,
+
+    /// Upload even if keyboard doesn't match `model` declared in config
+    #[arg(long)]
+    pub force: bool,
+
+    /// Reset keyboard on USB level after upload, for firmwares applying bindings only after reset
+    #[arg(long)]
+    pub reboot_after: bool,
+
+    /// Emulate features keyboard lacks with host actions, run daemon with the same option
+    #[arg(long)]
+    pub emulate_unsupported: bool,
+
+    /// Stop at first binding keyboard fails to accept (default)
+    #[arg(long, overrides_with = "keep_going")]
+    pub fail_fast: bool,
+
+    /// Upload remaining bindings when one fails and list failures at the end
+    #[arg(long, overrides_with = "fail_fast")]
+    pub keep_going: bool,
+
+    /// Bind single key first and upload the rest only once it's confirmed to work
+    #[arg(long, conflicts_with = "all")]
+    pub canary: bool,
+
+    /// Upload to every connected keyboard and report outcome for each
+    #[arg(long, conflicts_with = "emulate_unsupported")]
+    pub all: bool,
+
+    /// Upload bindings like alt-f4 or power, which are refused as dangerous otherwise
+    #[arg(long)]
+    pub allow_dangerous: bool,
+
+    /// Trade upload speed for reliability on flaky hardware, messages are sent back to back otherwise
+    #[arg(long, value_enum)]
+    pub speed: Option<Speed>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Speed {
+    /// Pause after each message and drain queued input reports without checking them (`paced` and `drain-in` quirks)
+    Safe,
+}
+
+#[derive(Parser)]
+pub struct DaemonParams {
+    #[clap(flatten)]
+    pub config: ConfigParams,
+
+    /// Perform host actions emulating features keyboard lacks, see `upload --emulate-unsupported`
+    #[arg(long)]
+    pub emulate_unsupported: bool,
+
+    /// When started as root, run host actions as this user once input devices are opened
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Ignore chords repeated sooner than this many milliseconds, like ones caused by key bounce
+    #[arg(long, default_value_t = 100)]
+    pub min_interval: u64,
+
+    /// Upload config again each time keyboard reconnects, for keyboards losing bindings on power loss
+    #[arg(long)]
+    pub reupload: bool,
+}
+
+#[derive(Parser)]
+pub struct VerifyParams {
+    #[clap(flatten)]
+    pub config: ConfigParams,
+
+    /// Ask to press each key and check what keyboard sends to computer
+    #[arg(long)]
+    pub interactive: bool,
+}
+
+#[derive(Parser)]
+pub struct ProvisionParams {
+    #[clap(flatten)]
+    pub config: ConfigParams,
+
+    /// Keep waiting for next keyboard after one is programmed
+    #[arg(long = "loop")]
+    pub repeat: bool,
+}
+
+#[derive(Parser)]
+pub struct DiffParams {
+    /// Path to old config file
+    pub old_config_path: OsString,
+    /// Path to new config file
+    pub new_config_path: OsString,
+
+    /// Keyboard model configs are for, if they don't declare it
+    #[arg(long, value_parser = PossibleValuesParser::new(MODELS.iter().map(|model| model.name)))]
+    pub model: Option<String>,
+
+    /// How to show macros: 'text' (ctrl-shift-c) or 'mac' (⌃⇧C)
+    #[arg(long, default_value_t)]
+    pub style: DisplayStyle,
+
+    /// Show keys by legends of keyboard layout, 'us', 'uk', 'de' or 'fr': ö instead of semicolon for 'de'
+    #[arg(long)]
+    pub legends: Option<Layout>,
+}
+
+#[derive(Parser)]
+pub struct ExportParams {
+    #[clap(flatten)]
+    pub config: ConfigParams,
+
+    /// Output format
+    #[arg(long, value_enum)]
+    pub format: ExportFormat,
+}
+
+#[derive(Parser)]
+pub struct ImportParams {
+    /// CSV file with `layer,key,macro` rows
+    pub csv_path: OsString,
+
+    /// Keyboard model bindings are for
+    #[arg(long, value_parser = PossibleValuesParser::new(MODELS.iter().map(|model| model.name)))]
+    pub model: String,
+}
+
+#[derive(Parser)]
+pub struct ListDevicesParams {
+    /// Also list all other USB devices and those which couldn't be read
+    #[arg(long, short)]
+    pub verbose: bool,
+}
+
+#[derive(Parser)]
+pub struct ExamplesParams {
+    /// Keyboard model, known models are listed if not given
+    #[arg(value_parser = PossibleValuesParser::new(MODELS.iter().map(|model| model.name)))]
+    pub model: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct ConvertParams {
+    #[clap(flatten)]
+    pub config: ConfigParams,
+
+    /// Target model (see `examples`) or protocol ('k884x' or 'k8890') to keep geometry
+    #[arg(long)]
+    pub to: Target,
+}
+
+#[derive(Parser)]
+pub struct DumpProtocolParams {
+    /// Protocol to describe: 'k884x' or 'k8890'
+    #[arg(long, alias = "model")]
+    pub protocol: Protocol,
+}
+
+#[derive(Parser)]
+pub struct SendRawParams {
+    /// Messages as hex bytes, like "03 fe 01", each is padded with zeroes to 64 bytes
+    #[arg(required = true, value_parser = parse_hex_bytes)]
+    pub messages: Vec<Vec<u8>>,
+
+    /// Don't ask for confirmation
+    #[arg(long)]
+    pub yes: bool,
 }
 
 #[derive(Parser)]
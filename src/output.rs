@@ -0,0 +1,378 @@
+//! Virtual output: emulates typing on host computer.
+//!
+//! There is no portable way to inject key presses, so platform tools are used:
+//! `wtype`, `xdotool` or `ydotool` on Linux, AppleScript on macOS and
+//! `SendKeys` on Windows. Chords are pressed with `wtype` or `xdotool` on Linux. Clipboard is set with `wl-copy`, `xclip` or `xsel`
+//! on Linux, `pbcopy` on macOS and `Set-Clipboard` on Windows.
+
+use std::io::{ErrorKind, Write as _};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use log::debug;
+
+use crate::keyboard::{Accord, Code, Modifier, Modifiers, WellKnownCode};
+
+/// Types given text on host computer.
+pub fn type_text(text: &str) -> Result<()> {
+    run_first_available("typing text", type_text_commands(text), None)
+}
+
+/// Replaces clipboard contents with given text.
+pub fn set_clipboard(text: &str) -> Result<()> {
+    run_first_available("setting clipboard", set_clipboard_commands(), Some(text))
+}
+
+/// Presses chord on host computer like keyboard would.
+pub fn press_chord(accord: &Accord) -> Result<()> {
+    let Some(Code::WellKnown(code)) = accord.code else {
+        bail!("chord '{accord}' can't be pressed on host, it has no key or has custom one");
+    };
+    run_first_available("pressing keys", press_chord_commands(accord.modifiers, code)?, None)
+}
+
+/// Sends paste chord: `cmd-v` on macOS, `ctrl-v` elsewhere.
+pub fn paste() -> Result<()> {
+    run_first_available("pressing keys", paste_commands(), None)
+}
+
+#[cfg(target_os = "linux")]
+fn type_text_commands(text: &str) -> Vec<Command> {
+    let mut commands = vec![];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(command("wtype", &["--", text]));
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        commands.push(command("xdotool", &["type", "--clearmodifiers", "--", text]));
+    }
+    commands.push(command("ydotool", &["type", "--", text]));
+    commands
+}
+
+#[cfg(target_os = "linux")]
+fn set_clipboard_commands() -> Vec<Command> {
+    let mut commands = vec![];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(command("wl-copy", &[]));
+    }
+    commands.push(command("xclip", &["-selection", "clipboard"]));
+    commands.push(command("xsel", &["--clipboard", "--input"]));
+    commands
+}
+
+#[cfg(target_os = "linux")]
+fn paste_commands() -> Vec<Command> {
+    let mut commands = vec![];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(command("wtype", &["-M", "ctrl", "v", "-m", "ctrl"]));
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        commands.push(command("xdotool", &["key", "--clearmodifiers", "ctrl+v"]));
+    }
+    // Linux key codes of left ctrl and 'v'.
+    commands.push(command("ydotool", &["key", "29:1", "47:1", "47:0", "29:0"]));
+    commands
+}
+
+#[cfg(target_os = "linux")]
+fn press_chord_commands(modifiers: Modifiers, code: WellKnownCode) -> Result<Vec<Command>> {
+    let key = keysym(code);
+    let names = |ctrl, alt, win| modifiers.iter().map(|m| match m {
+        Modifier::Ctrl | Modifier::RightCtrl => ctrl,
+        Modifier::Shift | Modifier::RightShift => "shift",
+        Modifier::Alt | Modifier::RightAlt => alt,
+        Modifier::Win | Modifier::RightWin => win,
+    }).collect::<Vec<&str>>();
+
+    let mut commands = vec![];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut args = vec![];
+        for m in names("ctrl", "alt", "logo") {
+            args.extend(["-M", m]);
+        }
+        args.extend(["-k", &key]);
+        for m in names("ctrl", "alt", "logo") {
+            args.extend(["-m", m]);
+        }
+        commands.push(command("wtype", &args));
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        let mut chord = names("ctrl", "alt", "super");
+        chord.push(&key);
+        commands.push(command("xdotool", &["key", "--clearmodifiers", &chord.join("+")]));
+    }
+    Ok(commands)
+}
+
+/// X keysym name of key.
+#[cfg(target_os = "linux")]
+fn keysym(code: WellKnownCode) -> String {
+    use WellKnownCode::*;
+    let name = match code {
+        Enter => "Return",
+        Escape => "Escape",
+        Backspace => "BackSpace",
+        Tab => "Tab",
+        Space => "space",
+        Minus => "minus",
+        Equal => "equal",
+        LeftBracket => "bracketleft",
+        RightBracket => "bracketright",
+        Backslash | NonUSHash | NonUSBackslash => "backslash",
+        Semicolon => "semicolon",
+        Quote => "apostrophe",
+        Grave => "grave",
+        Comma => "comma",
+        Dot => "period",
+        Slash => "slash",
+        CapsLock => "Caps_Lock",
+        PrintScreen => "Print",
+        ScrollLock => "Scroll_Lock",
+        Pause => "Pause",
+        Insert => "Insert",
+        Home => "Home",
+        PageUp => "Prior",
+        Delete => "Delete",
+        End => "End",
+        PageDown => "Next",
+        Right => "Right",
+        Left => "Left",
+        Down => "Down",
+        Up => "Up",
+        NumLock => "Num_Lock",
+        NumPadSlash => "KP_Divide",
+        NumPadAsterisk => "KP_Multiply",
+        NumPadMinus => "KP_Subtract",
+        NumPadPlus => "KP_Add",
+        NumPadEnter => "KP_Enter",
+        NumPadDot => "KP_Decimal",
+        NumPadEqual => "KP_Equal",
+        Application => "Menu",
+        Power => "XF86PowerOff",
+        NumPad1 | NumPad2 | NumPad3 | NumPad4 | NumPad5 | NumPad6 | NumPad7 | NumPad8 | NumPad9 | NumPad0 => {
+            return format!("KP_{}", &code.to_string()["numpad".len()..]);
+        }
+        // Letters, digits and function keys.
+        _ => {
+            let name = code.to_string();
+            return if name.len() > 1 { name.to_uppercase() } else { name };
+        }
+    };
+    name.to_owned()
+}
+
+#[cfg(target_os = "macos")]
+fn type_text_commands(text: &str) -> Vec<Command> {
+    let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+    let script = format!("tell application \"System Events\" to keystroke \"{escaped}\"");
+    vec![command("osascript", &["-e", &script])]
+}
+
+#[cfg(target_os = "macos")]
+fn set_clipboard_commands() -> Vec<Command> {
+    vec![command("pbcopy", &[])]
+}
+
+#[cfg(target_os = "macos")]
+fn paste_commands() -> Vec<Command> {
+    let script = "tell application \"System Events\" to keystroke \"v\" using command down";
+    vec![command("osascript", &["-e", script])]
+}
+
+#[cfg(target_os = "macos")]
+fn press_chord_commands(modifiers: Modifiers, code: WellKnownCode) -> Result<Vec<Command>> {
+    use WellKnownCode::*;
+    // Virtual key codes of keys which aren't characters.
+    let key_code = match code {
+        Enter | NumPadEnter => Some(36),
+        Tab => Some(48),
+        Space => Some(49),
+        Backspace => Some(51),
+        Escape => Some(53),
+        Delete => Some(117),
+        Home => Some(115),
+        End => Some(119),
+        PageUp => Some(116),
+        PageDown => Some(121),
+        Left => Some(123),
+        Right => Some(124),
+        Down => Some(125),
+        Up => Some(126),
+        F1 => Some(122), F2 => Some(120), F3 => Some(99), F4 => Some(118), F5 => Some(96),
+        F6 => Some(97), F7 => Some(98), F8 => Some(100), F9 => Some(101), F10 => Some(109),
+        F11 => Some(103), F12 => Some(111), F13 => Some(105), F14 => Some(107), F15 => Some(113),
+        F16 => Some(106), F17 => Some(64), F18 => Some(79), F19 => Some(80), F20 => Some(90),
+        _ => None,
+    };
+    let key = match (key_code, key_char(code)) {
+        (Some(key_code), _) => format!("key code {key_code}"),
+        (None, Some(c)) => format!("keystroke \"{}\"", if c == '\\' || c == '"' { format!("\\{c}") } else { c.to_string() }),
+        (None, None) => bail!("key '{code}' can't be pressed on macOS"),
+    };
+    let modifiers: Vec<&str> = modifiers.iter().map(|m| match m {
+        Modifier::Ctrl | Modifier::RightCtrl => "control down",
+        Modifier::Shift | Modifier::RightShift => "shift down",
+        Modifier::Alt | Modifier::RightAlt => "option down",
+        Modifier::Win | Modifier::RightWin => "command down",
+    }).collect();
+    let script = format!("tell application \"System Events\" to {key} using {{{}}}", modifiers.join(", "));
+    Ok(vec![command("osascript", &["-e", &script])])
+}
+
+#[cfg(windows)]
+fn type_text_commands(text: &str) -> Vec<Command> {
+    // Characters having special meaning for SendKeys are enclosed in braces.
+    let escaped: String = text.chars().map(|c| match c {
+        '+' | '^' | '%' | '~' | '(' | ')' | '{' | '}' | '[' | ']' => format!("{{{c}}}"),
+        '\'' => "''".to_owned(),
+        c => c.to_string(),
+    }).collect();
+    let script = format!(
+        "Add-Type -AssemblyName System.Windows.Forms; [System.Windows.Forms.SendKeys]::SendWait('{escaped}')");
+    vec![command("powershell", &["-NoProfile", "-Command", &script])]
+}
+
+#[cfg(windows)]
+fn set_clipboard_commands() -> Vec<Command> {
+    let script = "Set-Clipboard -Value ([Console]::In.ReadToEnd())";
+    vec![command("powershell", &["-NoProfile", "-Command", script])]
+}
+
+#[cfg(windows)]
+fn paste_commands() -> Vec<Command> {
+    let script = "Add-Type -AssemblyName System.Windows.Forms; [System.Windows.Forms.SendKeys]::SendWait('^v')";
+    vec![command("powershell", &["-NoProfile", "-Command", script])]
+}
+
+#[cfg(windows)]
+fn press_chord_commands(modifiers: Modifiers, code: WellKnownCode) -> Result<Vec<Command>> {
+    use WellKnownCode::*;
+    let key = match code {
+        Enter | NumPadEnter => "{ENTER}".to_owned(),
+        Escape => "{ESC}".to_owned(),
+        Backspace => "{BACKSPACE}".to_owned(),
+        Tab => "{TAB}".to_owned(),
+        CapsLock => "{CAPSLOCK}".to_owned(),
+        PrintScreen => "{PRTSC}".to_owned(),
+        ScrollLock => "{SCROLLLOCK}".to_owned(),
+        Pause => "{BREAK}".to_owned(),
+        Insert => "{INSERT}".to_owned(),
+        Home => "{HOME}".to_owned(),
+        PageUp => "{PGUP}".to_owned(),
+        Delete => "{DELETE}".to_owned(),
+        End => "{END}".to_owned(),
+        PageDown => "{PGDN}".to_owned(),
+        Right => "{RIGHT}".to_owned(),
+        Left => "{LEFT}".to_owned(),
+        Down => "{DOWN}".to_owned(),
+        Up => "{UP}".to_owned(),
+        NumLock => "{NUMLOCK}".to_owned(),
+        NumPadSlash => "{DIVIDE}".to_owned(),
+        NumPadAsterisk => "{MULTIPLY}".to_owned(),
+        NumPadMinus => "{SUBTRACT}".to_owned(),
+        NumPadPlus => "{ADD}".to_owned(),
+        F1 | F2 | F3 | F4 | F5 | F6 | F7 | F8 | F9 | F10 | F11 | F12 | F13 | F14 | F15 | F16 =>
+            format!("{{{}}}", code.to_string().to_uppercase()),
+        _ => match key_char(code) {
+            Some(c @ ('+' | '^' | '%' | '~' | '(' | ')' | '{' | '}' | '[' | ']')) => format!("{{{c}}}"),
+            Some('\'') => "''".to_owned(),
+            Some(c) => c.to_string(),
+            None => bail!("key '{code}' can't be pressed on Windows"),
+        },
+    };
+    let mut chord = String::new();
+    for m in modifiers {
+        chord.push_str(match m {
+            Modifier::Ctrl | Modifier::RightCtrl => "^",
+            Modifier::Shift | Modifier::RightShift => "+",
+            Modifier::Alt | Modifier::RightAlt => "%",
+            Modifier::Win | Modifier::RightWin => bail!("Windows key can't be pressed on Windows"),
+        });
+    }
+    chord.push_str(&key);
+    let script = format!(
+        "Add-Type -AssemblyName System.Windows.Forms; [System.Windows.Forms.SendKeys]::SendWait('{chord}')");
+    Ok(vec![command("powershell", &["-NoProfile", "-Command", &script])])
+}
+
+/// Character typed by key without modifiers on US layout.
+#[cfg(any(target_os = "macos", windows))]
+fn key_char(code: WellKnownCode) -> Option<char> {
+    use WellKnownCode::*;
+    let c = match code {
+        Space => ' ',
+        Minus => '-',
+        Equal => '=',
+        LeftBracket => '[',
+        RightBracket => ']',
+        Backslash => '\\',
+        Semicolon => ';',
+        Quote => '\'',
+        Grave => '`',
+        Comma => ',',
+        Dot => '.',
+        Slash => '/',
+        NumPad1 | NumPad2 | NumPad3 | NumPad4 | NumPad5 | NumPad6 | NumPad7 | NumPad8 | NumPad9 | NumPad0 =>
+            return code.to_string().chars().last(),
+        _ => {
+            let name = code.to_string();
+            let mut chars = name.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            };
+        }
+    };
+    Some(c)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn type_text_commands(_text: &str) -> Vec<Command> {
+    vec![]
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn set_clipboard_commands() -> Vec<Command> {
+    vec![]
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn paste_commands() -> Vec<Command> {
+    vec![]
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn press_chord_commands(_modifiers: Modifiers, _code: WellKnownCode) -> Result<Vec<Command>> {
+    Ok(vec![])
+}
+
+fn command(program: &str, args: &[&str]) -> Command {
+    let mut command = Command::new(program);
+    command.args(args);
+    command
+}
+
+/// Runs first command which is installed, feeding it given input.
+fn run_first_available(purpose: &str, commands: Vec<Command>, input: Option<&str>) -> Result<()> {
+    let mut tried = vec![];
+    for mut command in commands {
+        let program = command.get_program().to_string_lossy().into_owned();
+        debug!("run {command:?}");
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        match command.spawn() {
+            Ok(mut child) => {
+                if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+                    stdin.write_all(input.as_bytes()).with_context(|| format!("write to {program}"))?;
+                }
+                let status = child.wait().with_context(|| format!("run {program}"))?;
+                ensure!(status.success(), "{program} failed: {status}");
+                return Ok(());
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => tried.push(program),
+            Err(err) => return Err(err).with_context(|| format!("run {program}")),
+        }
+    }
+    Err(anyhow!("no tool for {purpose} found, install one of: {}", tried.join(", ")))
+}
@@ -1,22 +1,23 @@
-///! Collection of NOM parsers for various things.
-///! Generally only `parse` and `from_str` functions should be called
-///! from outside of this module, they ensures that whole input is
-///! consumed.
-///! Other functions are composable parsers for use within this module
-///! or as parameters for functions mentioned above.
+//! Collection of NOM parsers for various things.
+//! Generally only `parse` and `from_str` functions should be called
+//! from outside of this module, they ensures that whole input is
+//! consumed.
+//! Other functions are composable parsers for use within this module
+//! or as parameters for functions mentioned above.
 
 use nom::{
     Parser, IResult, InputLength,
     branch::alt,
     sequence::{tuple, terminated, separated_pair, delimited, pair, preceded},
     multi::{separated_list1, fold_many0},
-    bytes::complete::tag,
-    character::complete::{char, alpha1, alphanumeric1, digit1},
-    combinator::{map, map_res, opt, all_consuming, value},
+    bytes::complete::{tag, take_while_m_n},
+    character::complete::{char, alpha1, alphanumeric1, digit1, one_of, satisfy, space1},
+    combinator::{map, map_res, opt, all_consuming, recognize, value},
     error::ParseError,
 };
 
-use crate::keyboard::{Accord, Modifier, Modifiers, Macro, KeyboardPart, MouseEvent, MouseModifier, MouseButton, MouseButtons, MouseAction, MediaCode, Code, WellKnownCode};
+use crate::config::KeyRef;
+use crate::keyboard::{Accord, KnobAction, Modifier, Modifiers, Macro, KeyboardPart, MouseEvent, MouseModifier, MouseButton, MouseButtons, MouseAction, MediaCode, Code, WellKnownCode};
 
 use std::str::FromStr;
 
@@ -81,16 +82,26 @@ pub fn accord(s: &str) -> IResult<&str, Accord> {
 
 fn mouse_event(s: &str) -> IResult<&str, MouseEvent> {
     let button = alt((
-        value(MouseButton::Left, alt((tag("click"), tag("lclick")))),
-        value(MouseButton::Right, tag("rclick")),
-        value(MouseButton::Middle, tag("mclick")),
+        value(MouseButton::Left, alt((tag("click"), tag("lclick"), tag("leftclick")))),
+        value(MouseButton::Right, alt((tag("rclick"), tag("rightclick")))),
+        value(MouseButton::Middle, alt((tag("mclick"), tag("middleclick")))),
     ));
     let buttons = map(separated_list1(char('+'), button), MouseButtons::from_iter);
     let click = map(buttons, MouseAction::Click);
 
+    // Number of notches may be given: `wheelup*3`.
+    let notches = || map(
+        opt(preceded(char('*'), map_res(digit1, |d: &str| match d.parse::<u8>() {
+            Ok(n @ 1..=127) => Ok(n),
+            _ => Err("number of wheel notches must be from 1 to 127"),
+        }))),
+        |n| n.unwrap_or(1),
+    );
     let wheel = alt((
-        value(MouseAction::WheelUp, tag("wheelup")),
-        value(MouseAction::WheelDown, tag("wheeldown")),
+        map(preceded(tag("wheelup"), notches()), MouseAction::WheelUp),
+        map(preceded(tag("wheeldown"), notches()), MouseAction::WheelDown),
+        map(preceded(tag("wheelleft"), notches()), MouseAction::WheelLeft),
+        map(preceded(tag("wheelright"), notches()), MouseAction::WheelRight),
     ));
 
     let mousemove = map(
@@ -98,10 +109,21 @@ fn mouse_event(s: &str) -> IResult<&str, MouseEvent> {
         |(dx, dy)| MouseAction::Move { dx, dy }
     );
 
+    // Button is held while moving: `drag[10,0]`, `mdrag[0,-10]`.
+    let drag_button = alt((
+        value(MouseButton::Left, alt((tag("drag"), tag("ldrag")))),
+        value(MouseButton::Right, tag("rdrag")),
+        value(MouseButton::Middle, tag("mdrag")),
+    ));
+    let drag = map(
+        pair(drag_button, delimited(char('['), separated_pair(signed_int, char(','), signed_int), char(']'))),
+        |(button, (dx, dy))| MouseAction::Drag { button, dx, dy }
+    );
+
     let mut event = map(
         tuple((
             opt(terminated(mouse_modifier, char('-'))),
-            alt((click, wheel, mousemove)),
+            alt((click, wheel, mousemove, drag)),
         )),
         |(modifier, action)| MouseEvent(action, modifier)
     );
@@ -117,8 +139,23 @@ fn delay_part(s: &str) -> IResult<&str, KeyboardPart> {
     parser(s)
 }
 
+/// Single character which isn't a key name, like `!` or `:`,
+/// with optional modifiers.
+fn char_part(s: &str) -> IResult<&str, KeyboardPart> {
+    let mut parser = map(
+        pair(
+            fold_many0(terminated(modifier, char('-')),
+                       Modifiers::empty,
+                       |mods, m| mods | m),
+            satisfy(|c| !c.is_ascii_alphanumeric() && !c.is_whitespace() && c != ','),
+        ),
+        |(mods, c)| KeyboardPart::Char(mods, c),
+    );
+    parser(s)
+}
+
 fn keyboard_part(s: &str) -> IResult<&str, KeyboardPart> {
-    alt((map(accord, KeyboardPart::Key), delay_part))(s)
+    alt((map(accord, KeyboardPart::Key), delay_part, char_part))(s)
 }
 
 pub fn r#macro(s: &str) -> IResult<&str, Macro> {
@@ -135,6 +172,7 @@ pub fn r#macro(s: &str) -> IResult<&str, Macro> {
     }
 
     let mut parser = alt((
+        value(Macro::Keyboard(vec![]), all_consuming(tag("disabled"))),
         map(mouse_event, Macro::Mouse),
         map(media_code, Macro::Media),
         map(separated_list1(char(','), keyboard_part), Macro::Keyboard),
@@ -142,12 +180,39 @@ pub fn r#macro(s: &str) -> IResult<&str, Macro> {
     parser(s)
 }
 
+pub fn key_ref(s: &str) -> IResult<&str, KeyRef> {
+    // One-based numbers are used in config, zero-based internally.
+    let number = || map_res(digit1, |d: &str| match d.parse::<u8>() {
+        Ok(0) => Err("numbering starts from 1"),
+        Ok(n) => Ok(n - 1),
+        Err(_) => Err("number is too large"),
+    });
+    let knob_action = map_res(alpha1, KnobAction::from_str);
+    let mut parser = alt((
+        map(preceded(tag("knob"), separated_pair(number(), char('-'), knob_action)),
+            |(n, action)| KeyRef::Knob(n, action)),
+        value(KeyRef::All, char('*')),
+        map(pair(preceded(one_of("rR"), number()), preceded(one_of("cC"), number())), |(r, c)| KeyRef::Cell(r, c)),
+        map(preceded(one_of("kK"), number()), KeyRef::Legend),
+        map(number(), KeyRef::Button),
+        map(recognize(separated_list1(char('-'), alpha1)), |name: &str| KeyRef::Named(name.to_owned())),
+    ));
+    parser(s)
+}
+
 pub fn address(s: &str) -> IResult<&str, (u8, u8)> {
     let byte = || map_res(digit1, u8::from_str);
     let mut parser = separated_pair(byte(), char(':'), byte());
     parser(s)
 }
 
+/// Parses hex bytes separated by spaces: `03 fe 01`.
+pub fn hex_bytes(s: &str) -> IResult<&str, Vec<u8>> {
+    let byte = map_res(take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()), |b| u8::from_str_radix(b, 16));
+    let mut parser = separated_list1(space1, byte);
+    parser(s)
+}
+
 /// Parses string with given parser ensuring that whole input is consumed.
 pub fn parse<I, O, E, P>(parser: P, input: I) -> std::result::Result<O, E>
 where
@@ -172,9 +237,72 @@ where
     }
 }
 
+/// Words of mouse actions and macro syntax, other names are taken from enums.
+const SYNTAX_WORDS: [(&str, &str); 18] = [
+    ("click", "mouse action"), ("lclick", "mouse action"), ("leftclick", "mouse action"),
+    ("rclick", "mouse action"), ("rightclick", "mouse action"), ("mclick", "mouse action"),
+    ("middleclick", "mouse action"), ("wheelup", "mouse action"), ("wheeldown", "mouse action"),
+    ("wheelleft", "mouse action"), ("wheelright", "mouse action"), ("mousemove", "mouse action"),
+    ("drag", "mouse action"), ("ldrag", "mouse action"), ("rdrag", "mouse action"), ("mdrag", "mouse action"),
+    ("delay", "macro syntax"), ("disabled", "macro syntax"),
+];
+
+/// Explains why macro doesn't parse if it has unknown name, suggesting
+/// known names closest to it, like `unknown name 'volumup', did you mean
+/// 'volumeup' (media key)?`.
+pub fn suggest(s: &str) -> Option<String> {
+    use strum::{EnumMessage as _, IntoEnumIterator as _};
+
+    let mut names: Vec<(String, &str)> = SYNTAX_WORDS.iter().map(|&(name, kind)| (name.to_owned(), kind)).collect();
+    names.extend(WellKnownCode::iter().map(|code| (code.to_string(), "key")));
+    names.extend(Modifier::iter().flat_map(|m| m.get_serializations().iter().map(|name| (name.to_string(), "modifier")).collect::<Vec<_>>()));
+    names.extend(MediaCode::iter().flat_map(|c| c.get_serializations().iter().map(|name| (name.to_string(), "media key")).collect::<Vec<_>>()));
+
+    let unknown = s.split(|c: char| !c.is_ascii_alphanumeric())
+        .map(str::to_ascii_lowercase)
+        .find(|word| !word.is_empty() && !word.bytes().all(|b| b.is_ascii_digit())
+                     && !names.iter().any(|(name, _)| name == word))?;
+
+    let limit = (unknown.len() / 3).max(1);
+    let mut close: Vec<(usize, &(String, &str))> = names.iter()
+        .map(|entry| (edit_distance(&unknown, &entry.0), entry))
+        .filter(|(distance, _)| *distance <= limit)
+        .collect();
+    close.sort_by_key(|(distance, _)| *distance);
+    if close.is_empty() {
+        return Some(format!("unknown name '{unknown}', run `show-keys` to list key names"));
+    }
+    let close: Vec<String> = close.iter().take(3).map(|(_, (name, kind))| format!("'{name}' ({kind})")).collect();
+    Some(format!("unknown name '{unknown}', did you mean {}?", close.join(" or ")))
+}
+
+/// Edit distance between ASCII words, swapped adjacent letters count as
+/// one edit, as typos go.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j - 1] + cost).min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::keyboard::{Accord, Modifiers, Code, Modifier, Macro, KeyboardPart, MouseEvent, MouseModifier, MouseButton, MouseAction, MediaCode, WellKnownCode};
+    use strum::{EnumMessage as _, IntoEnumIterator as _};
+
+    use crate::config::KeyRef;
+    use crate::keyboard::{Accord, KnobAction, Modifiers, Code, Modifier, Macro, KeyboardPart, MouseEvent, MouseModifier, MouseButton, MouseAction, MediaCode, WellKnownCode};
 
     #[test]
     fn parse_custom_code() {
@@ -195,6 +323,15 @@ mod tests {
         assert!("a+".parse::<Accord>().is_err());
     }
 
+    #[test]
+    fn parse_high_function_keys() {
+        for (n, code) in (13..=24).zip(WellKnownCode::F13 as u8..) {
+            let Ok(Accord { code: Some(Code::WellKnown(key)), .. }) = format!("ctrl-F{n}").parse::<Accord>() else { panic!("f{n} not parsed") };
+            assert_eq!((key.to_string(), key as u8), (format!("f{n}"), code));
+        }
+        assert_eq!(WellKnownCode::F24 as u8, 0x73);
+    }
+
     #[test]
     fn parse_macro() {
         assert_eq!("A,B".parse(), Ok(Macro::Keyboard(vec![
@@ -212,16 +349,91 @@ mod tests {
             MouseEvent(MouseAction::Click(MouseButton::Left | MouseButton::Right), None)
         )));
         assert_eq!("ctrl-wheelup".parse(), Ok(Macro::Mouse(
-            MouseEvent(MouseAction::WheelUp, Some(MouseModifier::Ctrl))
+            MouseEvent(MouseAction::WheelUp(1), Some(MouseModifier::Ctrl))
         )));
         assert_eq!("ctrl-click".parse(), Ok(Macro::Mouse(
             MouseEvent(MouseAction::Click(MouseButton::Left.into()), Some(MouseModifier::Ctrl))
         )));
     }
 
+    #[test]
+    fn parse_click_aliases() {
+        assert_eq!("middleclick".parse(), "mclick".parse::<Macro>());
+        assert_eq!("leftclick+rightclick".parse(), "click+rclick".parse::<Macro>());
+    }
+
+    #[test]
+    fn parse_wheel_notches() {
+        assert_eq!("wheeldown*3".parse(), Ok(Macro::Mouse(MouseEvent(MouseAction::WheelDown(3), None))));
+        assert_eq!("wheelup".parse::<Macro>().unwrap().to_string(), "wheelup");
+        assert_eq!("wheelup*127".parse::<Macro>().unwrap().to_string(), "wheelup*127");
+        assert!("wheelup*0".parse::<Macro>().is_err());
+        assert!("wheelup*128".parse::<Macro>().is_err());
+        assert_eq!("wheelleft*2".parse(), Ok(Macro::Mouse(MouseEvent(MouseAction::WheelLeft(2), None))));
+        assert_eq!("wheelright".parse::<Macro>().unwrap().to_string(), "wheelright");
+    }
+
+    #[test]
+    fn parse_hex_bytes() {
+        assert_eq!(super::from_str(super::hex_bytes, "03 fe 0A"), Ok(vec![0x03, 0xfe, 0x0a]));
+        assert!(super::from_str(super::hex_bytes, "3 fe").is_err());
+        assert!(super::from_str(super::hex_bytes, "03fe").is_err());
+    }
+
+    #[test]
+    fn parse_key_ref() {
+        assert_eq!("1".parse(), Ok(KeyRef::Button(0)));
+        assert_eq!("12".parse(), Ok(KeyRef::Button(11)));
+        assert_eq!("knob2-cw".parse(), Ok(KeyRef::Knob(1, KnobAction::RotateCW)));
+        assert_eq!("knob1-press".parse(), Ok(KeyRef::Knob(0, KnobAction::Press)));
+        assert_eq!("knob-press".parse(), Ok(KeyRef::Named("knob-press".to_owned())));
+        assert_eq!("K12".parse(), Ok(KeyRef::Legend(11)));
+        assert_eq!("k1".parse(), Ok(KeyRef::Legend(0)));
+        assert_eq!("r2c3".parse(), Ok(KeyRef::Cell(1, 2)));
+        assert_eq!("*".parse(), Ok(KeyRef::All));
+
+        assert!("0".parse::<KeyRef>().is_err());
+        assert!("knob0-cw".parse::<KeyRef>().is_err());
+        assert!("knob1-left".parse::<KeyRef>().is_err());
+    }
+
+    #[test]
+    fn parse_characters() {
+        assert_eq!("!,a".parse(), Ok(Macro::Keyboard(vec![
+            KeyboardPart::Char(Modifiers::empty(), '!'),
+            KeyboardPart::Key(Accord::new(Modifiers::empty(), Some(WellKnownCode::A.into()))),
+        ])));
+        assert_eq!("ctrl-+".parse(), Ok(Macro::Keyboard(vec![
+            KeyboardPart::Char(Modifier::Ctrl.into(), '+'),
+        ])));
+        assert_eq!("é".parse(), Ok(Macro::Keyboard(vec![
+            KeyboardPart::Char(Modifiers::empty(), 'é'),
+        ])));
+        assert!("!!".parse::<Macro>().is_err());
+    }
+
     #[test]
     fn parse_media() {
         assert_eq!("play".parse(), Ok(Macro::Media(MediaCode::Play)));
+        assert_eq!("fastforward".parse(), Ok(Macro::Media(MediaCode::FastForward)));
+        assert_eq!("brightnessup".parse(), Ok(Macro::Media(MediaCode::BrightnessUp)));
+        // Media names are tried first, so they must not hide key names.
+        for code in MediaCode::iter() {
+            for name in code.get_serializations() {
+                assert!(name.parse::<WellKnownCode>().is_err(), "media name '{name}' hides key");
+            }
+        }
+    }
+
+    #[test]
+    fn suggest_names() {
+        assert_eq!(super::suggest("volumup").as_deref(), Some("unknown name 'volumup', did you mean 'volumeup' (media key)?"));
+        assert_eq!(super::suggest("ctrl-shfit-a").as_deref(), Some("unknown name 'shfit', did you mean 'shift' (modifier)?"));
+        assert_eq!(super::suggest("delay[100],a,entr").as_deref(), Some("unknown name 'entr', did you mean 'enter' (key)?"));
+        assert!(super::suggest("xyzzyq").unwrap().contains("show-keys"));
+        assert_eq!(super::suggest("ctrl-a,mousemove[-10,0]"), None);
+        assert_eq!(super::edit_distance("kitten", "sitting"), 3);
+        assert_eq!(super::edit_distance("shfit", "shift"), 1);
     }
 
     #[test]
@@ -234,4 +446,13 @@ mod tests {
             MouseEvent(MouseAction::Move { dx: -5, dy: -10 }, None)
         )));
     }
+
+    #[test]
+    fn parse_drag() {
+        let drag: Macro = "shift-mdrag[-20,0]".parse().unwrap();
+        assert_eq!(drag, Macro::Mouse(MouseEvent(MouseAction::Drag { button: MouseButton::Middle, dx: -20, dy: 0 }, Some(MouseModifier::Shift))));
+        assert_eq!("mdrag[-20,0]".parse::<Macro>().unwrap().to_string(), "mdrag[-20,0]");
+        assert_eq!("ldrag[1,2]".parse::<Macro>().unwrap().to_string(), "drag[1,2]");
+        assert!("drag[1]".parse::<Macro>().is_err());
+    }
 }
@@ -0,0 +1,89 @@
+//! Locations of default config file and of upload journals.
+//!
+//! Config is looked up in `$XDG_CONFIG_HOME/ch57x/mapping.yaml` (or
+//! `~/.config/ch57x/mapping.yaml`) and in platform config directory:
+//! `~/Library/Application Support/ch57x` on macOS, `%APPDATA%\ch57x` on Windows.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{ensure, Context as _, Result};
+
+const APP_DIR: &str = "ch57x";
+const CONFIG_FILE: &str = "mapping.yaml";
+
+/// Candidate paths of default config file in order of preference.
+pub fn config_candidates() -> Vec<PathBuf> {
+    let env_path = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let home = env_path("HOME");
+
+    let mut dirs = vec![];
+    dirs.extend(env_path("XDG_CONFIG_HOME"));
+    dirs.extend(home.as_ref().map(|home| home.join(".config")));
+    if cfg!(target_os = "macos") {
+        dirs.extend(home.as_ref().map(|home| home.join("Library/Application Support")));
+    }
+    if cfg!(windows) {
+        dirs.extend(env_path("APPDATA"));
+    }
+
+    let mut candidates: Vec<PathBuf> = vec![];
+    for dir in dirs {
+        let path = dir.join(APP_DIR).join(CONFIG_FILE);
+        if !candidates.contains(&path) {
+            candidates.push(path);
+        }
+    }
+    candidates
+}
+
+/// Returns first existing default config file or, if there is none, path
+/// where it is expected to be created.
+pub fn default_config_path() -> Option<PathBuf> {
+    let candidates = config_candidates();
+    candidates.iter().find(|path| path.is_file()).or(candidates.first()).cloned()
+}
+
+/// Directory for state kept between runs, like upload journals:
+/// `$XDG_STATE_HOME/ch57x` (or `~/.local/state/ch57x`), on macOS in
+/// `~/Library/Application Support/ch57x`, `%LOCALAPPDATA%\ch57x` on Windows.
+pub fn state_dir() -> Option<PathBuf> {
+    let env_path = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let dir = if cfg!(target_os = "macos") {
+        env_path("HOME").map(|home| home.join("Library/Application Support"))
+    } else if cfg!(windows) {
+        env_path("LOCALAPPDATA")
+    } else {
+        env_path("XDG_STATE_HOME").or_else(|| env_path("HOME").map(|home| home.join(".local/state")))
+    };
+    dir.map(|dir| dir.join(APP_DIR))
+}
+
+/// Opens file in `$VISUAL`/`$EDITOR` or in default application.
+pub fn open(path: &Path) -> Result<()> {
+    let editor = ["VISUAL", "EDITOR"].iter()
+        .filter_map(std::env::var_os)
+        .map(|v| v.to_string_lossy().into_owned())
+        .find(|v| !v.trim().is_empty());
+    let mut command = match editor {
+        Some(editor) => {
+            // Editor may be given with arguments, like "code --wait".
+            let mut parts = editor.split_whitespace();
+            let mut command = Command::new(parts.next().unwrap());
+            command.args(parts);
+            command
+        }
+        None if cfg!(target_os = "macos") => Command::new("open"),
+        None if cfg!(windows) => {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        }
+        None => Command::new("xdg-open"),
+    };
+    command.arg(path);
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command.status().with_context(|| format!("run {program}"))?;
+    ensure!(status.success(), "{program} failed: {status}");
+    Ok(())
+}
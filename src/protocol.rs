@@ -0,0 +1,277 @@
+//! Knowledge about keyboard protocols: limits of macros and meaning of
+//! messages, used to show raw messages in human readable form.
+//!
+//! Only messages sent to keyboard are known. Keyboards aren't known to
+//! reply to them, so any message received from keyboard is shown as unknown.
+
+use std::fmt::Write as _;
+
+use anyhow::Result;
+use itertools::Itertools as _;
+use strum::IntoEnumIterator as _;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::keyboard::{k884x, k8890, Accord, Code, Key, KnobAction, Macro, MacroKind, MediaCode, Modifiers, Quirks, WellKnownCode};
+
+/// Keyboard firmware protocol, it limits which macros may be uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumIter, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Protocol {
+    K884x,
+    K8890,
+}
+
+impl Protocol {
+    pub fn for_product(product_id: u16) -> Option<Self> {
+        Protocol::iter().find(|protocol| protocol.product_ids().contains(&product_id))
+    }
+
+    /// USB product IDs of keyboards using protocol.
+    pub fn product_ids(self) -> &'static [u16] {
+        match self {
+            Protocol::K884x => &[0x8840, 0x8842, 0x8850],
+            Protocol::K8890 => &[0x8890],
+        }
+    }
+
+    /// Maximum number of key presses in keyboard macro.
+    pub fn max_presses(self) -> usize {
+        match self {
+            Protocol::K884x => 18,
+            Protocol::K8890 => 5,
+        }
+    }
+
+    pub fn supports_delays(self) -> bool {
+        self == Protocol::K884x
+    }
+
+    /// Whether wheel may be scrolled by several notches at once.
+    pub fn supports_wheel_notches(self) -> bool {
+        self == Protocol::K884x
+    }
+
+    /// Whether pointer may be moved with mouse button held.
+    pub fn supports_drag(self) -> bool {
+        self == Protocol::K884x
+    }
+
+    /// Table of binding message kind bytes.
+    pub fn kinds(self) -> &'static [(MacroKind, u8)] {
+        match self {
+            Protocol::K884x => k884x::KINDS,
+            Protocol::K8890 => k8890::KINDS,
+        }
+    }
+
+    /// Encodes messages binding key on zero-based layer, same as keyboard
+    /// using protocol does, but without device.
+    pub fn encode(self, layer: u8, key: Key, macro_: &Macro, quirks: Quirks) -> Result<Vec<Vec<u8>>> {
+        match self {
+            Protocol::K884x => k884x::bind_messages(layer, key, macro_, quirks),
+            Protocol::K8890 => k8890::bind_messages(layer, key, macro_, quirks),
+        }
+    }
+
+    /// Key ids are counted from this base for knobs.
+    fn key_id_base(self) -> u8 {
+        match self {
+            Protocol::K884x => 15,
+            Protocol::K8890 => 12,
+        }
+    }
+}
+
+/// Describes message sent to keyboard, like `bind button 0 on layer 1 to ctrl-c`.
+pub fn decode(protocol: Protocol, message: &[u8]) -> String {
+    // Messages are padded with zeroes.
+    let len = message.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    let message = &message[..len];
+    let byte = |i: usize| message.get(i).copied().unwrap_or(0);
+    let key = |id: u8| Key::from_key_id(id, protocol.key_id_base())
+        .map_or_else(|| format!("unknown key {id}"), |key| key.to_string());
+
+    match (protocol, message) {
+        (_, []) => "empty message".to_owned(),
+        (_, [0x03, 0xaa, 0xaa]) => "end of programming".to_owned(),
+        (Protocol::K884x, [0x03, 0xfd, 0xfe, 0xff]) => "save bindings".to_owned(),
+
+        (Protocol::K884x, [0x03, 0xfe, id, layer, kind, ..]) => {
+            let target = format!("{} on layer {layer}", key(*id));
+            match MacroKind::from_byte(protocol.kinds(), *kind) {
+                Some(MacroKind::Keys) => {
+                    let count = byte(10) as usize;
+                    let accords: Vec<String> = (0..count).map(|i| accord(byte(11 + 2 * i), byte(12 + 2 * i))).collect();
+                    format!("bind {target} to {}", accords.join(","))
+                }
+                Some(MacroKind::Media) => format!("bind {target} to {}", media(u16::from_le_bytes([byte(11), byte(12)]))),
+                Some(MacroKind::Mouse) => format!("bind {target} to mouse event: modifier {:#04x}, buttons {:#04x}, x {}, y {}, wheel {}",
+                             byte(11), byte(12), byte(13) as i8, byte(14) as i8, byte(15) as i8),
+                Some(MacroKind::Delay) => format!("set delay of {target} to {}ms", u16::from_le_bytes([byte(5), byte(6)])),
+                None => format!("bind {target} to unknown macro kind {kind}"),
+            }
+        }
+
+        (Protocol::K8890, [0x03, 0xfe, layer, 0x01, 0x01]) => format!("start programming layer {layer}"),
+        (Protocol::K8890, [0x03, id, layer_kind, ..]) => {
+            let target = format!("{} on layer {}", key(*id), layer_kind >> 4);
+            let kind = layer_kind & 0x0f;
+            match MacroKind::from_byte(protocol.kinds(), kind) {
+                Some(MacroKind::Keys) => match (byte(3), byte(4)) {
+                    (len, 0) => format!("bind {target} to {len} key press(es)"),
+                    (len, i) => format!("bind {target}, key press {i} of {len}: {}", accord(byte(5), byte(6))),
+                },
+                Some(MacroKind::Media) => format!("bind {target} to {}", media(u16::from_le_bytes([byte(3), byte(4)]))),
+                Some(MacroKind::Mouse) => format!("bind {target} to mouse event: buttons {:#04x}, y {}, x {}, wheel {}, modifier {:#04x}",
+                             byte(3), byte(4) as i8, byte(5) as i8, byte(6) as i8, byte(7)),
+                _ => format!("bind {target} to unknown macro kind {kind}"),
+            }
+        }
+
+        _ => "unknown message".to_owned(),
+    }
+}
+
+/// Bindings encoded to document message formats, one of each kind.
+const SAMPLES: &[(Key, &str)] = &[
+    (Key::Button(0), "ctrl-c,enter"),
+    (Key::Button(1), "delay[1000],a"),
+    (Key::Knob(0, KnobAction::Press), "mute"),
+    (Key::Knob(0, KnobAction::RotateCW), "ctrl-wheelup"),
+    (Key::Button(2), "click"),
+    (Key::Button(3), "mousemove[10,-5]"),
+    (Key::Button(4), "mdrag[10,0]"),
+];
+
+/// Describes message formats of protocol in Markdown. Messages are produced
+/// by encoders themselves, so description can't get out of date.
+pub fn describe(protocol: Protocol) -> String {
+    let mut out = String::new();
+    writeln!(out, "# {protocol} protocol\n").unwrap();
+    writeln!(out, "Product IDs: {}", protocol.product_ids().iter().map(|id| format!("{id:04x}")).join(", ")).unwrap();
+    writeln!(out, "Macro kinds: {}", protocol.kinds().iter().map(|(kind, byte)| format!("{kind} = {byte}")).join(", ")).unwrap();
+    writeln!(out, "Key presses per macro: {}", protocol.max_presses()).unwrap();
+    writeln!(out, "\nMessages are padded with zeroes to 64 bytes, trailing zeroes are omitted below.").unwrap();
+
+    for (key, sample) in SAMPLES {
+        let macro_: Macro = sample.parse().expect("samples are valid macros");
+        writeln!(out, "\n## Bind {key} on layer 1 to `{sample}`\n").unwrap();
+        match protocol.encode(0, *key, &macro_, Quirks::empty()) {
+            Ok(messages) => {
+                writeln!(out, "```").unwrap();
+                for message in messages {
+                    let len = message.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                    writeln!(out, "{:<48} {}", hex(&message[..len]), decode(protocol, &message)).unwrap();
+                }
+                writeln!(out, "```").unwrap();
+            }
+            Err(err) => writeln!(out, "Not supported: {err}.").unwrap(),
+        }
+    }
+    out
+}
+
+/// Formats bytes as hex separated by spaces: `03 fe 01`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+}
+
+fn accord(modifiers: u8, code: u8) -> String {
+    let code = (code != 0).then(|| {
+        WellKnownCode::iter().find(|c| *c as u8 == code).map_or(Code::Custom(code), Code::WellKnown)
+    });
+    Accord::new(Modifiers::from_u8_truncated(modifiers), code).to_string()
+}
+
+fn media(code: u16) -> String {
+    MediaCode::iter().find(|c| *c as u16 == code)
+        .map_or_else(|| format!("unknown media code {code:#06x}"), |c| c.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator as _;
+
+    use super::{decode, describe, Protocol};
+    use crate::consts::DEVICE_IDS;
+    use crate::keyboard::{Key, KnobAction, MacroKind, Quirks};
+
+    // Messages as sent by `upload`.
+    #[test]
+    fn decode_k884x_messages() {
+        let bind = [0x03, 0xfe, 0x01, 0x01, 0x01, 0, 0, 0, 0, 0, 0x02, 0x01, 0x06, 0x00, 0x28];
+        assert_eq!(decode(Protocol::K884x, &bind), "bind button 0 on layer 1 to ctrl-c,enter");
+        let media = [0x03, 0xfe, 0x11, 0x02, 0x02, 0, 0, 0, 0, 0, 0, 0xe2, 0x00];
+        assert_eq!(decode(Protocol::K884x, &media), "bind knob 0 press on layer 2 to mute");
+        let delay = [0x03, 0xfe, 0x02, 0x01, 0x05, 0xe8, 0x03, 0, 0, 0, 0x01, 0x00, 0x04];
+        assert_eq!(decode(Protocol::K884x, &delay), "set delay of button 1 on layer 1 to 1000ms");
+        assert_eq!(decode(Protocol::K884x, &[0x03, 0xfd, 0xfe, 0xff, 0, 0]), "save bindings");
+        assert_eq!(decode(Protocol::K884x, &[0; 64]), "empty message");
+        assert_eq!(decode(Protocol::K884x, &[0x05, 0x01]), "unknown message");
+    }
+
+    #[test]
+    fn decode_k8890_messages() {
+        assert_eq!(decode(Protocol::K8890, &[0x03, 0xfe, 0x01, 0x01, 0x01, 0, 0, 0, 0]), "start programming layer 1");
+        assert_eq!(decode(Protocol::K8890, &[0x03, 0x02, 0x11, 0x01, 0x00, 0, 0, 0, 0]), "bind button 1 on layer 1 to 1 key press(es)");
+        assert_eq!(decode(Protocol::K8890, &[0x03, 0x02, 0x11, 0x01, 0x01, 0x02, 0x04, 0, 0]),
+                   "bind button 1 on layer 1, key press 1 of 1: shift-a");
+        assert_eq!(decode(Protocol::K8890, &[0x03, 0x0e, 0x12, 0xe9, 0x00, 0, 0, 0, 0]), "bind knob 0 press on layer 1 to volumeup");
+        assert_eq!(decode(Protocol::K8890, &[0x03, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0]), "end of programming");
+    }
+
+    // Pads with two or three knobs: ids of each knob action follow buttons
+    // and decode back to the same knob in both protocols.
+    #[test]
+    fn knob_ids_round_trip() {
+        for protocol in Protocol::iter() {
+            let base = protocol.key_id_base();
+            let mut ids = vec![];
+            for n in 0..3 {
+                for action in [KnobAction::RotateCCW, KnobAction::Press, KnobAction::RotateCW] {
+                    let id = Key::Knob(n, action).to_key_id(base).unwrap();
+                    assert!(id > base, "{protocol}: knob id {id} overlaps buttons");
+                    assert_eq!(Key::from_key_id(id, base).unwrap().to_string(), Key::Knob(n, action).to_string());
+                    ids.push(id);
+                }
+            }
+            ids.sort_unstable();
+            ids.dedup();
+            assert_eq!(ids.len(), 9, "{protocol}: knob ids aren't distinct");
+        }
+    }
+
+    // Modifier alone is pressed with zero key code.
+    #[test]
+    fn encode_modifier_only() {
+        let ctrl = "ctrl".parse().unwrap();
+        let k884x = Protocol::K884x.encode(0, Key::Button(0), &ctrl, Quirks::empty()).unwrap();
+        assert_eq!(decode(Protocol::K884x, &k884x[0]), "bind button 0 on layer 1 to ctrl");
+        assert_eq!(k884x[0][10..13], [1, 0x01, 0]);
+        let k8890 = Protocol::K8890.encode(0, Key::Button(0), &ctrl, Quirks::empty()).unwrap();
+        assert_eq!(decode(Protocol::K8890, &k8890[2]), "bind button 0 on layer 1, key press 1 of 1: ctrl");
+    }
+
+    #[test]
+    fn describe_protocol() {
+        let description = describe(Protocol::K884x);
+        assert!(description.contains("03 fe 01 01 01 00 00 00 00 00 02 01 06 00 28     bind button 0 on layer 1 to ctrl-c,enter\n"));
+        assert!(describe(Protocol::K8890).contains("Not supported: delays are not supported for this keyboard model."));
+    }
+
+    #[test]
+    fn product_ids_are_searched() {
+        for protocol in Protocol::iter() {
+            assert!(protocol.product_ids().iter().all(|id| DEVICE_IDS.iter().any(|(_, product_id)| product_id == id)), "{protocol}");
+        }
+        assert_eq!(Protocol::for_product(0x8842), Some(Protocol::K884x));
+    }
+
+    #[test]
+    fn macro_kind_tables() {
+        assert_eq!(MacroKind::Delay.byte(Protocol::K884x.kinds()).unwrap(), 5);
+        assert!(MacroKind::Delay.byte(Protocol::K8890.kinds()).is_err());
+        assert_eq!(MacroKind::from_byte(Protocol::K8890.kinds(), 3), Some(MacroKind::Mouse));
+        assert_eq!(MacroKind::from_byte(Protocol::K8890.kinds(), 5), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Interactive shell for exploring keyboard protocol: sends raw messages
+//! and shows replies.
+
+use std::io::Write as _;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+
+use crate::keyboard::Keyboard;
+use crate::parse;
+use crate::protocol::{self, hex, Protocol};
+
+/// How long `read` waits for reply by default.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+const HELP: &str = "\
+Commands:
+  send <hex bytes>   send message, like 'send 03 fe 01', 'send' may be omitted
+  read [ms]          wait for message from keyboard, 500ms by default
+  begin <layer>      send message starting programming of one-based layer (03 fe <layer> 01 01)
+  end                send message finishing programming (03 aa aa)
+  decode <hex bytes> describe message without sending it
+  history            list previous commands
+  !!, !<n>           repeat last or n-th command
+  help               show this help
+  quit               exit";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Send(Vec<u8>),
+    Read(Duration),
+    Decode(Vec<u8>),
+    History,
+    Help,
+    Quit,
+}
+
+/// Runs shell until `quit` command or end of input.
+pub fn run(keyboard: &mut dyn Keyboard, protocol: Protocol) -> Result<()> {
+    println!("Type 'help' to list commands.");
+    let mut history: Vec<String> = vec![];
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).context("read command")? == 0 {
+            return Ok(());
+        }
+        let line = match expand_history(line.trim(), &history) {
+            Ok(line) => line,
+            Err(err) => {
+                println!("{err}");
+                continue;
+            }
+        };
+        if line.is_empty() {
+            continue;
+        }
+
+        let command = match parse_command(&line) {
+            Ok(command) => command,
+            Err(err) => {
+                println!("{err}");
+                continue;
+            }
+        };
+        if command != Command::History {
+            history.push(line);
+        }
+
+        let result = match command {
+            Command::Send(message) => keyboard.send(&message)
+                .map(|()| println!("sent: {}", protocol::decode(protocol, &message))),
+            Command::Read(timeout) => keyboard.receive(timeout).map(|reply| match reply {
+                Some(reply) => println!("{}: {}", hex(&reply), protocol::decode(protocol, &reply)),
+                None => println!("no reply"),
+            }),
+            Command::Decode(message) => {
+                println!("{}", protocol::decode(protocol, &message));
+                Ok(())
+            }
+            Command::History => {
+                for (i, line) in history.iter().enumerate() {
+                    println!("{:4}  {line}", i + 1);
+                }
+                Ok(())
+            }
+            Command::Help => {
+                println!("{HELP}");
+                Ok(())
+            }
+            Command::Quit => return Ok(()),
+        };
+        if let Err(err) = result {
+            println!("error: {err:#}");
+        }
+    }
+}
+
+/// Replaces `!!` and `!<n>` with commands from history.
+fn expand_history(line: &str, history: &[String]) -> Result<String, String> {
+    let Some(reference) = line.strip_prefix('!') else { return Ok(line.to_owned()) };
+    let index = match reference {
+        "!" => history.len(),
+        n => n.parse().map_err(|_| format!("invalid history reference '{line}'"))?,
+    };
+    index.checked_sub(1).and_then(|i| history.get(i)).cloned()
+        .ok_or_else(|| format!("no command {line} in history"))
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let (name, args) = line.split_once(' ').map_or((line, ""), |(name, args)| (name, args.trim()));
+    let hex_bytes = |s: &str| parse::from_str(parse::hex_bytes, s).map_err(|_| format!("invalid hex bytes '{s}'"));
+    match name {
+        "send" => Ok(Command::Send(hex_bytes(args)?)),
+        "read" if args.is_empty() => Ok(Command::Read(READ_TIMEOUT)),
+        "read" => args.parse().map(|ms| Command::Read(Duration::from_millis(ms)))
+            .map_err(|_| format!("invalid timeout '{args}'")),
+        "begin" => args.parse::<u8>().map(|layer| Command::Send(vec![0x03, 0xfe, layer, 0x01, 0x01]))
+            .map_err(|_| format!("invalid layer '{args}'")),
+        "end" => Ok(Command::Send(vec![0x03, 0xaa, 0xaa])),
+        "decode" => Ok(Command::Decode(hex_bytes(args)?)),
+        "history" => Ok(Command::History),
+        "help" => Ok(Command::Help),
+        "quit" | "exit" => Ok(Command::Quit),
+        _ => hex_bytes(line).map(Command::Send).map_err(|_| format!("unknown command '{name}', type 'help'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{expand_history, parse_command, Command};
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse_command("send 03 fe 01"), Ok(Command::Send(vec![0x03, 0xfe, 0x01])));
+        assert_eq!(parse_command("03 aa aa"), Ok(Command::Send(vec![0x03, 0xaa, 0xaa])));
+        assert_eq!(parse_command("begin 2"), Ok(Command::Send(vec![0x03, 0xfe, 0x02, 0x01, 0x01])));
+        assert_eq!(parse_command("read 100"), Ok(Command::Read(Duration::from_millis(100))));
+        assert!(parse_command("send 3 fe").is_err());
+        assert!(parse_command("reboot").is_err());
+
+        let history = ["read".to_owned(), "end".to_owned()];
+        assert_eq!(expand_history("!!", &history), Ok("end".to_owned()));
+        assert_eq!(expand_history("!1", &history), Ok("read".to_owned()));
+        assert!(expand_history("!3", &history).is_err());
+        assert!(expand_history("!0", &history).is_err());
+    }
+}
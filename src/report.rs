@@ -0,0 +1,110 @@
+//! Outcome of uploading each binding, shown as table after upload.
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, Result};
+
+use crate::keyboard::{Key, Macro};
+use crate::term::{self, Style};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// Not attempted because earlier binding failed.
+    Skipped,
+    /// Keyboard rejected binding or it couldn't be encoded, with reason.
+    Failed(String),
+}
+
+impl Outcome {
+    fn text(&self) -> String {
+        match self {
+            Outcome::Ok => "OK".to_owned(),
+            Outcome::Skipped => "skipped".to_owned(),
+            Outcome::Failed(reason) => format!("failed: {reason}"),
+        }
+    }
+
+    fn style(&self) -> Style {
+        match self {
+            Outcome::Ok => Style::Success,
+            Outcome::Skipped => Style::Warning,
+            Outcome::Failed(_) => Style::Error,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UploadReport {
+    /// One-based layer number, key, macro and outcome of its binding.
+    entries: Vec<(usize, Key, String, Outcome)>,
+}
+
+impl UploadReport {
+    pub fn push(&mut self, layer: usize, key: Key, macro_: &Macro, outcome: Outcome) {
+        self.entries.push((layer, key, macro_.to_string(), outcome));
+    }
+
+    fn failures(&self) -> impl Iterator<Item = (usize, Key, &str)> {
+        self.entries.iter().filter_map(|(layer, key, _, outcome)| match outcome {
+            Outcome::Failed(reason) => Some((*layer, *key, reason.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Renders table with row per binding, outcome is colored if `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let header = ("Layer".to_owned(), "Key".to_owned(), "Macro".to_owned());
+        let rows: Vec<(String, String, String)> = self.entries.iter()
+            .map(|(layer, key, macro_, _)| (layer.to_string(), key.to_string(), macro_.clone()))
+            .collect();
+        let width = |column: fn(&(String, String, String)) -> &String| {
+            rows.iter().chain([&header]).map(|row| column(row).chars().count()).max().unwrap_or(0)
+        };
+        let widths = (width(|row| &row.0), width(|row| &row.1), width(|row| &row.2));
+
+        let mut out = String::new();
+        writeln!(out, "{:w0$}  {:w1$}  {:w2$}  Result", header.0, header.1, header.2,
+                 w0 = widths.0, w1 = widths.1, w2 = widths.2).unwrap();
+        for ((layer, key, macro_), (.., outcome)) in rows.iter().zip(&self.entries) {
+            writeln!(out, "{layer:w0$}  {key:w1$}  {macro_:w2$}  {}", term::paint(outcome.text(), outcome.style(), color),
+                     w0 = widths.0, w1 = widths.1, w2 = widths.2).unwrap();
+        }
+        out
+    }
+
+    /// Fails listing bindings which weren't uploaded, if any.
+    pub fn into_result(self) -> Result<()> {
+        let failures: Vec<String> = self.failures()
+            .map(|(layer, key, reason)| format!("bind {key} on layer {layer}: {reason}"))
+            .collect();
+        if !failures.is_empty() {
+            bail!("{} of {} bindings failed to upload:\n{}", failures.len(), self.entries.len(), failures.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{Outcome, UploadReport};
+    use crate::keyboard::{Key, KnobAction};
+
+    #[test]
+    fn render_report() {
+        let mut report = UploadReport::default();
+        report.push(1, Key::Button(0), &"ctrl-c".parse().unwrap(), Outcome::Ok);
+        report.push(1, Key::Button(1), &"a".parse().unwrap(), Outcome::Failed("timeout".to_owned()));
+        report.push(2, Key::Knob(0, KnobAction::Press), &"mute".parse().unwrap(), Outcome::Skipped);
+        assert_eq!(report.render(false), indoc! {"
+            Layer  Key           Macro   Result
+            1      button 0      ctrl-c  OK
+            1      button 1      a       failed: timeout
+            2      knob 0 press  mute    skipped
+        "});
+        assert_eq!(report.into_result().unwrap_err().to_string(),
+                   "1 of 3 bindings failed to upload:\nbind button 1 on layer 1: timeout");
+    }
+}
@@ -0,0 +1,121 @@
+//! Global shortcuts registered by desktop environment. Chords matching them
+//! are intercepted before they reach applications.
+//!
+//! Only GNOME shortcuts are known, they are read using `gsettings`.
+
+use std::process::Command;
+
+use log::debug;
+
+use crate::keyboard::Accord;
+
+/// GNOME settings schemas holding global shortcuts.
+const GNOME_SCHEMAS: [&str; 5] = [
+    "org.gnome.desktop.wm.keybindings",
+    "org.gnome.shell.keybindings",
+    "org.gnome.mutter.keybindings",
+    "org.gnome.mutter.wayland.keybindings",
+    "org.gnome.settings-daemon.plugins.media-keys",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Shortcut {
+    pub accord: Accord,
+    /// Human readable description, like "GNOME shortcut 'close'".
+    pub name: String,
+}
+
+/// Returns known global shortcuts, empty list if they can't be read.
+pub fn system_shortcuts() -> Vec<Shortcut> {
+    if !cfg!(target_os = "linux") {
+        return vec![];
+    }
+    GNOME_SCHEMAS.iter().flat_map(|schema| {
+        match Command::new("gsettings").args(["list-recursively", schema]).output() {
+            Ok(output) if output.status.success() => parse_gsettings(&String::from_utf8_lossy(&output.stdout)),
+            Ok(output) => {
+                debug!("gsettings failed for {schema}: {}", output.status);
+                vec![]
+            }
+            Err(err) => {
+                debug!("can't run gsettings: {err}");
+                vec![]
+            }
+        }
+    }).collect()
+}
+
+/// Parses output of `gsettings list-recursively`, lines look like
+/// `org.gnome.desktop.wm.keybindings close ['<Alt>F4']`.
+pub fn parse_gsettings(output: &str) -> Vec<Shortcut> {
+    output.lines().flat_map(|line| {
+        let mut fields = line.splitn(3, ' ');
+        let (_schema, key, value) = (fields.next(), fields.next().unwrap_or_default(), fields.next().unwrap_or_default());
+        // Quoted strings are at odd positions after splitting by quote.
+        value.split('\'').skip(1).step_by(2)
+            .filter_map(parse_gtk_accelerator)
+            .map(|accord| Shortcut { accord, name: format!("GNOME shortcut '{key}'") })
+            .collect::<Vec<_>>()
+    }).collect()
+}
+
+/// Parses GTK accelerator like `<Primary><Shift>Page_Up`.
+pub fn parse_gtk_accelerator(accelerator: &str) -> Option<Accord> {
+    let mut parts = vec![];
+    let mut rest = accelerator;
+    while let Some(stripped) = rest.strip_prefix('<') {
+        let (modifier, tail) = stripped.split_once('>')?;
+        parts.push(match modifier.to_ascii_lowercase().as_str() {
+            "primary" | "control" | "ctrl" => "ctrl",
+            "shift" => "shift",
+            "alt" | "mod1" => "alt",
+            "super" | "mod4" | "meta" => "win",
+            _ => return None,
+        });
+        rest = tail;
+    }
+
+    let key = rest.to_ascii_lowercase();
+    parts.push(match key.as_str() {
+        "return" => "enter",
+        "bracketleft" => "leftbracket",
+        "bracketright" => "rightbracket",
+        "apostrophe" => "quote",
+        "period" => "dot",
+        "print" => "printscreen",
+        "page_up" => "pageup",
+        "page_down" => "pagedown",
+        "" => return None,
+        key => key,
+    });
+    parts.join("-").parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{parse_gsettings, parse_gtk_accelerator, Shortcut};
+
+    #[test]
+    fn parse_accelerators() {
+        assert_eq!(parse_gtk_accelerator("<Primary><Alt>t"), Some("ctrl-alt-t".parse().unwrap()));
+        assert_eq!(parse_gtk_accelerator("<Super>Page_Up"), Some("win-pageup".parse().unwrap()));
+        assert_eq!(parse_gtk_accelerator("<Shift><Super>A"), Some("shift-win-a".parse().unwrap()));
+        assert_eq!(parse_gtk_accelerator("XF86AudioMute"), None);
+        assert_eq!(parse_gtk_accelerator("<Hyper>a"), None);
+    }
+
+    #[test]
+    fn parse_gsettings_output() {
+        let output = indoc! {"
+            org.gnome.desktop.wm.keybindings close ['<Alt>F4']
+            org.gnome.desktop.wm.keybindings minimize @as []
+            org.gnome.settings-daemon.plugins.media-keys screensaver ['<Super>l', 'XF86ScreenSaver']
+        "};
+        assert_eq!(parse_gsettings(output), [
+            Shortcut { accord: "alt-f4".parse().unwrap(), name: "GNOME shortcut 'close'".to_owned() },
+            Shortcut { accord: "win-l".parse().unwrap(), name: "GNOME shortcut 'screensaver'".to_owned() },
+        ]);
+    }
+}
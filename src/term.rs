@@ -0,0 +1,100 @@
+//! Terminal output styling. Colors are used when output is a terminal,
+//! unless `NO_COLOR` is set, and may be forced with `--color`.
+
+use std::fmt::Display;
+use std::io::IsTerminal as _;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR: OnceLock<ColorChoice> = OnceLock::new();
+
+/// Sets color choice from command line, must be called once at start.
+pub fn init(choice: ColorChoice) {
+    let _ = COLOR.set(choice);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Success,
+    Warning,
+    Error,
+    Heading,
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Success | Style::Added => "\x1b[32m",
+            Style::Warning | Style::Changed => "\x1b[33m",
+            Style::Error | Style::Removed => "\x1b[31m",
+            Style::Heading => "\x1b[1m",
+        }
+    }
+}
+
+fn enabled(choice: ColorChoice, is_terminal: bool, no_color: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && !no_color,
+    }
+}
+
+fn color_for(is_terminal: bool) -> bool {
+    // Empty `NO_COLOR` doesn't count, see https://no-color.org.
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    enabled(COLOR.get().copied().unwrap_or_default(), is_terminal, no_color)
+}
+
+/// Whether text printed to stdout is colored.
+pub fn stdout_color() -> bool {
+    color_for(std::io::stdout().is_terminal())
+}
+
+/// Whether text printed to stderr is colored.
+pub fn stderr_color() -> bool {
+    color_for(std::io::stderr().is_terminal())
+}
+
+/// Wraps text into escape codes of style if `color` is set.
+pub fn paint(text: impl Display, style: Style, color: bool) -> String {
+    if color {
+        format!("{}{text}\x1b[0m", style.code())
+    } else {
+        text.to_string()
+    }
+}
+
+/// Prints warning to stderr.
+pub fn warning(message: impl Display) {
+    eprintln!("{}: {message}", paint("Warning", Style::Warning, stderr_color()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enabled, paint, ColorChoice, Style};
+
+    #[test]
+    fn choose_color() {
+        assert!(enabled(ColorChoice::Auto, true, false));
+        assert!(!enabled(ColorChoice::Auto, true, true));
+        assert!(!enabled(ColorChoice::Auto, false, false));
+        assert!(enabled(ColorChoice::Always, false, true));
+        assert!(!enabled(ColorChoice::Never, true, false));
+
+        assert_eq!(paint("ok", Style::Success, true), "\x1b[32mok\x1b[0m");
+        assert_eq!(paint("ok", Style::Success, false), "ok");
+    }
+}
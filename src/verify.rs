@@ -0,0 +1,233 @@
+//! Interactive end-to-end verification of uploaded config: user presses
+//! keys one by one while chords keyboard sends to host are checked.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context as _, Result};
+
+use crate::config::FlatLayer;
+use crate::keyboard::{Accord, KeyboardPart, Macro};
+use crate::listener::{ChordTracker, Listener};
+use crate::shortcuts::{self, Shortcut};
+
+/// How long to wait for user to press key.
+const PRESS_TIMEOUT: Duration = Duration::from_secs(15);
+/// Macro is considered complete if no more chords arrive during this time.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(700);
+/// Keys sending macro slower than median by less than this aren't outliers.
+const OUTLIER_MARGIN: Duration = Duration::from_millis(20);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// Macro can't be checked, e.g. media keys and mouse events aren't
+    /// reported as key presses.
+    Skipped(&'static str),
+    Failed(String),
+}
+
+/// Returns chords macro is expected to send, fails for macros which can't be checked.
+pub fn expected_chords(macro_: &Macro) -> Result<Vec<Accord>, &'static str> {
+    let Macro::Keyboard(parts) = macro_ else { return Err("only keyboard macros can be checked") };
+    let chords: Vec<Accord> = parts.iter().filter_map(|part| match part {
+        KeyboardPart::Key(accord) => Some(*accord),
+        _ => None,
+    }).collect();
+    if chords.iter().any(|accord| accord.code.is_none()) {
+        return Err("modifier-only chords can't be checked");
+    }
+    Ok(chords)
+}
+
+pub fn check(expected: &[Accord], received: &[Accord]) -> Outcome {
+    if expected == received {
+        return Outcome::Passed;
+    }
+    let list = |chords: &[Accord]| chords.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(",");
+    if received.is_empty() {
+        Outcome::Failed("no key presses received".to_owned())
+    } else {
+        Outcome::Failed(format!("expected {}, got {}", list(expected), list(received)))
+    }
+}
+
+/// Asks user to press each bound key and checks chords received from keyboard.
+/// Fails if any binding doesn't pass.
+pub fn run(layers: &[FlatLayer], listener: &Listener) -> Result<()> {
+    let shortcuts = shortcuts::system_shortcuts();
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    let mut timings = vec![];
+
+    for (layer_idx, layer) in layers.iter().enumerate() {
+        let mut keys: Vec<(String, &Macro)> = vec![];
+        for (i, macro_) in layer.buttons.iter().enumerate() {
+            keys.extend(macro_.as_ref().map(|m| (format!("button {}", i + 1), m)));
+        }
+        for (i, knob) in layer.knobs.iter().enumerate() {
+            for (action, macro_) in [("ccw", &knob.ccw), ("press", &knob.press), ("cw", &knob.cw)] {
+                keys.extend(macro_.as_ref().map(|m| (format!("knob{}-{action}", i + 1), m)));
+            }
+        }
+        if keys.is_empty() {
+            continue;
+        }
+
+        if layers.len() > 1 {
+            println!("Switch keyboard to layer {} and press Enter on your main keyboard.", layer_idx + 1);
+            let mut line = String::new();
+            let read = std::io::stdin().read_line(&mut line).context("wait for Enter")?;
+            ensure!(read > 0, "verification is interrupted");
+        }
+
+        for (name, macro_) in keys {
+            let expected = expected_chords(macro_);
+            let mut timing = None;
+            let outcome = match &expected {
+                Err(reason) => Outcome::Skipped(reason),
+                Ok(expected) => {
+                    println!("Layer {}, {name}: press key bound to '{macro_}'", layer_idx + 1);
+                    let received = receive_chords(listener, expected.len())?;
+                    // Macros differ in length, so time per chord is compared.
+                    timing = received.duration.map(|duration| duration / received.chords.len().max(1) as u32);
+                    check(expected, &received.chords)
+                }
+            };
+            let timing_note = timing.map_or_else(String::new, |timing| format!(", {}ms per chord", timing.as_millis()));
+            timings.extend(timing.map(|timing| (format!("layer {}, {name}", layer_idx + 1), timing)));
+            let shortcut = expected.as_deref().ok().and_then(|expected| find_shortcut(expected, &shortcuts));
+            match outcome {
+                Outcome::Passed => {
+                    passed += 1;
+                    println!("  ✓ layer {}, {name}: ok{timing_note}", layer_idx + 1);
+                    if let Some(shortcut) = shortcut {
+                        println!("    but {} is {}, applications won't receive it", shortcut.accord, shortcut.name);
+                    }
+                }
+                Outcome::Skipped(reason) => {
+                    skipped += 1;
+                    println!("  - layer {}, {name}: '{macro_}' skipped, {reason}", layer_idx + 1);
+                }
+                Outcome::Failed(reason) => {
+                    failed += 1;
+                    println!("  ✗ layer {}, {name}: {reason}{timing_note}", layer_idx + 1);
+                    if let Some(shortcut) = shortcut {
+                        println!("    binding is likely intercepted by {} ({})", shortcut.name, shortcut.accord);
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{passed} passed, {failed} failed, {skipped} skipped");
+    if let Some((median, slow)) = outliers(&timings) {
+        println!("Keyboard takes {}ms per chord (median)", median.as_millis());
+        for (name, timing) in slow {
+            println!("  ! {name} is slow, {}ms per chord, key or USB hub may be flaky", timing.as_millis());
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} binding(s) failed verification");
+    }
+    Ok(())
+}
+
+/// Asks user to press single key and checks chords it sends.
+pub fn check_key(name: &str, macro_: &Macro, listener: &Listener) -> Result<Outcome> {
+    let expected = match expected_chords(macro_) {
+        Ok(expected) => expected,
+        Err(reason) => return Ok(Outcome::Skipped(reason)),
+    };
+    println!("Press {name} bound to '{macro_}'");
+    Ok(check(&expected, &receive_chords(listener, expected.len())?.chords))
+}
+
+/// Finds global shortcut matching any of expected chords.
+fn find_shortcut<'a>(expected: &[Accord], shortcuts: &'a [Shortcut]) -> Option<&'a Shortcut> {
+    shortcuts.iter().find(|shortcut| expected.contains(&shortcut.accord))
+}
+
+/// Chords sent by key and time keyboard took to send them, from the first
+/// key event to completion of the last chord.
+struct Received {
+    chords: Vec<Accord>,
+    duration: Option<Duration>,
+}
+
+/// Collects chords until given number of them is received or keyboard is idle.
+fn receive_chords(listener: &Listener, count: usize) -> Result<Received> {
+    let mut tracker = ChordTracker::default();
+    let mut chords = vec![];
+    let mut first_event = None;
+    let mut last_chord = None;
+    let mut deadline = Instant::now() + PRESS_TIMEOUT;
+    while chords.len() < count {
+        let Some(timeout) = deadline.checked_duration_since(Instant::now()) else { break };
+        let Some(event) = listener.next_event_timeout(timeout)? else { break };
+        first_event.get_or_insert_with(Instant::now);
+        if let Some(chord) = tracker.feed(&event) {
+            chords.push(chord);
+            last_chord = Some(Instant::now());
+            deadline = Instant::now() + IDLE_TIMEOUT;
+        }
+    }
+    // Let the rest of long macro arrive, so it isn't taken for the next key.
+    while let Some(event) = listener.next_event_timeout(IDLE_TIMEOUT)? {
+        if let Some(chord) = tracker.feed(&event) {
+            chords.push(chord);
+            last_chord = Some(Instant::now());
+        }
+    }
+    let duration = first_event.zip(last_chord).map(|(first, last)| last.saturating_duration_since(first));
+    Ok(Received { chords, duration })
+}
+
+/// Returns median of key timings and keys taking much longer than it.
+/// Small differences are USB polling jitter, so they aren't reported.
+fn outliers(timings: &[(String, Duration)]) -> Option<(Duration, Vec<&(String, Duration)>)> {
+    let mut sorted: Vec<Duration> = timings.iter().map(|(_, duration)| *duration).collect();
+    sorted.sort();
+    let median = *sorted.get(sorted.len() / 2)?;
+    let slow = timings.iter()
+        .filter(|(_, duration)| *duration > median * 2 && *duration > median + OUTLIER_MARGIN)
+        .collect();
+    Some((median, slow))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{check, expected_chords, outliers, Outcome};
+    use crate::keyboard::{Accord, Macro};
+
+    fn chords(s: &str) -> Vec<Accord> {
+        s.split(',').map(|c| c.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn check_received_chords() {
+        let macro_: Macro = "delay[100],ctrl-a,b".parse().unwrap();
+        let expected = expected_chords(&macro_).unwrap();
+        assert_eq!(expected, chords("ctrl-a,b"));
+
+        assert_eq!(check(&expected, &chords("ctrl-a,b")), Outcome::Passed);
+        assert_eq!(check(&expected, &chords("ctrl-a")), Outcome::Failed("expected ctrl-a,b, got ctrl-a".to_owned()));
+        assert_eq!(check(&expected, &[]), Outcome::Failed("no key presses received".to_owned()));
+
+        assert!(expected_chords(&"mute".parse().unwrap()).is_err());
+        assert!(expected_chords(&"wheelup".parse().unwrap()).is_err());
+        assert!(expected_chords(&"ctrl".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn find_slow_keys() {
+        let timing = |name: &str, ms| (name.to_owned(), Duration::from_millis(ms));
+        let timings = [timing("a", 10), timing("b", 12), timing("c", 25), timing("d", 90), timing("e", 11)];
+        let (median, slow) = outliers(&timings).unwrap();
+        assert_eq!(median, Duration::from_millis(12));
+        assert_eq!(slow, [&timing("d", 90)]);
+        assert!(outliers(&[]).is_none());
+    }
+}
@@ -0,0 +1,36 @@
+//! Watching config file or directory for changes.
+//!
+//! File modification time is polled: it is portable and cheap enough for
+//! files edited by hand.
+
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// How often file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Calls `on_change` immediately and then each time file is modified.
+/// Never returns.
+pub fn watch(path: &Path, mut on_change: impl FnMut()) -> ! {
+    let mut last_modified = modified(path);
+    on_change();
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let modified = modified(path);
+        // Editors may replace file on save, so it may be missing for a moment.
+        if modified.is_some() && modified != last_modified {
+            last_modified = modified;
+            on_change();
+        }
+    }
+}
+
+/// Returns modification time of file or latest one of directory and its files.
+fn modified(path: &Path) -> Option<SystemTime> {
+    let modified = std::fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+    let Ok(entries) = std::fs::read_dir(path) else { return Some(modified) };
+    entries.flatten()
+        .filter_map(|entry| entry.metadata().and_then(|meta| meta.modified()).ok())
+        .chain([modified])
+        .max()
+}
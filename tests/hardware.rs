@@ -0,0 +1,69 @@
+//! Tests against real keyboard, run only when `CH57X_HW_TESTS=1` is set:
+//!
+//! ```shell
+//! CH57X_HW_TESTS=1 CH57X_HW_MODEL=3x4-2knobs CH57X_HW_RESTORE=my-config.yaml \
+//!     cargo test --test hardware -- --nocapture
+//! ```
+//!
+//! Example config for `CH57X_HW_MODEL` is uploaded and verified interactively,
+//! so keys have to be pressed when asked. Keyboard bindings can't be read back,
+//! so config given by `CH57X_HW_RESTORE` is uploaded afterwards instead of backup.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_ch57x-keyboard-tool");
+
+fn enabled() -> bool {
+    std::env::var("CH57X_HW_TESTS").is_ok_and(|value| value == "1")
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().unwrap_or_else(|err| panic!("run {args:?}: {err}"))
+}
+
+/// Runs tool with terminal attached, so user may interact with it.
+fn run_interactive(args: &[&str]) -> bool {
+    Command::new(BIN).args(args).status().unwrap_or_else(|err| panic!("run {args:?}: {err}")).success()
+}
+
+fn upload(config: &Path) -> Output {
+    run(&["upload", config.to_str().unwrap()])
+}
+
+/// Uploads config to be restored when dropped, even if test fails.
+struct Restore(PathBuf);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let output = upload(&self.0);
+        if !output.status.success() {
+            eprintln!("failed to restore {}: {}", self.0.display(), String::from_utf8_lossy(&output.stderr));
+        }
+    }
+}
+
+#[test]
+fn upload_and_verify() {
+    if !enabled() {
+        eprintln!("skipped, set CH57X_HW_TESTS=1 to run tests against connected keyboard");
+        return;
+    }
+    let model = std::env::var("CH57X_HW_MODEL").unwrap_or_else(|_| "3x4-2knobs".to_owned());
+    let restore = std::env::var_os("CH57X_HW_RESTORE")
+        .expect("set CH57X_HW_RESTORE to config to upload after tests, bindings can't be read back");
+    let restore = Restore(PathBuf::from(restore));
+
+    let example = run(&["examples", &model]);
+    assert!(example.status.success(), "no example config for model '{model}'");
+    let config = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("hw-test-{model}.yaml"));
+    std::fs::write(&config, &example.stdout).unwrap();
+
+    let output = upload(&config);
+    assert!(output.status.success(), "upload failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(run_interactive(&["verify", "--interactive", config.to_str().unwrap()]), "verification failed");
+
+    let output = upload(&restore.0);
+    assert!(output.status.success(), "restore failed: {}", String::from_utf8_lossy(&output.stderr));
+    std::mem::forget(restore);
+}